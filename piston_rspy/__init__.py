@@ -9,6 +9,16 @@ __all__: list[str] = [
     "ExecResponse",
     "Executor",
     "Client",
+    "CancelToken",
+    "BackendStatus",
+    "FakeTransport",
+    "FakeRequest",
+    "HookRequest",
+    "HookResponse",
+    "ClientStats",
+    "HealthReport",
+    "PoolStats",
+    "set_log_level",
 ]
 
 __pdoc__: dict[str, bool] = {}
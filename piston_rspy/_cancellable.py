@@ -0,0 +1,32 @@
+"""`piston_rspy._cancellable` - Runs awaitables from the Rust layer as
+tasks that can be cancelled from any thread."""
+
+from __future__ import annotations
+
+__all__: list[str] = ["Cancellable"]
+
+import asyncio
+import typing as t
+
+
+class Cancellable:
+    """Runs an awaitable as a task that can be cancelled from any
+    thread, including before the task has started."""
+
+    def __init__(self, awaitable: t.Awaitable[t.Any]) -> None:
+        self.awaitable = awaitable
+        self.task: t.Optional[asyncio.Task[t.Any]] = None
+        self.cancelled = False
+
+    async def run(self) -> t.Any:
+        if self.cancelled:
+            raise asyncio.CancelledError
+
+        self.task = asyncio.current_task()
+        return await self.awaitable
+
+    def cancel(self) -> None:
+        self.cancelled = True
+
+        if self.task is not None:
+            self.task.cancel()
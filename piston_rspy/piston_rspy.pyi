@@ -10,8 +10,47 @@ __all__ = (
     "ExecResponse",
     "Executor",
     "Client",
+    "CancelToken",
+    "BackendStatus",
+    "FakeTransport",
+    "FakeRequest",
+    "HookRequest",
+    "HookResponse",
+    "ClientStats",
+    "HealthReport",
+    "PoolStats",
+    "set_log_level",
 )
 
+class _Transport(t.Protocol):
+    async def send(
+        self, method: str, path: str, body: t.Optional[str]
+    ) -> t.Tuple[int, str]: ...
+
+def set_log_level(level: t.Union[int, str]) -> None:
+    """Sets the level of the `piston_rspy` logger, and skips creating
+    records below it in the Rust layer.
+
+    - Requests, responses and failures are logged to
+    `piston_rspy.client`, and records from dependencies such as
+    reqwest to children like `piston_rspy.reqwest`.
+    - This is the same as calling `setLevel` on the logger. The Rust
+    layer follows the effective levels of the `piston_rspy` loggers,
+    `WARNING` by default, however they are configured. If the private
+    hook into `logging` this relies on is unavailable, levels are only
+    followed when set with this function.
+    - Child loggers can still be configured individually through
+    `logging`, including below this level.
+
+    Args:
+        level: `int | str`
+            A `logging` level, such as `logging.DEBUG` or `"INFO"`.
+
+    Raises:
+        `ValueError`: If the level is unknown.
+    """
+    ...
+
 @dataclass(frozen=True)
 class Runtime:
     """A runtime available to be used by Piston.
@@ -54,16 +93,50 @@ class File:
         content: str = ""
             **Required by Piston** The content of the file.
         encoding: str = "utf8"
-            The encoding of the file.
+            The encoding of the file. One of `utf8`, `base64` or `hex`.
+
+    Properties:
+        content_bytes: `bytes`
+            The content of the file, decoded according to its encoding.
+
+    Raises:
+        `ValueError`: If the encoding is not supported by Piston.
     """
 
     name: str = ""
     content: str = ""
     encoding: str = "utf8"
+    @property
+    def content_bytes(self) -> bytes: ...
+    @classmethod
+    def from_bytes(cls, name: str, data: bytes, encoding: str = "base64") -> File:
+        """Creates a new `File` from raw bytes.
+
+        Args:
+            name: `str`
+                The name of the file.
+            data: `bytes`
+                The raw content of the file.
+            encoding: `str` = "base64"
+                The encoding to store the content with, one of `utf8`,
+                `base64` or `hex`.
+
+        Returns:
+            `File`: The new file.
+
+        Raises:
+            `ValueError`: If the encoding is not supported by Piston,
+            or the data is not valid utf8 when using the `utf8`
+            encoding.
+        """
+        ...
+
     @classmethod
     def load_from(cls, path: str) -> File:
         """Creates a new `File` from an existing file on disk.
 
+        - Binary files are automatically base64 encoded.
+
         Args:
             path: `str`
                 The path to the file.
@@ -89,6 +162,9 @@ class File:
         """Sets the content of the file to the contents of an existing
         file on disk.
 
+        - Binary files are automatically base64 encoded, and the
+        encoding of the file is updated to match.
+
         Args:
             path: `str`
                 The path to the file.
@@ -119,6 +195,79 @@ class File:
 
         Returns:
             `File`: The file, for chained method calls.
+
+        Raises:
+            `ValueError`: If the encoding is not supported by Piston.
+        """
+        ...
+
+    def validate(self, max_size: t.Optional[int] = None) -> None:
+        """Validates the file before it is sent to Piston.
+
+        Args:
+            max_size: `int | None` = None
+                The maximum size of the content in bytes, or `None` for
+                no limit.
+
+        Raises:
+            `ValueError`: If the name is absolute, contains `..` or NUL
+            bytes, the encoding is unsupported, or the content is too
+            large.
+        """
+        ...
+
+    def save_to(self, path: str) -> None:
+        """Writes the content of the file to disk, decoding it
+        according to its encoding. This is the inverse of
+        `File.load_from()`.
+
+        - Any existing file at the path is overwritten.
+
+        Args:
+            path: `str`
+                The path to write the file to.
+
+        Raises:
+            `ValueError`: If the content is not valid for its encoding.
+            `RuntimeError`: If the file could not be written.
+        """
+        ...
+
+    @classmethod
+    async def aload_from(cls, path: str) -> File:
+        """`async` Creates a new `File` from an existing file on disk,
+        without blocking the event loop.
+
+        - Binary files are automatically base64 encoded.
+
+        Args:
+            path: `str`
+                The path to the file.
+
+        Returns:
+            `asyncio.Future[File]`: The new file.
+
+        Raises:
+            `RuntimeError`: If the file could not be read.
+        """
+        ...
+
+    async def aload_content_from(self, path: str) -> File:
+        """`async` Sets the content of the file to the contents of an
+        existing file on disk, without blocking the event loop.
+
+        - Binary files are automatically base64 encoded, and the
+        encoding of the file is updated to match.
+
+        Args:
+            path: `str`
+                The path to the file.
+
+        Returns:
+            `asyncio.Future[File]`: The file, for chained method calls.
+
+        Raises:
+            `RuntimeError`: If the file could not be read.
         """
         ...
 
@@ -150,6 +299,11 @@ class ExecResult:
             The optional exit code returned by the process.
         signal: `str | None`
             The optional signal sent to the process. (`SIGKILL` etc)
+        truncated: `bool`
+            Whether the response was cut off at the
+            `max_response_bytes` of the client before this result
+            ended. Its output is then incomplete, and its code and
+            signal may be missing.
 
     Raises:
         `TypeError`: If the class is instantiated manually.
@@ -160,6 +314,7 @@ class ExecResult:
     output: str
     code: t.Optional[int]
     signal: t.Optional[str]
+    truncated: bool
     def is_ok(self) -> bool:
         """Whether or not the execution was ok.
 
@@ -198,6 +353,28 @@ class ExecResponse:
             This will be `None` for non-compiled languages.
         status: `int`
             The response status returned by Piston.
+        backend: `str | None`
+            The base url of the Piston instance that served the
+            response.
+        truncated: `bool`
+            Whether the response was cut off at the
+            `max_response_bytes` of the client. A language or version
+            that could not be read is left empty.
+        raw_json: `str | None`
+            The body of the response as received from Piston, after
+            any decompression. This may not be JSON if the request
+            failed. `None` unless the client was created with
+            `Client.with_raw_responses()`.
+        headers: `dict[str, str]`
+            The headers of the response by lower case name, including
+            any rate limit headers. Empty for transports set with
+            `Client.with_transport()`.
+        request_id: `str`
+            The id generated for the request, and sent with it in the
+            `X-Request-Id` header.
+        elapsed: `float`
+            The number of seconds taken to receive the response,
+            measured by the client. Hooks are not included.
 
     Raises:
         `TypeError`: If the class is instantiated manually.
@@ -208,6 +385,12 @@ class ExecResponse:
     run: ExecResult
     compile: t.Optional[ExecResult]
     status: int
+    backend: t.Optional[str]
+    truncated: bool
+    raw_json: t.Optional[str]
+    headers: t.Dict[str, str]
+    request_id: str
+    elapsed: float
     def is_ok(self) -> bool:
         """Whether or not the request to Piston succeeded.
 
@@ -233,6 +416,8 @@ class Executor:
 
     - For `compile_memory_limit` and `run_memory_limit` -1 can be used
     to signify no limit.
+    - The executor is validated before it is sent to Piston, see
+    `Executor.validate()`.
 
     Args:
         language: `str` = ""
@@ -254,6 +439,9 @@ class Executor:
             The maximum allowed memory usage for compilation in bytes.
         run_memory_limit: `int` = -1
             The maximum allowed memory usage for execution in bytes.
+        max_total_size: `int | None` = None
+            The maximum total size of all file content in bytes, or
+            `None` for no limit.
 
     """
 
@@ -266,6 +454,81 @@ class Executor:
     run_timeout: int = 3000
     compile_memory_limit: int = -1
     run_memory_limit: int = -1
+    max_total_size: t.Optional[int] = None
+    @classmethod
+    def from_archive(
+        cls,
+        source: t.Union[str, bytes],
+        main: t.Optional[str] = None,
+        max_entries: int = 1000,
+        max_size: int = 10485760,
+    ) -> Executor:
+        """Creates a new `Executor` from the files in a zip, tar or
+        gzipped tar archive.
+
+        - Entries are extracted in memory and keep their relative names.
+        - Binary entries are base64 encoded.
+
+        Args:
+            source: `str | bytes`
+                The path to the archive, or the raw archive bytes.
+            main: `str | None` = None
+                The name of the file to move to the front of the files
+                list, so that Piston treats it as the main file.
+            max_entries: `int` = 1000
+                The maximum number of files to extract.
+            max_size: `int` = 10485760
+                The maximum total uncompressed size in bytes.
+
+        Returns:
+            `Executor`: The new executor.
+
+        Raises:
+            `ValueError`: If the archive is unrecognized, exceeds a
+            limit, contains unsafe paths, or `main` is not in the
+            archive.
+            `RuntimeError`: If the archive could not be read.
+        """
+        ...
+
+    def validate(self) -> None:
+        """Validates the executor before it is sent to Piston. This is
+        called automatically by `Client.execute()`.
+
+        Raises:
+            `ValueError`: If a file name is absolute, contains `..` or
+            NUL bytes, is duplicated, a file has an unsupported
+            encoding, or the files exceed `max_total_size`.
+        """
+        ...
+
+    def export_to_directory(self, path: str, overwrite: bool = False) -> None:
+        """Writes the executor's files to a directory on disk, decoding
+        them according to their encoding, so a submission can be
+        reproduced locally.
+
+        - A `piston_manifest.json` file containing the language,
+        version, stdin, args and file names is written alongside the
+        files.
+        - Unnamed files are written as `file{index}.code`, matching
+        Piston.
+
+        Args:
+            path: `str`
+                The directory to write to. It is created if it does not
+                exist.
+            overwrite: `bool` = False
+                Whether or not existing files may be overwritten.
+
+        Raises:
+            `ValueError`: If a file name is absolute or contains `..`,
+            or a file's content is not valid for its encoding.
+            `FileExistsError`: If a file already exists and `overwrite`
+            is `False`, or two files would be written to the same path.
+            `RuntimeError`: If a file could not be written.
+        """
+        ...
+
     def copy(self) -> Executor:
         """Copies the executor, leaving the existing one unchanged.
 
@@ -448,6 +711,18 @@ class Executor:
         """
         ...
 
+    def set_max_total_size(self, size: t.Optional[int]) -> Executor:
+        """Sets the maximum total size of all file content in bytes.
+
+        Args:
+            size: `int | None`
+                The size to set, or `None` for no limit.
+
+        Returns:
+            `Executor`: The executor, for chained method calls.
+        """
+        ...
+
 @dataclass(frozen=True)
 class Client:
     """A client used to send requests to Piston.
@@ -460,7 +735,35 @@ class Client:
         url: `str`
             The base url for the Piston v2 api.
         headers: `dict[str, str]`
-            The headers being sent with requests.
+            The headers being sent with requests, with the values of
+            sensitive headers masked. See `Client.reveal_headers()`.
+        auth: `str | None`
+            A description of the auth provider in use, such as
+            `keys(2)` or `callable`, if any.
+        cassette: `tuple[str, str] | None`
+            The path and mode of the cassette in use, if any.
+        transport: `str`
+            The name of the transport requests are sent through.
+        backends: `list[BackendStatus] | None`
+            The health of each backend, for clients created with
+            `Client.with_urls()`.
+        closed: `bool`
+            Whether the client has been closed.
+        grace_period: `float`
+            The number of seconds in-flight requests are given to
+            complete when the client is closed.
+        timeout: `float | None`
+            The number of seconds to wait for each request, or `None`
+            to wait indefinitely.
+        max_response_bytes: `int | None`
+            The most bytes of each response read, or `None` for no
+            limit.
+        rate_limit: `tuple[int, float] | None`
+            The most requests started in a period, and its length in
+            seconds, or `None` for no limit.
+
+    - The client can be used as an async context manager, which
+    closes it on exit.
     """
 
     url: str = field(init=False, default="https://emkc.org/api/v2/piston")
@@ -471,6 +774,17 @@ class Client:
             "User-Agent": "piston-rs",
         },
     )
+    auth: t.Optional[str] = field(init=False, default=None)
+    cassette: t.Optional[t.Tuple[str, str]] = field(init=False, default=None)
+    transport: str = field(init=False, default="reqwest")
+    backends: t.Optional[t.List[BackendStatus]] = field(init=False, default=None)
+    closed: bool = field(init=False, default=False)
+    grace_period: float = field(init=False, default=10.0)
+    timeout: t.Optional[float] = field(init=False, default=None)
+    max_response_bytes: t.Optional[int] = field(init=False, default=None)
+    rate_limit: t.Optional[t.Tuple[int, float]] = field(init=False, default=None)
+    async def __aenter__(self) -> Client: ...
+    async def __aexit__(self, *args: t.Any) -> None: ...
     @staticmethod
     def with_key(key: str) -> Client:
         """Creates a new client, with an api key.
@@ -484,6 +798,54 @@ class Client:
         """
         ...
 
+    @staticmethod
+    def with_urls(
+        urls: t.List[str],
+        strategy: str = "round_robin",
+        key: t.Optional[str] = None,
+        max_failures: int = 3,
+        cooldown: float = 30.0,
+    ) -> Client:
+        """Creates a new client that spreads requests over several
+        Piston instances.
+
+        - `round_robin` cycles through the backends in turn.
+        - `least_inflight` uses the backend with the fewest requests
+        in-flight.
+        - `failover` uses the first backend, moving down the list only
+        when it is unhealthy.
+        - Backends are ejected from rotation for the cooldown after
+        failing `max_failures` requests in a row, where connection
+        errors and 502, 503 and 504 statuses count as failures.
+        - Requests failing with a connection error are retried on the
+        next backend. Other failures are not retried, as the backend
+        may already have received the request.
+        - The backend that served each execution is available as
+        `ExecResponse.backend`.
+
+        Args:
+            urls: `list[str]`
+                The urls of the Piston backends.
+            strategy: `str` = "round_robin"
+                One of `round_robin`, `least_inflight` or `failover`.
+            key: `str | None` = None
+                The api key to use.
+            max_failures: `int` = 3
+                The number of consecutive failures before a backend is
+                ejected.
+            cooldown: `float` = 30.0
+                The number of seconds an ejected backend is out of
+                rotation.
+
+        Returns:
+            `Client`: The new client.
+
+        Raises:
+            `ValueError`: If no urls are given, or the strategy is
+            unsupported.
+        """
+        ...
+
     @staticmethod
     def with_url(url: str) -> Client:
         """Creates a new client with a custom url.
@@ -512,6 +874,650 @@ class Client:
         """
         ...
 
+    @staticmethod
+    def with_unix_socket(
+        path: str, prefix: str = "/api/v2", key: t.Optional[str] = None
+    ) -> Client:
+        """Creates a new client that connects to a Piston instance
+        through a Unix domain socket, rather than over tcp.
+
+        - Proxy and TLS settings do not apply to the socket.
+
+        Args:
+            path: `str`
+                The path of the socket Piston is listening on.
+            prefix: `str`
+                The path of the Piston v2 api on the server. Defaults
+                to `/api/v2`.
+            key: `str | None`
+                The api key to use. Defaults to `None`.
+
+        Returns:
+            `Client`: The new client.
+        """
+        ...
+
+    @staticmethod
+    def from_env(prefix: str = "PISTON_") -> Client:
+        """Creates a new client from settings read from environment
+        variables. Each is named after the setting in upper case, such
+        as `PISTON_URL` or `PISTON_TIMEOUT`, and unset or empty
+        variables are ignored.
+
+        - `URL`, `URLS` (comma separated) or `UNIX_SOCKET`: Where
+        Piston is, at most one of them. Defaults to the public api.
+        - `KEY`: The api key.
+        - `STRATEGY`, `MAX_FAILURES` and `COOLDOWN`: Balancing over
+        `URLS`, see `Client.with_urls()`.
+        - `TIMEOUT` and `GRACE_PERIOD`: In seconds, see
+        `Client.with_timeout()` and `Client.with_grace_period()`.
+        - `MAX_RESPONSE_BYTES`: See `Client.with_max_response_bytes()`.
+        - `RATE_LIMIT` and `RATE_LIMIT_PERIOD`: The most requests, and
+        the period in seconds, see `Client.with_rate_limit()`.
+        - `PROXY`, `PROXY_USERNAME`, `PROXY_PASSWORD` and `NO_PROXY`
+        (comma separated): See `Client.with_proxy()`.
+        - `ENV_PROXY`: `true` to use `Client.with_env_proxy()`.
+        - `CA`, `CLIENT_CERT`, `CLIENT_KEY` and `TLS_MIN_VERSION`:
+        Paths and the version, see `Client.with_tls()`.
+
+        Args:
+            prefix: `str`
+                The prefix of the variable names. Defaults to
+                `PISTON_`.
+
+        Returns:
+            `Client`: The new client.
+
+        Raises:
+            `ValueError`: If a variable is malformed, or the settings
+            conflict.
+        """
+        ...
+
+    @staticmethod
+    def from_config(path: str, profile: t.Optional[str] = None) -> Client:
+        """Creates a new client from a profile in a TOML config file.
+
+        - Profiles are tables under `profiles`, holding the settings
+        described in `Client.from_env()` in lower case, where `urls`
+        and `no_proxy` are arrays.
+        - A profile may also set `key_env` to read the api key from an
+        environment variable.
+        - The top level `default` names the profile used when none is
+        given.
+
+        ```toml
+        default = "public"
+
+        [profiles.public]
+        url = "https://emkc.org/api/v2/piston"
+
+        [profiles.prod]
+        urls = ["http://piston-1:2000/api/v2", "http://piston-2:2000/api/v2"]
+        key_env = "PISTON_PROD_KEY"
+        timeout = 30.0
+        rate_limit = 20
+        ```
+
+        Args:
+            path: `str`
+                The path to the config file.
+            profile: `str | None`
+                The profile to use. Defaults to the `default` of the
+                file, or its only profile.
+
+        Returns:
+            `Client`: The new client.
+
+        Raises:
+            `ValueError`: If the file could not be read or parsed, the
+            profile is missing, or its settings are malformed or
+            conflict.
+        """
+        ...
+
+    def with_cassette(
+        self, path: str, mode: t.Literal["record", "replay", "auto"] = "auto"
+    ) -> Client:
+        """Creates a copy of this client that records its requests to,
+        or replays them from, a JSON cassette file.
+
+        - Requests are matched on their method, path and JSON body,
+        ignoring key order and whitespace.
+        - The values of response headers that may hold credentials,
+        such as `Set-Cookie`, are masked when recorded.
+        - `record` sends every request and records it, replacing any
+        existing cassette.
+        - `replay` never sends requests, and raises on any request that
+        is not in the cassette.
+        - `auto` replays recorded requests, and records new ones.
+
+        Args:
+            path: `str`
+                The path to the cassette file.
+            mode: `str` = "auto"
+                One of `record`, `replay` or `auto`.
+
+        Returns:
+            `Client`: The new client.
+
+        Raises:
+            `ValueError`: If the mode is unsupported, or the cassette is
+            missing in `replay` mode or malformed.
+        """
+        ...
+
+    def with_transport(self, transport: t.Union[FakeTransport, _Transport]) -> Client:
+        """Creates a copy of this client that sends its requests through
+        a different transport, replacing any cassette in use.
+
+        - The transport can be a `FakeTransport`, or any object with an
+        `async send(method, path, body)` method returning a
+        `(status, body)` tuple.
+        - `path` is relative to the base url, and `body` is `None` for
+        requests without one.
+
+        Args:
+            transport: `FakeTransport | Transport`
+                The transport to use.
+
+        Returns:
+            `Client`: The new client.
+
+        Raises:
+            `TypeError`: If the transport has no `send` method.
+        """
+        ...
+
+    def with_proxy(
+        self,
+        url: str,
+        scheme: str = "all",
+        username: t.Optional[str] = None,
+        password: t.Optional[str] = None,
+        no_proxy: t.Optional[t.List[str]] = None,
+    ) -> Client:
+        """Creates a copy of this client that sends its requests through
+        a proxy. Proxies added earlier take precedence, so this can be
+        called again to use a different proxy for `http` and `https`
+        urls.
+
+        - The proxy url can use the `http`, `https`, `socks5` or
+        `socks5h` scheme, where `socks5h` resolves hostnames through
+        the proxy.
+        - Credentials can also be given in the url itself.
+        - Once a proxy is added, the proxy environment variables are
+        ignored unless `Client.with_env_proxy()` is also used.
+        - Proxies do not apply to transports set with
+        `Client.with_transport()`.
+
+        Args:
+            url: `str`
+                The url of the proxy.
+            scheme: `str`
+                Which requests to proxy, one of `all`, `http` or
+                `https`, by the scheme of the Piston url. Defaults to
+                `all`.
+            username: `str | None`
+                The username to authenticate with. Defaults to `None`.
+            password: `str | None`
+                The password to authenticate with. Defaults to `None`.
+            no_proxy: `list[str] | None`
+                Hosts, domains or ip ranges such as `10.0.0.0/8` to
+                connect to directly. Defaults to `None`.
+
+        Returns:
+            `Client`: The new client.
+
+        Raises:
+            `ValueError`: If the url or scheme is unsupported, or only
+            one of username and password is given.
+        """
+        ...
+
+    def with_env_proxy(self) -> Client:
+        """Creates a copy of this client that uses the proxies
+        configured by the `HTTPS_PROXY`, `HTTP_PROXY`, `ALL_PROXY` and
+        `NO_PROXY` environment variables, in upper or lower case. The
+        variables are read when this is called.
+
+        - Clients without proxies already use the environment, but
+        once a proxy is added with `Client.with_proxy()` it is ignored
+        unless this is called.
+        - Proxies added with `Client.with_proxy()` take precedence.
+        - Values without a scheme, such as `proxy.corp:8080`, are used
+        as http proxies.
+
+        Returns:
+            `Client`: The new client.
+
+        Raises:
+            `ValueError`: If a variable holds an unsupported proxy url.
+        """
+        ...
+
+    def with_tls(
+        self,
+        ca: t.Union[str, bytes, None] = None,
+        cert: t.Union[str, bytes, None] = None,
+        key: t.Union[str, bytes, None] = None,
+        min_version: t.Optional[str] = None,
+        danger_accept_invalid_certs: bool = False,
+    ) -> Client:
+        """Creates a copy of this client that connects to Piston over
+        TLS with different settings, replacing any set previously.
+
+        - PEM data can be given as `bytes`, or as the path of a file.
+        - The client certificate may include its private key, in which
+        case `key` can be omitted.
+        - TLS settings do not apply to transports set with
+        `Client.with_transport()`.
+
+        Args:
+            ca: `str | bytes | None`
+                Root certificates to trust, alongside the built-in
+                roots. Defaults to `None`.
+            cert: `str | bytes | None`
+                A client certificate chain to present. Defaults to
+                `None`.
+            key: `str | bytes | None`
+                The private key of the client certificate. Defaults to
+                `None`.
+            min_version: `str | None`
+                The minimum TLS version, `1.2` or `1.3`. Defaults to
+                `None`.
+            danger_accept_invalid_certs: `bool`
+                Skips verifying the certificate of Piston entirely,
+                leaving the connection open to interception. Only for
+                local development. Defaults to `False`.
+
+        Returns:
+            `Client`: The new client.
+
+        Raises:
+            `ValueError`: If a file could not be read, the PEM data is
+            invalid, a key is given without a certificate, or the
+            version is unsupported.
+        """
+        ...
+
+    def with_pool(
+        self,
+        max_idle_per_host: t.Optional[int] = None,
+        idle_timeout: t.Optional[float] = 90.0,
+        tcp_keepalive: t.Optional[float] = None,
+        tcp_nodelay: bool = True,
+        http2_prior_knowledge: bool = False,
+        max_connections_per_host: t.Optional[int] = None,
+    ) -> Client:
+        """Creates a copy of this client that pools and configures its
+        connections differently, replacing any settings given
+        previously. Defaults match those of a new client.
+
+        - Pool settings do not apply to Unix sockets, or transports set
+        with `Client.with_transport()`.
+
+        Args:
+            max_idle_per_host: `int | None`
+                The most idle connections kept open to each host, or
+                `None` for no limit. Defaults to `None`.
+            idle_timeout: `float | None`
+                The number of seconds an idle connection is kept open,
+                or `None` to keep it open indefinitely. Defaults to
+                `90.0`.
+            tcp_keepalive: `float | None`
+                The interval in seconds of TCP keep-alive probes, or
+                `None` to disable them. Defaults to `None`.
+            tcp_nodelay: `bool`
+                Whether to disable Nagle's algorithm. Defaults to
+                `True`.
+            http2_prior_knowledge: `bool`
+                Whether to speak HTTP/2 without negotiating it first,
+                for servers known to support it. Defaults to `False`.
+            max_connections_per_host: `int | None`
+                The most requests sent to each host at once, or `None`
+                for no limit. Further requests wait for one to finish,
+                so over HTTP/1.1 this bounds the connections opened to
+                each host. Defaults to `None`.
+
+        Returns:
+            `Client`: The new client.
+
+        Raises:
+            `ValueError`: If a duration is not positive, or
+                `max_connections_per_host` is 0.
+        """
+        ...
+
+    def with_header(self, name: str, value: str, sensitive: bool = False) -> Client:
+        """Creates a copy of this client that sends a header with every
+        request, replacing any existing value.
+
+        - The values of sensitive headers are masked in the repr of the
+        client and `Client.headers`.
+        - `Authorization`, `Proxy-Authorization`, `Cookie`, `X-Api-Key`
+        and `X-Auth-Token` are always sensitive.
+
+        Args:
+            name: `str`
+                The name of the header, which is case insensitive.
+            value: `str`
+                The value of the header.
+            sensitive: `bool`
+                Whether to mask the value. Defaults to `False`.
+
+        Returns:
+            `Client`: The new client.
+
+        Raises:
+            `ValueError`: If the name or value is not a valid header.
+        """
+        ...
+
+    def reveal_headers(self) -> t.Dict[str, str]:
+        """Gets the headers being sent with requests, without masking
+        the values of sensitive headers such as the api key.
+
+        - Values from an auth provider are never included, see
+        `Client.with_auth()`.
+
+        Returns:
+            `dict[str, str]`: The headers.
+        """
+        ...
+
+    def with_auth(
+        self,
+        provider: t.Union[
+            t.List[str], t.Callable[[], t.Union[str, t.Awaitable[str]]]
+        ],
+    ) -> Client:
+        """Creates a copy of this client that gets the `Authorization`
+        value for each request from an auth provider, replacing any api
+        key.
+
+        - A list of keys is used round-robin. A request receiving a 401
+        is retried once with the next key.
+        - A callable, regular or async, is called without arguments and
+        returns the value. It is called for the first request, then
+        again whenever a request using the value receives a 401, which
+        is retried once with the new value.
+        - The value is not exposed through `Client.headers`, hooks or
+        the repr of the client.
+
+        Args:
+            provider: `list[str] | Callable[[], str | Awaitable[str]]`
+                The keys, or the callable.
+
+        Returns:
+            `Client`: The new client.
+
+        Raises:
+            `ValueError`: If the list of keys is empty.
+            `TypeError`: If the provider is neither a list nor
+            callable.
+        """
+        ...
+
+    def add_hook(
+        self,
+        before_request: t.Optional[
+            t.Callable[[HookRequest], t.Union[Executor, None, t.Awaitable[t.Optional[Executor]]]]
+        ] = None,
+        after_response: t.Optional[
+            t.Callable[[HookRequest, HookResponse], t.Union[None, t.Awaitable[None]]]
+        ] = None,
+        on_error: t.Optional[
+            t.Callable[[HookRequest, Exception], t.Union[None, t.Awaitable[None]]]
+        ] = None,
+    ) -> Client:
+        """Creates a copy of this client that calls the given hooks
+        around every request it sends. Hooks run in the order they were
+        added, and each may be a regular or an async callable.
+
+        - `before_request(request)` receives a `HookRequest` before it
+        is sent. It may change the request's headers or executor, or
+        return a new `Executor` to send instead.
+        - `after_response(request, response)` receives the request and
+        a `HookResponse` once a response has been received.
+        - `on_error(request, error)` receives the request and the
+        exception if the request failed, which is raised afterwards.
+        - An exception raised by a hook is raised by the request.
+
+        Args:
+            before_request: `Callable[[HookRequest], Executor | None] | None` = None
+                Called before each request is sent.
+            after_response: `Callable[[HookRequest, HookResponse], None] | None` = None
+                Called after each response is received.
+            on_error: `Callable[[HookRequest, Exception], None] | None` = None
+                Called when a request fails.
+
+        Returns:
+            `Client`: The new client.
+
+        Raises:
+            `TypeError`: If a hook is not callable.
+        """
+        ...
+
+    def with_timeout(self, timeout: t.Optional[float]) -> Client:
+        """Creates a copy of this client that gives up on requests that
+        take longer than the timeout, including any retries on other
+        backends. Hooks are not included in the time.
+
+        Args:
+            timeout: `float | None`
+                The number of seconds to wait for each request, or
+                `None` to wait indefinitely.
+
+        Returns:
+            `Client`: The new client.
+
+        Raises:
+            `ValueError`: If the timeout is not positive.
+        """
+        ...
+
+    def with_rate_limit(self, max_requests: t.Optional[int], per: float = 1.0) -> Client:
+        """Creates a copy of this client that starts at most
+        `max_requests` requests in any `per` seconds, such as the 5
+        requests a second allowed by the public api.
+
+        - Requests over the limit wait for their turn, which is not
+        included in the timeout.
+        - The limit is shared by copies made from the new client, but
+        not with this one.
+
+        Args:
+            max_requests: `int | None`
+                The most requests to start in the period, or `None` for
+                no limit.
+            per: `float`
+                The length of the period in seconds. Defaults to `1.0`.
+
+        Returns:
+            `Client`: The new client.
+
+        Raises:
+            `ValueError`: If `max_requests` is 0, or the period is not
+            positive.
+        """
+        ...
+
+    def with_compression(
+        self,
+        encoding: t.Optional[t.Literal["gzip", "zstd"]] = "gzip",
+        min_size: int = 1024,
+        decompress: bool = True,
+    ) -> Client:
+        """Creates a copy of this client that compresses the executors
+        it sends, for Piston instances behind a proxy that accepts
+        compressed requests. Responses compressed with gzip or zstd are
+        always decoded, and those in other encodings are passed through
+        as is, with a warning.
+
+        - Compression does not apply to transports set with
+        `Client.with_transport()`.
+
+        Args:
+            encoding: `"gzip" | "zstd" | None`
+                How to compress executors, or `None` to send them as
+                is. Defaults to `"gzip"`.
+            min_size: `int`
+                The size in bytes executors must reach to be
+                compressed. Defaults to `1024`.
+            decompress: `bool`
+                Whether to ask for compressed responses. Defaults to
+                `True`.
+
+        Returns:
+            `Client`: The new client.
+
+        Raises:
+            `ValueError`: If the encoding is not supported.
+        """
+        ...
+
+    def with_max_response_bytes(
+        self, max_response_bytes: t.Optional[int], truncate: bool = True
+    ) -> Client:
+        """Creates a copy of this client that stops reading responses
+        once they exceed a number of bytes, protecting memory from
+        programs that print without end.
+
+        - Execute responses are cut off at the limit, recovering what
+        was read and marking the results that were cut off with
+        `ExecResult.truncated`. Other responses over the limit fail.
+
+        Args:
+            max_response_bytes: `int | None`
+                The most bytes of each response to read, after
+                decompression, or `None` for no limit.
+            truncate: `bool`
+                Whether to cut off execute responses over the limit,
+                rather than fail. Defaults to `True`.
+
+        Returns:
+            `Client`: The new client.
+
+        Raises:
+            `ValueError`: If the limit is zero.
+        """
+        ...
+
+    def with_raw_responses(self, enabled: bool = True) -> Client:
+        """Creates a copy of this client that keeps the body of each
+        execute response in `ExecResponse.raw_json`. Bodies are
+        dropped once parsed otherwise, as they repeat the output of
+        the program.
+
+        Args:
+            enabled: `bool`
+                Whether to keep the bodies. Defaults to `True`.
+
+        Returns:
+            `Client`: The new client.
+        """
+        ...
+
+    def with_grace_period(self, grace_period: float) -> Client:
+        """Creates a copy of this client that gives in-flight requests a
+        different number of seconds to complete when it is closed.
+
+        Args:
+            grace_period: `float`
+                The number of seconds to wait before aborting requests.
+
+        Returns:
+            `Client`: The new client.
+
+        Raises:
+            `ValueError`: If the grace period is negative.
+        """
+        ...
+
+    async def aclose(self, grace_period: t.Optional[float] = None) -> None:
+        """`async` Closes the client, and every copy of it made with the
+        `with_*` and `add_hook` methods.
+
+        - New requests are rejected immediately, while in-flight
+        requests are given the grace period to complete before being
+        aborted. Pooled connections are then released.
+        - Closing a closed client starts nothing new, but waits until
+        the first close has finished with its in-flight requests.
+
+        Args:
+            grace_period: `float | None` = None
+                The number of seconds to wait before aborting requests.
+                Defaults to the grace period of the client, see
+                `Client.with_grace_period()`.
+
+        Raises:
+            `ValueError`: If the grace period is negative.
+        """
+        ...
+
+    def pool_stats(self) -> t.Optional[PoolStats]:
+        """Takes a snapshot of the connections used by this client, and
+        the copies sharing its connection pool.
+
+        - Copies made with `with_proxy()`, `with_tls()`, `with_pool()`
+        or `with_env_proxy()` have their own pool.
+
+        Returns:
+            `PoolStats | None`: The snapshot, or `None` if the client
+            does not connect over tcp.
+        """
+        ...
+
+    def stats(self) -> ClientStats:
+        """Takes a snapshot of the requests sent by this client, and
+        every copy of it made with the `with_*` and `add_hook` methods.
+
+        Returns:
+            `ClientStats`: The snapshot.
+        """
+        ...
+
+    def export_prometheus(self, prefix: str = "piston_rspy") -> str:
+        """Renders the stats of this client in the Prometheus text
+        exposition format, for serving from a `/metrics` endpoint.
+
+        Args:
+            prefix: `str` = "piston_rspy"
+                The prefix of every metric name.
+
+        Returns:
+            `str`: The rendered metrics.
+        """
+        ...
+
+    async def health(self, timeout: float = 5.0) -> HealthReport:
+        """`async` Probes Piston, checking that it is reachable and that
+        its runtimes can be parsed. These are http requests, which are
+        not counted in `Client.stats()` and do not run hooks.
+
+        - Probes skip any cassette, and each backend of a client made
+        with `Client.with_urls()` is probed on its own, see
+        `HealthReport.backends`.
+        - Failing to reach Piston does not raise, it is reported
+        instead.
+
+        Args:
+            timeout: `float`
+                The number of seconds to wait for each request.
+                Defaults to `5.0`.
+
+        Returns:
+            `asyncio.Future[HealthReport]`: The health of the Piston
+            instance.
+
+        Raises:
+            `ValueError`: If the timeout is not positive.
+            `RuntimeError`: If the client is closed.
+        """
+        ...
+
     async def fetch_runtimes(self) -> t.List[Runtime]:
         """`async` Fetches the runtimes from Piston. This is an http
         request.
@@ -521,22 +1527,344 @@ class Client:
             runtimes.
 
         Raises:
-            `RuntimeError`: If the request to Piston failed.
+            `RuntimeError`: If the request to Piston failed, or the
+            client is closed.
         """
         ...
 
-    async def execute(self, executor: Executor) -> ExecResponse:
+    async def execute(
+        self, executor: Executor, cancel_token: t.Optional[CancelToken] = None
+    ) -> ExecResponse:
         """`async` Executes code using a given executor. This is an http
         request.
 
+        - Cancelling the awaiting task aborts the request.
+
         Args:
             executor: `Executor`
                 The executor to use for the request.
+            cancel_token: `CancelToken | None` = None
+                A token that aborts the request when cancelled.
 
         Returns:
             `asyncio.Future[ExecResponse]`: The response from Piston.
 
         Raises:
-            `RuntimeError`: If the request to Piston failed.
+            `ValueError`: If the executor failed validation, see
+            `Executor.validate()`.
+            `RuntimeError`: If the request to Piston failed, or the
+            client is closed.
+            `asyncio.CancelledError`: If the cancel token was cancelled.
+        """
+        ...
+
+@dataclass(init=False, frozen=True)
+class BackendStatus:
+    """The health of a backend used by a client created with
+    `Client.with_urls()`.
+
+    NOTE:
+        - This object cannot be instantiated, and is immutable.
+        - It can only be created by accessing `Client.backends`.
+
+    Properties:
+        url: `str`
+            The base url of the backend.
+        healthy: `bool`
+            Whether the backend is in rotation, rather than ejected.
+        in_flight: `int`
+            The number of requests currently in-flight.
+        consecutive_failures: `int`
+            The number of failed requests since the last success.
+        ejected_for: `float | None`
+            The number of seconds until an ejected backend is used
+            again, or `None` if it is healthy.
+    """
+
+    url: str
+    healthy: bool
+    in_flight: int
+    consecutive_failures: int
+    ejected_for: t.Optional[float]
+
+class CancelToken:
+    """A handle used to cancel a group of executions together.
+
+    Pass the same token to any number of `Client.execute()` calls, then
+    call `cancel()` to abort all of them. Their requests are dropped,
+    and awaiting them raises `asyncio.CancelledError`.
+
+    - Executions started with an already cancelled token are cancelled
+    immediately.
+    - A token can not be reset once cancelled.
+
+    Properties:
+        cancelled: `bool`
+            Whether the token has been cancelled.
+    """
+
+    def __init__(self) -> None: ...
+    @property
+    def cancelled(self) -> bool: ...
+    def cancel(self) -> None:
+        """Cancels every execution using this token, and any started
+        with it later.
+        """
+        ...
+
+@dataclass(init=False, frozen=True)
+class FakeRequest:
+    """A request received by a `FakeTransport`.
+
+    NOTE:
+        - This object cannot be instantiated, and is immutable.
+
+    Properties:
+        method: `str`
+            The http method of the request.
+        path: `str`
+            The path of the request, relative to the base url.
+        headers: `dict[str, str]`
+            The headers of the request.
+        body: `str | None`
+            The body of the request.
+    """
+
+    method: str
+    path: str
+    headers: t.Dict[str, str]
+    body: t.Optional[str]
+
+@dataclass(init=False)
+class HookRequest:
+    """A view of a request about to be sent to Piston, passed to
+    hooks added with `Client.add_hook()`.
+
+    - `before_request` hooks may modify the `executor` in place, or
+    replace it or the `headers` by assigning to them.
+    - The values of sensitive headers are masked, see
+    `HookRequest.reveal_headers()`. Masked values assigned back keep
+    their original value.
+
+    NOTE:
+        - This object cannot be instantiated.
+
+    Properties:
+        method: `str`
+            The http method of the request.
+        path: `str`
+            The path of the request, relative to the base url.
+        url: `str`
+            The base url the request is sent to.
+        headers: `dict[str, str]`
+            The headers sent with the request, with the values of
+            sensitive headers masked.
+        executor: `Executor | None`
+            The executor being sent, or `None` for requests without
+            one.
+    """
+
+    method: str = field(init=False)
+    path: str = field(init=False)
+    url: str = field(init=False)
+    headers: t.Dict[str, str]
+    executor: t.Optional[Executor]
+    def reveal_headers(self) -> t.Dict[str, str]:
+        """Gets the headers sent with the request, without masking the
+        values of sensitive headers such as the api key.
+
+        Returns:
+            `dict[str, str]`: The headers.
         """
         ...
+
+@dataclass(init=False, frozen=True)
+class HookResponse:
+    """A view of a response received from Piston, passed to hooks
+    added with `Client.add_hook()`.
+
+    NOTE:
+        - This object cannot be instantiated, and is immutable.
+
+    Properties:
+        status: `int`
+            The http status of the response.
+        body: `str`
+            The raw body of the response.
+        elapsed: `float`
+            The time the request took, in seconds.
+    """
+
+    status: int
+    body: str
+    elapsed: float
+
+@dataclass(init=False, frozen=True)
+class ClientStats:
+    """A snapshot of the requests sent by a `Client` and all of its
+    copies.
+
+    NOTE:
+        - This object cannot be instantiated, and is immutable.
+        - It can only be created with a call to `Client.stats()`.
+
+    Properties:
+        requests: `int`
+            The number of requests sent.
+        errors: `int`
+            The number of requests that failed without a response.
+        statuses: `dict[int, int]`
+            The number of responses with each http status.
+        executions: `dict[str, dict[str, int]]`
+            The number of executions for each language, by outcome.
+            Outcomes are `success`, `failure` (a non-zero exit code or
+            a signal), `rejected` (a non-200 status) and `error` (no
+            usable response). Executions past the first 100 languages
+            are counted under `other`.
+        latency_p50: `float | None`
+            The median latency in seconds, over the most recent 1000
+            requests.
+        latency_p95: `float | None`
+            The 95th percentile latency in seconds, over the most
+            recent 1000 requests.
+        latency_p99: `float | None`
+            The 99th percentile latency in seconds, over the most
+            recent 1000 requests.
+        bytes_sent: `int`
+            The number of request body bytes sent, after compression.
+        bytes_received: `int`
+            The number of response body bytes received, before
+            decompression.
+    """
+
+    requests: int
+    errors: int
+    statuses: t.Dict[int, int]
+    executions: t.Dict[str, t.Dict[str, int]]
+    latency_p50: t.Optional[float]
+    latency_p95: t.Optional[float]
+    latency_p99: t.Optional[float]
+    bytes_sent: int
+    bytes_received: int
+
+@dataclass(init=False, frozen=True)
+class PoolStats:
+    """A snapshot of the connections used by a `Client` and the copies
+    sharing its connection pool.
+
+    NOTE:
+        - This object cannot be instantiated, and is immutable.
+        - It can only be created with a call to `Client.pool_stats()`.
+
+    Properties:
+        connections: `int`
+            The number of connections opened.
+        requests: `int`
+            The number of responses received.
+        reused: `int`
+            The number of responses received on a connection that had
+            already delivered a response when the request was sent.
+        reuse_ratio: `float | None`
+            The fraction of responses received on a connection that
+            was reused, if any were received.
+        in_flight: `int`
+            The number of requests sent whose response has not been
+            read in full.
+        versions: `dict[str, int]`
+            The number of responses received with each http version,
+            such as `HTTP/1.1` or `HTTP/2.0`.
+    """
+
+    connections: int
+    requests: int
+    reused: int
+    reuse_ratio: t.Optional[float]
+    in_flight: int
+    versions: t.Dict[str, int]
+
+@dataclass(init=False, frozen=True)
+class HealthReport:
+    """The result of probing a Piston instance with `Client.health()`.
+
+    NOTE:
+        - This object cannot be instantiated, and is immutable.
+        - It can only be created with a call to `Client.health()`.
+
+    Properties:
+        url: `str`
+            The base url of the instance that was probed.
+        reachable: `bool`
+            Whether the instance responded at all.
+        healthy: `bool`
+            Whether the instance responded with runtimes that could be
+            parsed.
+        status: `int | None`
+            The status of the runtimes response, if reachable.
+        latency: `float | None`
+            The time the runtimes request took in seconds, if
+            reachable.
+        runtimes: `int | None`
+            The number of runtimes available, if healthy.
+        version: `str | None`
+            The Piston version of the instance, if it could be
+            detected. Only self-hosted instances reached over http
+            report their version.
+        features: `list[str]`
+            The optional features the instance supports, out of
+            `packages` and `websocket`.
+        error: `str | None`
+            Why the instance is not healthy, if it is not.
+        backends: `list[HealthReport]`
+            The report of each backend of a client made with
+            `Client.with_urls()`, in the order given. The other
+            properties describe the first healthy backend, or the first
+            backend if none are healthy. Empty for other clients.
+    """
+
+    url: str
+    reachable: bool
+    healthy: bool
+    status: t.Optional[int]
+    latency: t.Optional[float]
+    runtimes: t.Optional[int]
+    version: t.Optional[str]
+    features: t.List[str]
+    error: t.Optional[str]
+    backends: t.List[HealthReport]
+
+class FakeTransport:
+    """An in-memory transport that answers requests with canned
+    responses, without any network access. Pass it to
+    `Client.with_transport()`.
+
+    - Requests without a canned response receive a 404.
+
+    Properties:
+        requests: `list[FakeRequest]`
+            The requests received, in order.
+    """
+
+    @property
+    def requests(self) -> t.List[FakeRequest]: ...
+    def add_response(self, method: str, path: str, status: int, body: str) -> FakeTransport:
+        """Sets the response sent for a method and path, replacing any
+        existing response.
+
+        Args:
+            method: `str`
+                The http method, such as `GET` or `POST`.
+            path: `str`
+                The path relative to the base url, such as `/execute`.
+            status: `int`
+                The http status to respond with.
+            body: `str`
+                The body to respond with.
+
+        Returns:
+            `FakeTransport`: The transport, for chained method calls.
+        """
+        ...
+
+    def reset(self) -> None:
+        """Clears all responses and recorded requests."""
+        ...
@@ -0,0 +1,16 @@
+"""`piston_rspy.testing` - Utilities for testing code that uses
+`piston_rspy` without network access."""
+
+from __future__ import annotations
+
+__all__: list[str] = [
+    "MockPiston",
+    "MockRequest",
+    "MockResponse",
+]
+
+from .piston_rspy import testing as _testing
+
+MockPiston = _testing.MockPiston
+MockRequest = _testing.MockRequest
+MockResponse = _testing.MockResponse
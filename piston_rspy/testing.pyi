@@ -0,0 +1,169 @@
+from __future__ import annotations
+
+import typing as t
+from dataclasses import dataclass
+
+from .piston_rspy import Executor, Runtime
+
+__all__ = (
+    "MockPiston",
+    "MockRequest",
+    "MockResponse",
+)
+
+@dataclass(frozen=True)
+class MockResponse:
+    """A canned response served by a `MockPiston` server.
+
+    When `body` is set it is sent verbatim, otherwise a Piston execute
+    response is generated from the other fields, echoing the language
+    and version of the request.
+
+    Args:
+        stdout: `str` = ""
+            The text to send as `stdout`.
+        stderr: `str` = ""
+            The text to send as `stderr`.
+        code: `int | None` = 0
+            The exit code to send.
+        signal: `str | None` = None
+            The signal to send.
+        status: `int` = 200
+            The http status to respond with.
+        delay: `float` = 0.0
+            The number of seconds to wait before responding.
+        body: `str | None` = None
+            The raw body to respond with.
+
+    Raises:
+        `ValueError`: If the status is not from 100 to 999, or the
+        delay is negative or not finite.
+    """
+
+    stdout: str = ""
+    stderr: str = ""
+    code: t.Optional[int] = 0
+    signal: t.Optional[str] = None
+    status: int = 200
+    delay: float = 0.0
+    body: t.Optional[str] = None
+
+@dataclass(init=False, frozen=True)
+class MockRequest:
+    """A request received by a `MockPiston` server.
+
+    NOTE:
+        - This object cannot be instantiated, and is immutable.
+
+    Properties:
+        method: `str`
+            The http method of the request.
+        path: `str`
+            The path of the request.
+        headers: `dict[str, str]`
+            The headers of the request.
+        body: `str`
+            The body of the request.
+    """
+
+    method: str
+    path: str
+    headers: t.Dict[str, str]
+    body: str
+
+class MockPiston:
+    """A local http server that mimics the Piston v2 api, for testing
+    without network access.
+
+    It serves `/api/v2/runtimes` and `/api/v2/execute` from
+    programmable fixtures, and records every request it receives. Point
+    a client at it with `Client.with_url(mock.url)`.
+
+    - The server can be used as a context manager, which starts it on
+    enter and stops it on exit.
+
+    Args:
+        port: `int` = 0
+            The port to bind to. `0` picks a free port.
+
+    Properties:
+        url: `str | None`
+            The base url to pass to `Client.with_url()`, or `None` if
+            the server has not been started.
+        port: `int | None`
+            The port the server is bound to, or `None` if the server
+            has not been started.
+        requests: `list[MockRequest]`
+            The requests received by the server, in order.
+    """
+
+    def __init__(self, port: int = 0) -> None: ...
+    def __enter__(self) -> MockPiston: ...
+    def __exit__(self, *args: t.Any) -> None: ...
+    @property
+    def url(self) -> t.Optional[str]: ...
+    @property
+    def port(self) -> t.Optional[int]: ...
+    @property
+    def requests(self) -> t.List[MockRequest]: ...
+    def start(self) -> None:
+        """Starts the server on `127.0.0.1`. Does nothing if the server
+        is already running.
+
+        Raises:
+            `RuntimeError`: If the port could not be bound.
+        """
+        ...
+
+    def stop(self) -> None:
+        """Stops the server. Does nothing if the server is not
+        running.
+        """
+        ...
+
+    def set_runtimes(
+        self, runtimes: t.List[Runtime], response: t.Optional[MockResponse] = None
+    ) -> MockPiston:
+        """Sets the runtimes served by `/api/v2/runtimes`.
+
+        Args:
+            runtimes: `list[Runtime]`
+                The runtimes to serve.
+            response: `MockResponse | None` = None
+                An optional response controlling the status and delay,
+                or replacing the body entirely.
+
+        Returns:
+            `MockPiston`: The server, for chained method calls.
+        """
+        ...
+
+    def on_execute(
+        self,
+        handler: t.Union[MockResponse, t.Callable[[Executor], MockResponse]],
+        language: t.Optional[str] = None,
+    ) -> MockPiston:
+        """Sets how `/api/v2/execute` responds.
+
+        Args:
+            handler: `MockResponse | Callable[[Executor], MockResponse]`
+                The canned response to send, or a callable that
+                receives the requested `Executor` and returns the
+                response. If the callable raises, a 500 response is
+                sent.
+            language: `str | None` = None
+                The language this handler applies to. `None` applies to
+                any language without its own handler.
+
+        Returns:
+            `MockPiston`: The server, for chained method calls.
+        """
+        ...
+
+    def clear_requests(self) -> None:
+        """Clears the recorded requests."""
+        ...
+
+    def reset(self) -> None:
+        """Clears all fixtures and recorded requests."""
+        ...
@@ -0,0 +1,179 @@
+use std::io::{Cursor, Read};
+use std::path::{Component, Path, PathBuf};
+
+use flate2::read::GzDecoder;
+use log::debug;
+use pyo3::exceptions::{PyRuntimeError, PyValueError};
+use pyo3::prelude::*;
+
+use piston_rs::File as File_;
+
+use super::File;
+
+/// The default maximum number of files extracted from an archive.
+pub const DEFAULT_MAX_ENTRIES: usize = 1000;
+
+/// The default maximum total uncompressed size of an archive in bytes.
+pub const DEFAULT_MAX_SIZE: u64 = 10 * 1024 * 1024;
+
+/// Where the bytes of an archive come from.
+#[derive(FromPyObject)]
+pub enum ArchiveSource<'a> {
+    #[pyo3(transparent, annotation = "bytes")]
+    Bytes(&'a [u8]),
+    #[pyo3(transparent, annotation = "str")]
+    Path(PathBuf),
+}
+
+/// The limits enforced while extracting an archive, used to guard
+/// against zip bombs.
+#[derive(Clone, Copy)]
+pub struct ArchiveLimits {
+    pub max_entries: usize,
+    pub max_size: u64,
+}
+
+/// Tracks the files extracted so far against the limits.
+struct Extractor {
+    limits: ArchiveLimits,
+    total_size: u64,
+    files: Vec<File_>,
+}
+
+impl Extractor {
+    fn new(limits: ArchiveLimits) -> Self {
+        Self {
+            limits,
+            total_size: 0,
+            files: Vec::new(),
+        }
+    }
+
+    /// Reads a single entry into memory, refusing to read past the
+    /// remaining size budget regardless of what the archive claims.
+    fn push(&mut self, name: &Path, reader: impl Read) -> PyResult<()> {
+        if self.files.len() >= self.limits.max_entries {
+            return Err(PyValueError::new_err(format!(
+                "Archive contains more than {} files",
+                self.limits.max_entries
+            )));
+        }
+
+        let name = normalize_name(name)?;
+        let remaining = self.limits.max_size - self.total_size;
+        let mut data = Vec::new();
+
+        reader
+            .take(remaining.saturating_add(1))
+            .read_to_end(&mut data)
+            .map_err(|e| PyRuntimeError::new_err(format!("{:?}", e)))?;
+
+        if data.len() as u64 > remaining {
+            return Err(PyValueError::new_err(format!(
+                "Archive exceeds the maximum uncompressed size of {} bytes",
+                self.limits.max_size
+            )));
+        }
+
+        self.total_size += data.len() as u64;
+        self.files.push(File::from_data(name, data).convert());
+        Ok(())
+    }
+}
+
+/// Converts an archive entry path into a relative, `/` separated name.
+fn normalize_name(path: &Path) -> PyResult<String> {
+    let mut parts = Vec::new();
+
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => parts.push(part.to_string_lossy().to_string()),
+            Component::CurDir => {}
+            _ => {
+                return Err(PyValueError::new_err(format!(
+                    "Archive entry has an unsafe path: {:?}",
+                    path
+                )))
+            }
+        }
+    }
+
+    Ok(parts.join("/"))
+}
+
+fn extract_zip(data: &[u8], extractor: &mut Extractor) -> PyResult<()> {
+    let mut archive = zip::ZipArchive::new(Cursor::new(data))
+        .map_err(|e| PyRuntimeError::new_err(format!("{:?}", e)))?;
+
+    for i in 0..archive.len() {
+        let entry = archive
+            .by_index(i)
+            .map_err(|e| PyRuntimeError::new_err(format!("{:?}", e)))?;
+
+        if entry.is_dir() {
+            continue;
+        }
+
+        let name = PathBuf::from(entry.name());
+        extractor.push(&name, entry)?;
+    }
+
+    Ok(())
+}
+
+fn extract_tar(reader: impl Read, extractor: &mut Extractor) -> PyResult<()> {
+    let mut archive = tar::Archive::new(reader);
+    let entries = archive
+        .entries()
+        .map_err(|e| PyRuntimeError::new_err(format!("{:?}", e)))?;
+
+    for entry in entries {
+        let entry = entry.map_err(|e| PyRuntimeError::new_err(format!("{:?}", e)))?;
+
+        // Directories, links and devices have no content to send.
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+
+        let name = entry
+            .path()
+            .map_err(|e| PyRuntimeError::new_err(format!("{:?}", e)))?
+            .into_owned();
+
+        extractor.push(&name, entry)?;
+    }
+
+    Ok(())
+}
+
+/// Extracts every regular file in a zip, tar or gzipped tar archive
+/// into memory.
+pub fn extract(source: ArchiveSource, limits: ArchiveLimits) -> PyResult<Vec<File_>> {
+    let owned;
+    let data = match source {
+        ArchiveSource::Bytes(data) => data,
+        ArchiveSource::Path(path) => {
+            owned = std::fs::read(&path).map_err(|e| {
+                PyRuntimeError::new_err(format!("Unable to read {:?}: {:?}", path, e))
+            })?;
+            owned.as_slice()
+        }
+    };
+
+    let mut extractor = Extractor::new(limits);
+
+    if data.starts_with(b"PK\x03\x04") || data.starts_with(b"PK\x05\x06") {
+        extract_zip(data, &mut extractor)?;
+    } else if data.starts_with(&[0x1f, 0x8b]) {
+        extract_tar(GzDecoder::new(data), &mut extractor)?;
+    } else if data.get(257..262) == Some(b"ustar") {
+        extract_tar(data, &mut extractor)?;
+    } else {
+        return Err(PyValueError::new_err(
+            "Unrecognized archive format, expected zip, tar or tar.gz",
+        ));
+    }
+
+    debug!("Extracted {} files from archive", extractor.files.len());
+    Ok(extractor.files)
+}
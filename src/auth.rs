@@ -0,0 +1,176 @@
+use pyo3::exceptions::{PyTypeError, PyValueError};
+use pyo3::prelude::*;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use log::info;
+use tokio::sync::Mutex;
+
+use super::callback::call_maybe_async;
+
+/// Supplies the `Authorization` value sent with each request, in place
+/// of a static api key.
+pub enum AuthProvider {
+    /// Keys used in turn, moving to the next on a 401.
+    Keys {
+        keys: Vec<String>,
+        next: AtomicUsize,
+    },
+    /// A Python callable returning the value, which is cached until a
+    /// request using it receives a 401.
+    Callable {
+        callable: PyObject,
+        current: Mutex<Option<String>>,
+    },
+}
+
+impl AuthProvider {
+    /// Creates a provider from a list of keys, or a callable.
+    pub fn new(provider: &PyAny) -> PyResult<Self> {
+        if let Ok(keys) = provider.extract::<Vec<String>>() {
+            if keys.is_empty() {
+                return Err(PyValueError::new_err("At least one key is required"));
+            }
+
+            return Ok(Self::Keys {
+                keys,
+                next: AtomicUsize::new(0),
+            });
+        }
+
+        if provider.is_callable() {
+            return Ok(Self::Callable {
+                callable: provider.into(),
+                current: Mutex::new(None),
+            });
+        }
+
+        Err(PyTypeError::new_err(
+            "The auth provider must be a list of keys, or a callable",
+        ))
+    }
+
+    /// A short description of the provider, that does not reveal keys.
+    pub fn name(&self) -> String {
+        match self {
+            Self::Keys { keys, .. } => format!("keys({})", keys.len()),
+            Self::Callable { .. } => "callable".to_string(),
+        }
+    }
+
+    /// Calls the Python provider for a new value.
+    async fn call(callable: &PyObject) -> PyResult<String> {
+        let value = call_maybe_async(callable, ()).await?;
+
+        Python::with_gil(|py| {
+            value
+                .extract(py)
+                .map_err(|_| PyTypeError::new_err("The auth provider must return a str"))
+        })
+    }
+
+    /// The value to send with the next request.
+    pub async fn get(&self) -> PyResult<String> {
+        match self {
+            Self::Keys { keys, next } => {
+                let i = next.fetch_add(1, Ordering::Relaxed) % keys.len();
+                Ok(keys[i].clone())
+            }
+            Self::Callable { callable, current } => {
+                // Held while calling, so that concurrent requests wait
+                // for a single call rather than each making their own.
+                let mut current = current.lock().await;
+
+                if let Some(value) = current.as_ref() {
+                    return Ok(value.clone());
+                }
+
+                let value = Self::call(callable).await?;
+                *current = Some(value.clone());
+                Ok(value)
+            }
+        }
+    }
+
+    /// The value to retry with after a request using `rejected`
+    /// received a 401, or `None` if there is nothing else to try.
+    pub async fn refresh(&self, rejected: &str) -> PyResult<Option<String>> {
+        let value = match self {
+            Self::Keys { keys, .. } if keys.len() == 1 => return Ok(None),
+            // The shared counter may have moved on, and back to the
+            // rejected key, since it was handed out.
+            Self::Keys { keys, .. } => match keys.iter().position(|key| key == rejected) {
+                Some(i) => keys[(i + 1) % keys.len()].clone(),
+                None => self.get().await?,
+            },
+            Self::Callable { callable, current } => {
+                let mut current = current.lock().await;
+
+                // Another request may have refreshed it already.
+                match current.as_deref() {
+                    Some(value) if value != rejected => value.to_string(),
+                    _ => {
+                        info!("Refreshing authorization after a 401 response");
+                        let value = Self::call(callable).await?;
+                        *current = Some(value.clone());
+                        value
+                    }
+                }
+            }
+        };
+
+        Ok(Some(value).filter(|value| value != rejected))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::future::join_all;
+    use std::future::Future;
+
+    fn keys(keys: &[&str]) -> AuthProvider {
+        AuthProvider::Keys {
+            keys: keys.iter().map(|key| key.to_string()).collect(),
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    #[test]
+    fn keys_are_used_in_turn() {
+        let provider = keys(&["a", "b", "c"]);
+        let used: Vec<_> = (0..4).map(|_| block_on(provider.get()).unwrap()).collect();
+
+        assert_eq!(used, ["a", "b", "c", "a"]);
+    }
+
+    #[test]
+    fn rejected_keys_are_retried_with_the_next_key() {
+        let provider = keys(&["a", "b"]);
+
+        block_on(async {
+            // Both keys are handed out before either request is
+            // rejected, leaving the counter back at the first key.
+            let used = join_all([provider.get(), provider.get()]).await;
+            let used: Vec<_> = used.into_iter().map(Result::unwrap).collect();
+            assert_eq!(used, ["a", "b"]);
+
+            let retries = join_all(used.iter().map(|key| provider.refresh(key))).await;
+            let retries: Vec<_> = retries.into_iter().map(Result::unwrap).collect();
+            assert_eq!(retries, [Some("b".to_string()), Some("a".to_string())]);
+        });
+    }
+
+    #[test]
+    fn single_keys_are_not_retried() {
+        let provider = keys(&["a"]);
+
+        assert_eq!(block_on(provider.refresh("a")).unwrap(), None);
+    }
+}
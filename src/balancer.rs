@@ -0,0 +1,498 @@
+use pyo3::exceptions::{PyRuntimeError, PyValueError};
+use pyo3::prelude::*;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use log::{info, warn};
+
+use super::http::PoolOptions;
+use super::pool::PoolStats;
+use super::transport::{
+    ReqwestTransport, Transport, TransportError, TransportFuture, TransportRequest,
+};
+
+/// How a `BalancedTransport` picks the backend for each request.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Strategy {
+    /// Cycle through the healthy backends in turn.
+    RoundRobin,
+    /// Use the healthy backend with the fewest requests in-flight.
+    LeastInflight,
+    /// Use the first healthy backend, in the order given.
+    Failover,
+}
+
+impl Strategy {
+    /// Parses the strategy from its Python name.
+    pub fn parse(strategy: &str) -> PyResult<Self> {
+        match strategy {
+            "round_robin" => Ok(Self::RoundRobin),
+            "least_inflight" => Ok(Self::LeastInflight),
+            "failover" => Ok(Self::Failover),
+            _ => Err(PyValueError::new_err(format!(
+                "Unsupported strategy {:?}, expected one of [\"round_robin\", \"least_inflight\", \"failover\"]",
+                strategy
+            ))),
+        }
+    }
+
+    /// The Python name of the strategy.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::RoundRobin => "round_robin",
+            Self::LeastInflight => "least_inflight",
+            Self::Failover => "failover",
+        }
+    }
+}
+
+#[derive(Default)]
+struct Health {
+    consecutive_failures: u32,
+    ejected_until: Option<Instant>,
+}
+
+/// A single Piston instance behind a `BalancedTransport`.
+struct Backend {
+    url: String,
+    transport: ReqwestTransport,
+    in_flight: AtomicUsize,
+    health: Mutex<Health>,
+}
+
+impl Backend {
+    /// When the backend will be used again, if it is ejected.
+    fn ejected_until(&self, now: Instant) -> Option<Instant> {
+        self.health
+            .lock()
+            .unwrap()
+            .ejected_until
+            .filter(|until| *until > now)
+    }
+}
+
+/// Decrements the in-flight count of a backend when dropped.
+struct InFlight<'a>(&'a Backend);
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Spreads requests over several Piston instances, ejecting instances
+/// that fail repeatedly. Requests that could not connect are retried
+/// on the next instance, while other failures are not, as the request
+/// may already have been received.
+pub struct BalancedTransport {
+    backends: Vec<Backend>,
+    strategy: Strategy,
+    max_failures: u32,
+    cooldown: Duration,
+    next: AtomicUsize,
+}
+
+impl BalancedTransport {
+    pub fn new(
+        urls: &[String],
+        http: &reqwest::Client,
+        pool: &PoolOptions,
+        strategy: Strategy,
+        max_failures: u32,
+        cooldown: Duration,
+    ) -> PyResult<Self> {
+        if urls.is_empty() {
+            return Err(PyValueError::new_err("At least one url is required"));
+        }
+
+        let backends = urls
+            .iter()
+            .map(|url| Backend {
+                url: url.clone(),
+                transport: ReqwestTransport::new(url, http.clone(), pool),
+                in_flight: AtomicUsize::new(0),
+                health: Mutex::new(Health::default()),
+            })
+            .collect();
+
+        Ok(Self {
+            backends,
+            strategy,
+            max_failures: max_failures.max(1),
+            cooldown,
+            next: AtomicUsize::new(0),
+        })
+    }
+
+    /// Creates a transport for the same backends and settings that
+    /// sends requests with a different http client and pool. Backend
+    /// health is not carried over.
+    pub fn with_http(&self, http: &reqwest::Client, pool: &PoolOptions) -> PyResult<Self> {
+        let urls: Vec<_> = self.backends.iter().map(|b| b.url.clone()).collect();
+
+        Self::new(
+            &urls,
+            http,
+            pool,
+            self.strategy,
+            self.max_failures,
+            self.cooldown,
+        )
+    }
+
+    /// The order to try the backends in for a request. Healthy backends
+    /// come first, ordered by the strategy, followed by ejected ones in
+    /// the order they return.
+    fn order(&self) -> Vec<usize> {
+        let now = Instant::now();
+        let count = self.backends.len();
+
+        let start = match self.strategy {
+            Strategy::Failover => 0,
+            _ => self.next.fetch_add(1, Ordering::Relaxed) % count,
+        };
+
+        let (mut healthy, mut ejected): (Vec<_>, Vec<_>) = (0..count)
+            .map(|i| (start + i) % count)
+            .map(|i| (i, self.backends[i].ejected_until(now)))
+            .partition(|(_, until)| until.is_none());
+
+        if self.strategy == Strategy::LeastInflight {
+            healthy.sort_by_key(|(i, _)| self.backends[*i].in_flight.load(Ordering::SeqCst));
+        }
+
+        ejected.sort_by_key(|(_, until)| *until);
+        healthy.into_iter().chain(ejected).map(|(i, _)| i).collect()
+    }
+
+    /// Records the result of a request to a backend.
+    fn record(&self, backend: &Backend, failed: bool) {
+        let mut health = backend.health.lock().unwrap();
+
+        if !failed {
+            *health = Health::default();
+            return;
+        }
+
+        health.consecutive_failures += 1;
+        let failures = health.consecutive_failures;
+        if failures < self.max_failures {
+            return;
+        }
+
+        health.ejected_until = Some(Instant::now() + self.cooldown);
+
+        // Logging takes the GIL, which callers of `statuses` may hold
+        // while waiting for this lock.
+        drop(health);
+        warn!(
+            "Ejecting backend {} for {:.1?} after {} consecutive failures",
+            backend.url, self.cooldown, failures
+        );
+    }
+
+    /// The url and transport of each backend, in the order given.
+    pub fn backends(&self) -> impl Iterator<Item = (&str, &ReqwestTransport)> {
+        self.backends
+            .iter()
+            .map(|backend| (backend.url.as_str(), &backend.transport))
+    }
+
+    /// The current health of each backend.
+    pub fn statuses(&self) -> Vec<BackendStatus> {
+        let now = Instant::now();
+
+        self.backends
+            .iter()
+            .map(|backend| {
+                let health = backend.health.lock().unwrap();
+                let ejected_for = health
+                    .ejected_until
+                    .filter(|until| *until > now)
+                    .map(|until| (until - now).as_secs_f64());
+
+                BackendStatus {
+                    url: backend.url.clone(),
+                    healthy: ejected_for.is_none(),
+                    in_flight: backend.in_flight.load(Ordering::SeqCst),
+                    consecutive_failures: health.consecutive_failures,
+                    ejected_for,
+                }
+            })
+            .collect()
+    }
+}
+
+impl Transport for BalancedTransport {
+    fn send<'a>(&'a self, request: &'a TransportRequest) -> TransportFuture<'a> {
+        Box::pin(async move {
+            let mut last_error = None;
+
+            for (attempt, i) in self.order().into_iter().enumerate() {
+                let backend = &self.backends[i];
+
+                if attempt > 0 {
+                    info!(
+                        "Retrying {} {} on backend {} (attempt {})",
+                        request.method,
+                        request.path,
+                        backend.url,
+                        attempt + 1
+                    );
+                }
+
+                backend.in_flight.fetch_add(1, Ordering::SeqCst);
+                let in_flight = InFlight(backend);
+                let result = backend.transport.try_send(request).await;
+                drop(in_flight);
+
+                match result {
+                    Ok(mut response) => {
+                        self.record(backend, matches!(response.status, 502..=504));
+                        response.backend = Some(backend.url.clone());
+                        return Ok(response);
+                    }
+                    // Nothing reached the backend, so the request is safe
+                    // to send to the next one.
+                    Err(TransportError::Connect(e)) => {
+                        warn!("Unable to connect to backend {}: {}", backend.url, e);
+                        self.record(backend, true);
+                        last_error = Some(TransportError::Connect(e).into());
+                    }
+                    // The backend may have received the request, and an
+                    // execute in particular must not run twice.
+                    Err(e) => {
+                        warn!("Backend {} failed: {}", backend.url, e);
+                        self.record(backend, true);
+                        return Err(e.into());
+                    }
+                }
+            }
+
+            Err(last_error.unwrap_or_else(|| PyRuntimeError::new_err("No backends available")))
+        })
+    }
+
+    fn name(&self) -> String {
+        format!("balanced({})", self.strategy.name())
+    }
+
+    fn close(&self) {
+        for backend in self.backends.iter() {
+            backend.transport.close();
+        }
+    }
+
+    fn pool_stats(&self) -> Option<PoolStats> {
+        self.backends
+            .iter()
+            .filter_map(|backend| backend.transport.pool_stats())
+            .reduce(PoolStats::merge)
+    }
+}
+
+/// The health of a backend used by a client created with
+/// `Client.with_urls`.
+///
+/// **NOTE**:
+///
+/// - This object cannot be instantiated, and is immutable.
+/// - It can only be created by accessing `Client.backends`.
+#[pyclass]
+#[derive(Clone)]
+pub struct BackendStatus {
+    url: String,
+    healthy: bool,
+    in_flight: usize,
+    consecutive_failures: u32,
+    ejected_for: Option<f64>,
+}
+
+#[pymethods]
+impl BackendStatus {
+    fn __repr__(&self) -> PyResult<String> {
+        Ok(format!(
+            "BackendStatus {{ url: \"{}\", healthy: {}, in_flight: {}, consecutive_failures: {} }}",
+            self.url, self.healthy, self.in_flight, self.consecutive_failures
+        ))
+    }
+
+    fn __str__(&self) -> PyResult<String> {
+        self.__repr__()
+    }
+
+    /// `str`: The base url of the backend.
+    #[getter]
+    fn url(&self) -> String {
+        self.url.clone()
+    }
+
+    /// `bool`: Whether the backend is in rotation, rather than ejected.
+    #[getter]
+    fn healthy(&self) -> bool {
+        self.healthy
+    }
+
+    /// `int`: The number of requests currently in-flight.
+    #[getter]
+    fn in_flight(&self) -> usize {
+        self.in_flight
+    }
+
+    /// `int`: The number of failed requests since the last success.
+    #[getter]
+    fn consecutive_failures(&self) -> u32 {
+        self.consecutive_failures
+    }
+
+    /// `float | None`: The number of seconds until an ejected backend
+    /// is used again, or `None` if it is healthy.
+    #[getter]
+    fn ejected_for(&self) -> Option<f64> {
+        self.ejected_for
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::sync::Arc;
+    use std::thread;
+
+    fn balancer(strategy: Strategy, max_failures: u32) -> BalancedTransport {
+        let urls: Vec<_> = (0..3).map(|i| format!("http://piston-{}", i)).collect();
+
+        BalancedTransport::new(
+            &urls,
+            &reqwest::Client::new(),
+            &PoolOptions::default(),
+            strategy,
+            max_failures,
+            Duration::from_secs(60),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn round_robin_cycles_through_backends() {
+        let balancer = balancer(Strategy::RoundRobin, 1);
+
+        assert_eq!(balancer.order(), vec![0, 1, 2]);
+        assert_eq!(balancer.order(), vec![1, 2, 0]);
+        assert_eq!(balancer.order(), vec![2, 0, 1]);
+        assert_eq!(balancer.order(), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn failover_keeps_the_given_order() {
+        let balancer = balancer(Strategy::Failover, 1);
+
+        assert_eq!(balancer.order(), vec![0, 1, 2]);
+        assert_eq!(balancer.order(), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn least_inflight_prefers_idle_backends() {
+        let balancer = balancer(Strategy::LeastInflight, 1);
+        balancer.backends[0].in_flight.store(2, Ordering::SeqCst);
+        balancer.backends[1].in_flight.store(1, Ordering::SeqCst);
+
+        assert_eq!(balancer.order()[0], 2);
+        assert_eq!(balancer.order(), vec![2, 1, 0]);
+    }
+
+    #[test]
+    fn backends_are_ejected_after_max_failures() {
+        let balancer = balancer(Strategy::Failover, 2);
+        let backend = &balancer.backends[0];
+
+        balancer.record(backend, true);
+        assert!(balancer.statuses()[0].healthy);
+        assert_eq!(balancer.order(), vec![0, 1, 2]);
+
+        balancer.record(backend, true);
+        let status = &balancer.statuses()[0];
+        assert!(!status.healthy);
+        assert_eq!(status.consecutive_failures, 2);
+        assert!(status.ejected_for.is_some_and(|s| s > 0.0 && s <= 60.0));
+        assert_eq!(balancer.order(), vec![1, 2, 0]);
+
+        balancer.record(backend, false);
+        let status = &balancer.statuses()[0];
+        assert!(status.healthy);
+        assert_eq!(status.consecutive_failures, 0);
+        assert_eq!(balancer.order(), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn ejected_backends_are_tried_in_the_order_they_return() {
+        let balancer = balancer(Strategy::Failover, 1);
+        balancer.record(&balancer.backends[1], true);
+        balancer.record(&balancer.backends[0], true);
+
+        assert_eq!(balancer.order(), vec![2, 1, 0]);
+    }
+
+    /// Forwards records through the GIL, like the logger installed for
+    /// Python.
+    struct GilLogger;
+
+    impl log::Log for GilLogger {
+        fn enabled(&self, _: &log::Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, _: &log::Record) {
+            Python::with_gil(|_| {});
+        }
+
+        fn flush(&self) {}
+    }
+
+    static LOGGER: GilLogger = GilLogger;
+
+    #[test]
+    fn statuses_can_be_read_during_ejections() {
+        pyo3::prepare_freethreaded_python();
+        if log::set_logger(&LOGGER).is_ok() {
+            log::set_max_level(log::LevelFilter::Warn);
+        }
+
+        let balancer = Arc::new(balancer(Strategy::RoundRobin, 1));
+        let (done, finished) = mpsc::channel();
+
+        let ejecting = thread::spawn({
+            let balancer = balancer.clone();
+            let done = done.clone();
+            move || {
+                for _ in 0..5000 {
+                    for backend in balancer.backends.iter() {
+                        balancer.record(backend, true);
+                    }
+                }
+                done.send(()).unwrap();
+            }
+        });
+
+        let reading = thread::spawn({
+            let balancer = balancer.clone();
+            move || {
+                for _ in 0..5000 {
+                    Python::with_gil(|_| balancer.statuses());
+                }
+                done.send(()).unwrap();
+            }
+        });
+
+        for _ in 0..2 {
+            finished
+                .recv_timeout(Duration::from_secs(10))
+                .expect("reading statuses deadlocked with an ejection");
+        }
+
+        ejecting.join().unwrap();
+        reading.join().unwrap();
+    }
+}
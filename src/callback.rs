@@ -0,0 +1,85 @@
+use pyo3::once_cell::GILOnceCell;
+use pyo3::prelude::*;
+use pyo3::types::PyTuple;
+
+use futures_util::future::Either;
+
+/// The `Cancellable` class shipped in `piston_rspy._cancellable`, which
+/// runs an awaitable as a task that can be cancelled from any thread.
+static CANCELLABLE_TYPE: GILOnceCell<PyObject> = GILOnceCell::new();
+
+/// Cancels a Python task when dropped, so that dropping the Rust future
+/// awaiting it also stops the Python side.
+struct CancelOnDrop {
+    cancellable: Option<PyObject>,
+    event_loop: PyObject,
+}
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        let cancellable = match self.cancellable.take() {
+            Some(cancellable) => cancellable,
+            None => return,
+        };
+
+        if unsafe { pyo3::ffi::Py_IsInitialized() } == 0 {
+            return;
+        }
+
+        // The loop may already be closed, in which case there is
+        // nothing left to cancel.
+        Python::with_gil(|py| {
+            let _ = cancellable.getattr(py, "cancel").and_then(|cancel| {
+                self.event_loop
+                    .call_method1(py, "call_soon_threadsafe", (cancel,))
+            });
+        });
+    }
+}
+
+/// Calls a Python callable that may be sync or async, awaiting the
+/// result if it is awaitable.
+///
+/// If the returned future is dropped while awaiting, the Python task
+/// is cancelled.
+///
+/// This must be called from within a future started by
+/// `pyo3_asyncio`, so that the running event loop can be found.
+pub async fn call_maybe_async<A>(callable: &PyObject, args: A) -> PyResult<PyObject>
+where
+    A: IntoPy<Py<PyTuple>>,
+{
+    let pending = Python::with_gil(|py| -> PyResult<_> {
+        let result = callable.as_ref(py).call1(args)?;
+
+        if !result.hasattr("__await__")? {
+            return Ok(Either::Left(PyObject::from(result)));
+        }
+
+        let cancellable_type = CANCELLABLE_TYPE.get_or_try_init(py, || -> PyResult<_> {
+            let module = py.import("piston_rspy._cancellable")?;
+            Ok(module.getattr("Cancellable")?.into())
+        })?;
+
+        let locals = pyo3_asyncio::tokio::get_current_locals(py)?;
+        let cancellable = cancellable_type.call1(py, (result,))?;
+        let coroutine = cancellable.call_method0(py, "run")?;
+        let future = pyo3_asyncio::into_future_with_locals(&locals, coroutine.as_ref(py))?;
+
+        let guard = CancelOnDrop {
+            cancellable: Some(cancellable),
+            event_loop: locals.event_loop(py).into(),
+        };
+
+        Ok(Either::Right((future, guard)))
+    })?;
+
+    match pending {
+        Either::Left(result) => Ok(result),
+        Either::Right((future, mut guard)) => {
+            let result = future.await;
+            guard.cancellable = None;
+            result
+        }
+    }
+}
@@ -0,0 +1,91 @@
+use pyo3::exceptions::asyncio::CancelledError;
+use pyo3::prelude::*;
+use std::future::Future;
+use std::sync::Arc;
+
+use futures_util::future::{select, Either};
+use tokio::sync::watch;
+
+/// A handle used to cancel a group of executions together.
+///
+/// Pass the same token to any number of `Client.execute` calls, then
+/// call `cancel` to abort all of them. Their requests are dropped, and
+/// awaiting them raises `asyncio.CancelledError`.
+///
+/// - Executions started with an already cancelled token are cancelled
+/// immediately.
+/// - A token can not be reset once cancelled.
+#[pyclass]
+#[derive(Clone)]
+#[pyo3(text_signature = "() -> CancelToken")]
+pub struct CancelToken {
+    sender: Arc<watch::Sender<bool>>,
+}
+
+impl CancelToken {
+    /// Runs a future until it completes, or the token is cancelled.
+    pub async fn run<F, T>(&self, future: F) -> PyResult<T>
+    where
+        F: Future<Output = PyResult<T>>,
+    {
+        self.run_or(future, || {
+            CancelledError::new_err("The execution was cancelled by its CancelToken")
+        })
+        .await
+    }
+
+    /// Runs a future until it completes, or the token is cancelled, in
+    /// which case the future is dropped and `error` is returned.
+    pub async fn run_or<F, T, E>(&self, future: F, error: E) -> PyResult<T>
+    where
+        F: Future<Output = PyResult<T>>,
+        E: FnOnce() -> PyErr,
+    {
+        let mut receiver = self.sender.subscribe();
+        let cancelled = Box::pin(async move {
+            let _ = receiver.wait_for(|cancelled| *cancelled).await;
+        });
+
+        match select(Box::pin(future), cancelled).await {
+            Either::Left((result, _)) => result,
+            Either::Right(_) => Err(error()),
+        }
+    }
+}
+
+impl Default for CancelToken {
+    fn default() -> Self {
+        Self {
+            sender: Arc::new(watch::Sender::new(false)),
+        }
+    }
+}
+
+#[pymethods]
+impl CancelToken {
+    #[new]
+    fn new() -> Self {
+        Self::default()
+    }
+
+    fn __repr__(&self) -> PyResult<String> {
+        Ok(format!("CancelToken {{ cancelled: {} }}", self.cancelled()))
+    }
+
+    fn __str__(&self) -> PyResult<String> {
+        self.__repr__()
+    }
+
+    /// `bool`: Whether the token has been cancelled.
+    #[getter]
+    fn cancelled(&self) -> bool {
+        *self.sender.borrow()
+    }
+
+    /// Cancels every execution using this token, and any started with
+    /// it later.
+    #[pyo3(text_signature = "(self) -> None")]
+    pub fn cancel(&self) {
+        self.sender.send_replace(true);
+    }
+}
@@ -0,0 +1,203 @@
+use pyo3::exceptions::{PyRuntimeError, PyValueError};
+use pyo3::prelude::*;
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::Mutex;
+
+use super::client::redact_headers;
+use super::transport::TransportResponse;
+
+/// Response headers masked in cassettes, besides those always masked
+/// in requests, as they may hold credentials or session state.
+const SENSITIVE_RESPONSE_HEADERS: [&str; 4] = [
+    "set-cookie",
+    "set-cookie2",
+    "www-authenticate",
+    "proxy-authenticate",
+];
+
+/// How a cassette treats the requests sent through it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CassetteMode {
+    /// Send every request, and record it to a fresh cassette.
+    Record,
+    /// Never send requests, and fail on any that were not recorded.
+    Replay,
+    /// Replay recorded requests, and record any new ones.
+    Auto,
+}
+
+impl CassetteMode {
+    /// Parses the mode from its Python name.
+    pub fn parse(mode: &str) -> PyResult<Self> {
+        match mode {
+            "record" => Ok(Self::Record),
+            "replay" => Ok(Self::Replay),
+            "auto" => Ok(Self::Auto),
+            _ => Err(PyValueError::new_err(format!(
+                "Unsupported cassette mode {:?}, expected one of [\"record\", \"replay\", \"auto\"]",
+                mode
+            ))),
+        }
+    }
+
+    /// The Python name of the mode.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Record => "record",
+            Self::Replay => "replay",
+            Self::Auto => "auto",
+        }
+    }
+}
+
+/// A recorded request/response pair.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct Interaction {
+    method: String,
+    path: String,
+    body: Value,
+    response: TransportResponse,
+    #[serde(skip)]
+    played: bool,
+}
+
+/// The on disk format of a cassette.
+#[derive(Default, Serialize, Deserialize)]
+struct CassetteFile {
+    interactions: Vec<Interaction>,
+}
+
+/// Normalizes a request body so that semantically equal JSON bodies
+/// match, regardless of key order or whitespace.
+fn normalize_body(body: Option<&str>) -> Value {
+    match body {
+        None => Value::Null,
+        Some(body) => serde_json::from_str(body).unwrap_or_else(|_| Value::String(body.into())),
+    }
+}
+
+/// Records request/response pairs to a JSON file, and replays them.
+pub struct Cassette {
+    path: PathBuf,
+    mode: CassetteMode,
+    interactions: Mutex<Vec<Interaction>>,
+}
+
+impl Cassette {
+    /// Opens a cassette, loading any existing interactions unless
+    /// recording from scratch.
+    pub fn open(path: PathBuf, mode: CassetteMode) -> PyResult<Self> {
+        let interactions = match (mode, path.exists()) {
+            (CassetteMode::Record, _) | (CassetteMode::Auto, false) => Vec::new(),
+            (CassetteMode::Replay, false) => {
+                return Err(PyValueError::new_err(format!(
+                    "Cassette {:?} does not exist and can not be replayed",
+                    path
+                )))
+            }
+            (_, true) => {
+                let data = std::fs::read(&path).map_err(|e| {
+                    PyRuntimeError::new_err(format!("Unable to read cassette {:?}: {:?}", path, e))
+                })?;
+
+                serde_json::from_slice::<CassetteFile>(&data)
+                    .map_err(|e| {
+                        PyValueError::new_err(format!("Malformed cassette {:?}: {}", path, e))
+                    })?
+                    .interactions
+            }
+        };
+
+        Ok(Self {
+            path,
+            mode,
+            interactions: Mutex::new(interactions),
+        })
+    }
+
+    /// The path of the cassette file.
+    pub fn path(&self) -> &PathBuf {
+        &self.path
+    }
+
+    /// The mode of the cassette.
+    pub fn mode(&self) -> CassetteMode {
+        self.mode
+    }
+
+    /// Finds the recorded response for a request. Unplayed interactions
+    /// are used first, in order, after which the last match repeats.
+    pub async fn play(
+        &self,
+        method: &str,
+        path: &str,
+        body: Option<&str>,
+    ) -> PyResult<Option<TransportResponse>> {
+        if self.mode == CassetteMode::Record {
+            return Ok(None);
+        }
+
+        let body = normalize_body(body);
+        let mut interactions = self.interactions.lock().await;
+        let mut matches = interactions
+            .iter_mut()
+            .filter(|i| i.method == method && i.path == path && i.body == body)
+            .peekable();
+
+        let mut last = None;
+        while let Some(interaction) = matches.next() {
+            if !interaction.played || matches.peek().is_none() {
+                interaction.played = true;
+                last = Some(interaction.response.clone());
+                break;
+            }
+        }
+
+        match (last, self.mode) {
+            (Some(response), _) => Ok(Some(response)),
+            (None, CassetteMode::Replay) => Err(PyRuntimeError::new_err(format!(
+                "No interaction in cassette {:?} matches {} {} with body {}",
+                self.path, method, path, body
+            ))),
+            (None, _) => Ok(None),
+        }
+    }
+
+    /// Records an interaction, and saves the cassette to disk. The
+    /// values of sensitive response headers are masked.
+    pub async fn record(
+        &self,
+        method: &str,
+        path: &str,
+        body: Option<&str>,
+        mut response: TransportResponse,
+    ) -> PyResult<()> {
+        let sensitive: HashSet<_> = SENSITIVE_RESPONSE_HEADERS
+            .iter()
+            .map(|name| name.to_string())
+            .collect();
+        response.headers = redact_headers(&response.headers, &sensitive);
+
+        let mut interactions = self.interactions.lock().await;
+        interactions.push(Interaction {
+            method: method.to_string(),
+            path: path.to_string(),
+            body: normalize_body(body),
+            response,
+            played: true,
+        });
+
+        let file = CassetteFile {
+            interactions: interactions.clone(),
+        };
+
+        let data = serde_json::to_vec_pretty(&file).unwrap();
+        tokio::fs::write(&self.path, data).await.map_err(|e| {
+            PyRuntimeError::new_err(format!("Unable to write cassette {:?}: {:?}", self.path, e))
+        })
+    }
+}
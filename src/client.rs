@@ -1,12 +1,614 @@
+use pyo3::exceptions::{PyRuntimeError, PyTypeError, PyValueError};
 use pyo3::prelude::*;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use piston_rs::Client as Client_;
+use futures_util::future::join_all;
+use log::{debug, error, info, log, warn, Level};
 
+use piston_rs::ExecResponse as ExecResponse_;
+use piston_rs::ExecResult as ExecResult_;
+use piston_rs::Executor as Executor_;
+use piston_rs::Runtime as Runtime_;
+use reqwest::header::{HeaderName, HeaderValue};
+use reqwest::{Method, StatusCode};
+use serde::Deserialize;
+
+use super::auth::AuthProvider;
+use super::balancer::{BackendStatus, BalancedTransport, Strategy};
+use super::cancel::CancelToken;
+use super::cassette::{Cassette, CassetteMode};
+use super::compression::{Compression, Encoding};
+use super::config::Settings;
+use super::executor::Exchange;
+use super::health::{HealthReport, Probe};
+use super::hooks::{self, Hook, HookRequest, HookResponse};
+use super::http::{self, HttpOptions, PoolOptions, ProxyOptions, ProxyScheme, TlsOptions};
+use super::lifecycle::{Lifecycle, DEFAULT_GRACE_PERIOD};
+use super::metrics::{ClientStats, Metrics, Outcome};
+use super::partial;
+use super::pool::PoolStats;
+use super::ratelimit::RateLimiter;
+use super::transport::{
+    CassetteTransport, FakeTransport, PyTransport, ReqwestTransport, Transport, TransportRequest,
+    TransportResponse, UnixTransport,
+};
 use super::ExecResponse;
 use super::Executor;
 use super::Runtime;
 
+/// The url of the public Piston v2 api.
+const DEFAULT_URL: &str = "https://emkc.org/api/v2/piston";
+
+/// The body Piston sends when code is executed successfully.
+#[derive(Deserialize)]
+struct RawExecResponse {
+    language: String,
+    version: String,
+    run: ExecResult_,
+    compile: Option<ExecResult_>,
+}
+
+/// The body Piston sends when code is executed successfully, after
+/// being cut off at `max_response_bytes`, so that any field may be
+/// missing.
+#[derive(Deserialize)]
+struct TruncatedExecResponse {
+    language: Option<String>,
+    version: Option<String>,
+    run: Option<TruncatedExecResult>,
+    compile: Option<TruncatedExecResult>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct TruncatedExecResult {
+    stdout: String,
+    stderr: String,
+    output: String,
+    code: Option<isize>,
+    signal: Option<String>,
+}
+
+impl From<TruncatedExecResult> for ExecResult_ {
+    fn from(result: TruncatedExecResult) -> Self {
+        Self {
+            stdout: result.stdout,
+            stderr: result.stderr,
+            output: result.output,
+            code: result.code,
+            signal: result.signal,
+        }
+    }
+}
+
+/// Parses an execute response that was cut off, recovering what it
+/// can. A language or version that was not read in full is left empty.
+/// Returns the response, and whether the run and compile results were
+/// cut off.
+fn parse_truncated(body: &str) -> serde_json::Result<(RawExecResponse, bool, bool)> {
+    let partial = partial::complete(body)
+        .ok_or_else(|| serde::de::Error::custom("nothing could be recovered"))?;
+    let raw: TruncatedExecResponse = serde_json::from_str(&partial.json)?;
+
+    // A language or version that was cut off is only partly read.
+    let open = partial.path.first().map(String::as_str);
+    let language = raw.language.filter(|_| open != Some("language"));
+    let version = raw.version.filter(|_| open != Some("version"));
+
+    let truncated = (
+        raw.run.is_none() || open == Some("run"),
+        open == Some("compile"),
+    );
+
+    let raw = RawExecResponse {
+        language: language.unwrap_or_default(),
+        version: version.unwrap_or_default(),
+        run: raw.run.unwrap_or_default().into(),
+        compile: raw.compile.map(Into::into),
+    };
+
+    Ok((raw, truncated.0, truncated.1))
+}
+
+/// Headers whose values are always masked, see `redact_headers`.
+const SENSITIVE_HEADERS: [&str; 5] = [
+    "authorization",
+    "proxy-authorization",
+    "cookie",
+    "x-api-key",
+    "x-auth-token",
+];
+
+/// What sensitive header values are replaced with.
+pub const REDACTED: &str = "***";
+
+/// Whether the value of a header must be masked, given the headers
+/// marked sensitive besides `SENSITIVE_HEADERS`.
+pub fn is_sensitive(name: &str, sensitive: &HashSet<String>) -> bool {
+    let name = name.to_ascii_lowercase();
+    SENSITIVE_HEADERS.contains(&name.as_str()) || sensitive.contains(&name)
+}
+
+/// Copies headers, masking the values of sensitive headers.
+pub fn redact_headers(
+    headers: &HashMap<String, String>,
+    sensitive: &HashSet<String>,
+) -> HashMap<String, String> {
+    headers
+        .iter()
+        .map(|(key, value)| match is_sensitive(key, sensitive) {
+            true => (key.clone(), REDACTED.to_string()),
+            false => (key.clone(), value.clone()),
+        })
+        .collect()
+}
+
+/// The header a generated id is sent in with each request.
+const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// A response to a request sent by `Connection::send`.
+struct Sent {
+    response: TransportResponse,
+    /// The executor that was sent, after any hooks.
+    executor: Option<Executor_>,
+    request_id: String,
+    /// The seconds taken to receive the response, excluding hooks.
+    elapsed: f64,
+}
+
+/// Generates the headers sent with every request.
+fn generate_headers(key: Option<&str>) -> HashMap<String, String> {
+    let mut headers = HashMap::with_capacity(3);
+    headers.insert("accept".to_string(), "application/json".to_string());
+    headers.insert("user-agent".to_string(), "piston-rs".to_string());
+
+    if let Some(key) = key {
+        headers.insert("authorization".to_string(), key.to_string());
+    }
+
+    headers
+}
+
+/// Validates a grace period given in seconds.
+fn checked_grace_period(grace_period: f64) -> PyResult<f64> {
+    match grace_period.is_finite() && grace_period >= 0.0 {
+        true => Ok(grace_period),
+        false => Err(PyValueError::new_err(
+            "The grace period must be a non-negative number of seconds",
+        )),
+    }
+}
+
+/// Ensures a timeout is a positive number of seconds.
+fn checked_timeout(timeout: f64) -> PyResult<Duration> {
+    match timeout.is_finite() && timeout > 0.0 {
+        true => Ok(Duration::from_secs_f64(timeout)),
+        false => Err(PyValueError::new_err(format!(
+            "Timeout must be a positive number of seconds, got {}",
+            timeout
+        ))),
+    }
+}
+
+/// Sends requests to Piston through a transport.
+#[derive(Clone)]
+struct Connection {
+    transport: Arc<dyn Transport>,
+    url: String,
+    headers: HashMap<String, String>,
+    cassette: Option<Arc<CassetteTransport>>,
+    balancer: Option<Arc<BalancedTransport>>,
+    custom_transport: bool,
+    options: HttpOptions,
+    auth: Option<Arc<AuthProvider>>,
+    /// Headers marked sensitive, besides `SENSITIVE_HEADERS`.
+    sensitive: HashSet<String>,
+    hooks: Arc<Vec<Hook>>,
+    metrics: Arc<Metrics>,
+    lifecycle: Arc<Lifecycle>,
+    grace_period: f64,
+    timeout: Option<Duration>,
+    compression: Compression,
+    max_response_bytes: Option<usize>,
+    /// Whether execute responses over `max_response_bytes` are cut
+    /// off, rather than failing.
+    truncate: bool,
+    /// Whether execute responses keep the body they were parsed from.
+    raw_responses: bool,
+    rate_limit: Option<Arc<RateLimiter>>,
+}
+
+impl Connection {
+    /// The headers sent with every request, with the values of
+    /// sensitive headers masked.
+    fn redacted_headers(&self) -> HashMap<String, String> {
+        redact_headers(&self.headers, &self.sensitive)
+    }
+
+    /// Creates a copy of this connection using different http options,
+    /// rebuilding its http transports beneath any cassette. Transports
+    /// set with `Client.with_transport`, and Unix socket transports,
+    /// are kept as they are.
+    fn with_options(&self, options: HttpOptions) -> PyResult<Self> {
+        let mut connection = self.clone();
+        let http = options.build()?;
+        connection.options = options;
+
+        if self.custom_transport {
+            return Ok(connection);
+        }
+
+        let base: Arc<dyn Transport> = match &self.balancer {
+            Some(balancer) => {
+                let balancer = Arc::new(balancer.with_http(&http, &connection.options.pool)?);
+                connection.balancer = Some(balancer.clone());
+                balancer
+            }
+            None => Arc::new(ReqwestTransport::new(
+                &self.url,
+                http,
+                &connection.options.pool,
+            )),
+        };
+
+        connection.transport = match &self.cassette {
+            Some(cassette) => {
+                let cassette = Arc::new(CassetteTransport::new(cassette.cassette.clone(), base));
+                connection.cassette = Some(cassette.clone());
+                cassette
+            }
+            None => base,
+        };
+
+        Ok(connection)
+    }
+
+    /// Sends a request through the transport, logging its progress.
+    /// Returns the response, and the time it took in seconds.
+    async fn transmit(&self, request: &TransportRequest) -> PyResult<(TransportResponse, f64)> {
+        let sent = request.body.as_ref().map_or(0, |b| b.len());
+        debug!(
+            "Sending {} {}{} ({} bytes) via {}",
+            request.method,
+            self.url,
+            request.path,
+            sent,
+            self.transport.name()
+        );
+
+        let start = Instant::now();
+        let result = match self.timeout {
+            Some(timeout) => tokio::time::timeout(timeout, self.transport.send(request))
+                .await
+                .unwrap_or_else(|_| {
+                    Err(PyRuntimeError::new_err(format!(
+                        "Request timed out after {:.1?}",
+                        timeout
+                    )))
+                }),
+            None => self.transport.send(request).await,
+        };
+        let elapsed = start.elapsed();
+
+        let result = result.and_then(|mut response| match request.max_response_bytes {
+            Some(limit) => {
+                response.limit(limit);
+                if response.truncated && !(self.truncate && request.path == "/execute") {
+                    return Err(PyRuntimeError::new_err(format!(
+                        "Response exceeded max_response_bytes of {} bytes",
+                        limit
+                    )));
+                }
+
+                Ok(response)
+            }
+            None => Ok(response),
+        });
+
+        // Transports that compress the body report what they sent and
+        // received on the wire.
+        let status = result.as_ref().ok().map(|r| r.status);
+        let sent = result.as_ref().ok().and_then(|r| r.sent).unwrap_or(sent);
+        let received = result
+            .as_ref()
+            .map_or(0, |r| r.received.unwrap_or(r.body.len()));
+        self.metrics
+            .record_request(status, elapsed.as_secs_f64(), sent, received);
+
+        match &result {
+            Ok(response) => {
+                let level = if response.status < 400 {
+                    Level::Debug
+                } else {
+                    Level::Warn
+                };
+
+                log!(
+                    level,
+                    "{} {} returned {} in {:.1?} ({} bytes)",
+                    request.method,
+                    request.path,
+                    response.status,
+                    elapsed,
+                    response.body.len()
+                );
+            }
+            Err(error) => error!(
+                "{} {} failed after {:.1?}: {}",
+                request.method, request.path, elapsed, error
+            ),
+        }
+
+        result.map(|response| (response, elapsed.as_secs_f64()))
+    }
+
+    /// Sends a request with the value from the auth provider, if any.
+    /// A 401 response refreshes the value, and the request is retried
+    /// once if it changed. Returns the response, and the seconds taken
+    /// to send the request, including any retry.
+    async fn authorize(&self, mut request: TransportRequest) -> PyResult<(TransportResponse, f64)> {
+        let auth = match &self.auth {
+            Some(auth) => auth,
+            None => return self.transmit(&request).await,
+        };
+
+        let value = auth.get().await?;
+        request
+            .headers
+            .insert("authorization".to_string(), value.clone());
+
+        let (response, elapsed) = self.transmit(&request).await?;
+        if response.status != 401 {
+            return Ok((response, elapsed));
+        }
+
+        match auth.refresh(&value).await? {
+            Some(value) => {
+                info!(
+                    "Retrying {} {} with refreshed authorization",
+                    request.method, request.path
+                );
+
+                request.headers.insert("authorization".to_string(), value);
+                let (response, retried) = self.transmit(&request).await?;
+                Ok((response, elapsed + retried))
+            }
+            None => Ok((response, elapsed)),
+        }
+    }
+
+    /// Sends a request to the given path, relative to the base url,
+    /// with a generated request id, running any hooks around it.
+    async fn send(&self, method: Method, path: &str, executor: Option<Executor>) -> PyResult<Sent> {
+        if let Some(rate_limit) = &self.rate_limit {
+            rate_limit.acquire().await;
+        }
+
+        let request_id = uuid::Uuid::new_v4().to_string();
+        let mut headers = self.headers.clone();
+        headers.insert(REQUEST_ID_HEADER.to_string(), request_id.clone());
+
+        if self.hooks.is_empty() {
+            let executor = executor.map(|e| e.convert());
+            let request = TransportRequest {
+                method,
+                path: path.to_string(),
+                headers,
+                body: executor.as_ref().map(|e| serde_json::to_string(e).unwrap()),
+                compression: self.compression,
+                max_response_bytes: self.max_response_bytes,
+            };
+
+            let (response, elapsed) = self.authorize(request).await?;
+
+            return Ok(Sent {
+                response,
+                executor,
+                request_id,
+                elapsed,
+            });
+        }
+
+        let hook_request = Python::with_gil(|py| {
+            let executor = executor.map(|e| Py::new(py, e)).transpose()?;
+            let request = HookRequest::new(
+                method.as_str(),
+                path,
+                &self.url,
+                headers,
+                self.sensitive.clone(),
+                executor,
+            );
+
+            Py::new(py, request)
+        })?;
+
+        hooks::before_request(&self.hooks, &hook_request).await?;
+
+        let (headers, executor) = Python::with_gil(|py| hook_request.borrow(py).prepare(py))?;
+        // Hooks may have replaced the id.
+        let request_id = headers
+            .get(REQUEST_ID_HEADER)
+            .cloned()
+            .unwrap_or(request_id);
+
+        let request = TransportRequest {
+            method,
+            path: path.to_string(),
+            headers,
+            body: executor.as_ref().map(|e| serde_json::to_string(e).unwrap()),
+            compression: self.compression,
+            max_response_bytes: self.max_response_bytes,
+        };
+
+        match self.authorize(request).await {
+            Ok((response, elapsed)) => {
+                let view = HookResponse::new(response.status, response.body.clone(), elapsed);
+
+                hooks::after_response(&self.hooks, &hook_request, view).await?;
+                Ok(Sent {
+                    response,
+                    executor,
+                    request_id,
+                    elapsed,
+                })
+            }
+            Err(error) => {
+                hooks::on_error(&self.hooks, &hook_request, &error).await?;
+                Err(error)
+            }
+        }
+    }
+
+    async fn fetch_runtimes(&self) -> PyResult<Vec<Runtime_>> {
+        let Sent { response, .. } = self.send(Method::GET, "/runtimes", None).await?;
+
+        if response.status != 200 {
+            let message = serde_json::from_str::<serde_json::Value>(&response.body)
+                .ok()
+                .and_then(|body| body.get("message")?.as_str().map(str::to_string))
+                .unwrap_or(response.body);
+
+            error!(
+                "Unable to fetch runtimes, Piston responded with {}: {}",
+                response.status, message
+            );
+            return Err(PyRuntimeError::new_err(format!(
+                "{}: {}",
+                status_reason(response.status),
+                message
+            )));
+        }
+
+        let runtimes: Vec<Runtime_> = serde_json::from_str(&response.body).map_err(|e| {
+            error!("Unable to parse runtimes: {}", e);
+            PyRuntimeError::new_err(format!("{:?}", e))
+        })?;
+
+        debug!("Parsed {} runtimes", runtimes.len());
+        Ok(runtimes)
+    }
+
+    async fn execute(&self, executor: Executor) -> PyResult<ExecResponse> {
+        let language = executor.language();
+        let sent = match self.send(Method::POST, "/execute", Some(executor)).await {
+            Ok(sent) => sent,
+            Err(e) => {
+                self.metrics.record_execution(&language, Outcome::Error);
+                return Err(e);
+            }
+        };
+
+        let Sent {
+            response,
+            executor,
+            request_id,
+            elapsed,
+        } = sent;
+
+        let executor = executor.unwrap_or_default();
+        let backend = response.backend.unwrap_or_else(|| self.url.clone());
+        let status = response.status;
+        let body = response.body;
+        let mut exchange = Exchange {
+            raw_json: None,
+            headers: response.headers,
+            request_id,
+            elapsed,
+        };
+
+        if status == 200 {
+            let parsed = match response.truncated {
+                true => {
+                    parse_truncated(&body).map(|(raw, run, compile)| (raw, Some((run, compile))))
+                }
+                false => serde_json::from_str(&body).map(|raw| (raw, None)),
+            };
+
+            let (raw, truncated) = parsed.map_err(|e| {
+                error!("Unable to parse execute response: {}", e);
+                self.metrics
+                    .record_execution(&executor.language, Outcome::Error);
+                PyRuntimeError::new_err(format!("{:?}", e))
+            })?;
+
+            if response.truncated {
+                warn!("Execute response was truncated to {} bytes", body.len());
+            }
+
+            let outcome = match (raw.run.code, &raw.run.signal) {
+                (Some(0), None) => Outcome::Success,
+                _ => Outcome::Failure,
+            };
+
+            // Aliases are counted under the language Piston resolved
+            // them to.
+            let language = match raw.language.is_empty() {
+                true => &executor.language,
+                false => &raw.language,
+            };
+            self.metrics.record_execution(language, outcome);
+
+            debug!(
+                "Executed {} {}, run exited with code {:?}",
+                raw.language, raw.version, raw.run.code
+            );
+
+            let response = ExecResponse_ {
+                language: raw.language,
+                version: raw.version,
+                run: raw.run,
+                compile: raw.compile,
+                status,
+            };
+
+            exchange.raw_json = self.raw_responses.then_some(body);
+            let response = ExecResponse::from_response(response)
+                .with_backend(backend)
+                .with_exchange(exchange);
+
+            return Ok(match truncated {
+                Some((run, compile)) => response.with_truncated(run, compile),
+                None => response,
+            });
+        }
+
+        let text = format!("{}: {}", status_reason(status), body);
+        self.metrics
+            .record_execution(&executor.language, Outcome::Rejected);
+
+        let response = ExecResponse_ {
+            language: executor.language,
+            version: executor.version,
+            run: ExecResult_ {
+                stdout: String::new(),
+                stderr: text.clone(),
+                output: text,
+                code: Some(1),
+                signal: None,
+            },
+            compile: None,
+            status,
+        };
+
+        exchange.raw_json = self.raw_responses.then_some(body);
+        Ok(ExecResponse::from_response(response)
+            .with_backend(backend)
+            .with_exchange(exchange))
+    }
+}
+
+/// Describes an http status, along with its reason if it is known.
+fn status_reason(status: u16) -> String {
+    match StatusCode::from_u16(status) {
+        Ok(status) => status.to_string(),
+        Err(_) => status.to_string(),
+    }
+}
+
 /// A client used to send requests to Piston.
 ///
 /// **NOTE**:
@@ -17,30 +619,182 @@ use super::Runtime;
 #[derive(Clone)]
 #[pyo3(text_signature = "() -> Client")]
 pub struct Client {
-    inner: Client_,
-    headers: HashMap<String, String>,
+    inner: Connection,
+}
+
+impl Client {
+    /// Creates a new client with the given url and optional api key.
+    fn create(url: &str, key: Option<&str>) -> PyResult<Self> {
+        let options = HttpOptions::default();
+
+        Ok(Self {
+            inner: Connection {
+                transport: Arc::new(ReqwestTransport::new(url, options.build()?, &options.pool)),
+                url: url.to_string(),
+                headers: generate_headers(key),
+                cassette: None,
+                balancer: None,
+                custom_transport: false,
+                options,
+                auth: None,
+                sensitive: HashSet::new(),
+                hooks: Arc::new(Vec::new()),
+                metrics: Arc::new(Metrics::default()),
+                lifecycle: Arc::new(Lifecycle::default()),
+                grace_period: DEFAULT_GRACE_PERIOD,
+                timeout: None,
+                compression: Compression::default(),
+                max_response_bytes: None,
+                truncate: true,
+                raw_responses: false,
+                rate_limit: None,
+            },
+        })
+    }
+}
+
+impl Client {
+    /// Creates a new client from settings, naming their source in
+    /// any `ValueError` raised.
+    fn from_settings(settings: Settings, source: &str) -> PyResult<Self> {
+        Self::apply_settings(settings).map_err(|e| {
+            Python::with_gil(|py| match e.is_instance_of::<PyValueError>(py) {
+                true => PyValueError::new_err(format!("Invalid {}: {}", source, e.value(py))),
+                false => e,
+            })
+        })
+    }
+
+    fn apply_settings(settings: Settings) -> PyResult<Self> {
+        let key = settings.key()?;
+
+        let balancing = settings.strategy.is_some()
+            || settings.max_failures.is_some()
+            || settings.cooldown.is_some();
+
+        if balancing && settings.urls.is_none() {
+            return Err(PyValueError::new_err(
+                "strategy, max_failures and cooldown require urls",
+            ));
+        }
+
+        let mut client = match (settings.url, settings.urls, settings.unix_socket) {
+            (None, None, None) => Self::create(DEFAULT_URL, key.as_deref())?,
+            (Some(url), None, None) => Self::create(&url, key.as_deref())?,
+            (None, Some(urls), None) => Self::with_urls(
+                urls,
+                settings
+                    .strategy
+                    .unwrap_or_else(|| "round_robin".to_string()),
+                key,
+                settings.max_failures.unwrap_or(3),
+                settings.cooldown.unwrap_or(30.0),
+            )?,
+            (None, None, Some(path)) => Self::with_unix_socket(path, "/api/v2".to_string(), key)?,
+            _ => {
+                return Err(PyValueError::new_err(
+                    "Only one of url, urls and unix_socket can be set",
+                ))
+            }
+        };
+
+        let proxy_settings = settings.proxy_username.is_some()
+            || settings.proxy_password.is_some()
+            || settings.no_proxy.is_some();
+
+        match settings.proxy {
+            Some(proxy) => {
+                client = client.with_proxy(
+                    proxy,
+                    "all".to_string(),
+                    settings.proxy_username,
+                    settings.proxy_password,
+                    settings.no_proxy,
+                )?
+            }
+            None if proxy_settings => {
+                return Err(PyValueError::new_err(
+                    "proxy_username, proxy_password and no_proxy require proxy",
+                ))
+            }
+            None => (),
+        }
+
+        if settings.env_proxy == Some(true) {
+            client = client.with_env_proxy()?;
+        }
+
+        let tls = TlsOptions {
+            ca: settings
+                .ca
+                .map(|path| http::read_pem_file(&path, "CA bundle"))
+                .transpose()?,
+            identity: match (settings.client_cert, settings.client_key) {
+                (Some(cert), key) => {
+                    let mut identity = http::read_pem_file(&cert, "client certificate")?;
+                    if let Some(key) = key {
+                        identity.push(b'\n');
+                        identity.extend(http::read_pem_file(&key, "client key")?);
+                    }
+
+                    Some(identity)
+                }
+                (None, Some(_)) => {
+                    return Err(PyValueError::new_err("client_key requires client_cert"))
+                }
+                (None, None) => None,
+            },
+            min_version: settings
+                .tls_min_version
+                .as_deref()
+                .map(http::parse_tls_version)
+                .transpose()?,
+            accept_invalid_certs: false,
+        };
+
+        let mut options = client.inner.options.clone();
+        options.tls = tls;
+        client.inner = client.inner.with_options(options)?;
+
+        client = client.with_timeout(settings.timeout)?;
+        if let Some(max_response_bytes) = settings.max_response_bytes {
+            client = client.with_max_response_bytes(Some(max_response_bytes), true)?;
+        }
+
+        if let Some(grace_period) = settings.grace_period {
+            client = client.with_grace_period(grace_period)?;
+        }
+
+        match (settings.rate_limit, settings.rate_limit_period) {
+            (Some(max_requests), period) => {
+                client = client.with_rate_limit(Some(max_requests), period.unwrap_or(1.0))?
+            }
+            (None, Some(_)) => {
+                return Err(PyValueError::new_err(
+                    "rate_limit_period requires rate_limit",
+                ))
+            }
+            (None, None) => (),
+        }
+
+        Ok(client)
+    }
 }
 
 #[pymethods]
 impl Client {
     /// Creates a new client.
     #[new]
-    fn new() -> Self {
-        let inner = Client_::new();
-        let headers = inner
-            .get_headers()
-            .iter()
-            .map(|(key, value)| (key.to_string(), value.to_str().unwrap().to_string()))
-            .collect();
-
-        Self { inner, headers }
+    fn new() -> PyResult<Self> {
+        Self::create(DEFAULT_URL, None)
     }
 
     fn __repr__(&self) -> PyResult<String> {
         Ok(format!(
-            "Client {{ url: \"{}\", headers: {:?} }}",
+            "Client {{ url: \"{}\", headers: {:?}, transport: \"{}\" }}",
             self.url(),
-            self.headers,
+            self.headers(),
+            self.transport(),
         ))
     }
 
@@ -63,15 +817,8 @@ impl Client {
     /// - `Client`: The new client.
     #[staticmethod]
     #[pyo3(text_signature = "(key: str, /) -> Client")]
-    fn with_url_and_key(url: String, key: String) -> Self {
-        let inner = Client_::with_url_and_key(&url, &key);
-        let headers = inner
-            .get_headers()
-            .iter()
-            .map(|(key, value)| (key.to_string(), value.to_str().unwrap().to_string()))
-            .collect();
-
-        Self { inner, headers }
+    fn with_url_and_key(url: String, key: String) -> PyResult<Self> {
+        Self::create(&url, Some(&key))
     }
 
     /// Creates a new Client with a custom url.
@@ -86,15 +833,8 @@ impl Client {
     /// - `Client`: The new client.
     #[staticmethod]
     #[pyo3(text_signature = "(key: str, /) -> Client")]
-    fn with_url(url: String) -> Self {
-        let inner = Client_::with_url(&url);
-        let headers = inner
-            .get_headers()
-            .iter()
-            .map(|(key, value)| (key.to_string(), value.to_str().unwrap().to_string()))
-            .collect();
-
-        Self { inner, headers }
+    fn with_url(url: String) -> PyResult<Self> {
+        Self::create(&url, None)
     }
 
     /// Creates a new client, with an api key.
@@ -109,87 +849,1323 @@ impl Client {
     /// - `Client`: The new client.
     #[staticmethod]
     #[pyo3(text_signature = "(key: str, /) -> Client")]
-    fn with_key(key: String) -> Self {
-        let inner = Client_::with_key(&key);
-        let headers = inner
-            .get_headers()
-            .iter()
-            .map(|(key, value)| (key.to_string(), value.to_str().unwrap().to_string()))
-            .collect();
+    fn with_key(key: String) -> PyResult<Self> {
+        Self::create(DEFAULT_URL, Some(&key))
+    }
+
+    /// Creates a new client that spreads requests over several Piston
+    /// instances.
+    ///
+    /// - `round_robin` cycles through the backends in turn.
+    /// - `least_inflight` uses the backend with the fewest requests
+    /// in-flight.
+    /// - `failover` uses the first backend, moving down the list only
+    /// when it is unhealthy.
+    ///
+    /// Backends are ejected from rotation for the cooldown after
+    /// failing `max_failures` requests in a row, where connection
+    /// errors and 502, 503 and 504 statuses count as failures. Requests
+    /// failing with a connection error are retried on the next backend.
+    /// Other failures are not retried, as the backend may already have
+    /// received the request. The backend that served each execution is available as
+    /// `ExecResponse.backend`.
+    ///
+    /// ### Args:
+    ///
+    /// - urls `list[str]`:
+    /// The urls of the Piston backends.
+    ///
+    /// - strategy `str`:
+    /// One of `round_robin`, `least_inflight` or `failover`. Defaults
+    /// to `round_robin`.
+    ///
+    /// - key `str | None`:
+    /// The api key to use. Defaults to `None`.
+    ///
+    /// - max_failures `int`:
+    /// The number of consecutive failures before a backend is ejected.
+    /// Defaults to `3`.
+    ///
+    /// - cooldown `float`:
+    /// The number of seconds an ejected backend is out of rotation.
+    /// Defaults to `30.0`.
+    ///
+    /// ### Returns:
+    ///
+    /// - `Client`: The new client.
+    ///
+    /// ### Raises:
+    ///
+    /// - `ValueError`: If no urls are given, or the strategy is
+    /// unsupported.
+    #[staticmethod]
+    #[pyo3(
+        signature = (urls, strategy = "round_robin".to_string(), key = None, max_failures = 3, cooldown = 30.0),
+        text_signature = "(urls: list[str], /, strategy: str = \"round_robin\", key: str | None = None, max_failures: int = 3, cooldown: float = 30.0) -> Client"
+    )]
+    fn with_urls(
+        urls: Vec<String>,
+        strategy: String,
+        key: Option<String>,
+        max_failures: u32,
+        cooldown: f64,
+    ) -> PyResult<Self> {
+        let cooldown = Duration::try_from_secs_f64(cooldown)
+            .map_err(|_| PyValueError::new_err("The cooldown must be a non-negative number"))?;
+
+        let strategy = Strategy::parse(&strategy)?;
+        let options = HttpOptions::default();
+        let balancer = Arc::new(BalancedTransport::new(
+            &urls,
+            &options.build()?,
+            &options.pool,
+            strategy,
+            max_failures,
+            cooldown,
+        )?);
+
+        let mut client = Self::create(&urls[0], key.as_deref())?;
+        client.inner.balancer = Some(balancer.clone());
+        client.inner.transport = balancer;
 
-        Self { inner, headers }
+        Ok(client)
     }
 
-    /// `str`: The base url for the Piston v2 api.
-    #[getter]
-    fn url(&self) -> String {
-        self.inner.get_url()
+    /// Creates a new client that connects to a Piston instance through
+    /// a Unix domain socket, rather than over tcp.
+    ///
+    /// Proxy and TLS settings do not apply to the socket.
+    ///
+    /// ### Args:
+    ///
+    /// - path `str`:
+    /// The path of the socket Piston is listening on.
+    ///
+    /// - prefix `str`:
+    /// The path of the Piston v2 api on the server. Defaults to
+    /// `/api/v2`.
+    ///
+    /// - key `str | None`:
+    /// The api key to use. Defaults to `None`.
+    ///
+    /// ### Returns:
+    ///
+    /// - `Client`: The new client.
+    #[staticmethod]
+    #[pyo3(
+        signature = (path, prefix = "/api/v2".to_string(), key = None),
+        text_signature = "(path: str, /, prefix: str = \"/api/v2\", key: str | None = None) -> Client"
+    )]
+    fn with_unix_socket(path: PathBuf, prefix: String, key: Option<String>) -> PyResult<Self> {
+        let url = format!("unix://{}{}", path.display(), prefix.trim_end_matches('/'));
+
+        let mut client = Self::create(&url, key.as_deref())?;
+        client.inner.transport = Arc::new(UnixTransport::new(path, &prefix));
+        client.inner.custom_transport = true;
+
+        Ok(client)
     }
 
-    /// `dict[str, str]`: The headers being sent with requests.
-    #[getter]
-    fn headers(&self) -> HashMap<String, String> {
-        self.headers.clone()
+    /// Creates a new client from settings read from environment
+    /// variables. Each is named after the setting in upper case, such
+    /// as `PISTON_URL` or `PISTON_TIMEOUT`, and unset or empty
+    /// variables are ignored.
+    ///
+    /// - `URL`, `URLS` (comma separated) or `UNIX_SOCKET`: Where Piston
+    /// is, at most one of them. Defaults to the public api.
+    /// - `KEY`: The api key.
+    /// - `STRATEGY`, `MAX_FAILURES` and `COOLDOWN`: Balancing over
+    /// `URLS`, see `Client.with_urls`.
+    /// - `TIMEOUT` and `GRACE_PERIOD`: In seconds, see
+    /// `Client.with_timeout` and `Client.with_grace_period`.
+    /// - `MAX_RESPONSE_BYTES`: See `Client.with_max_response_bytes`.
+    /// - `RATE_LIMIT` and `RATE_LIMIT_PERIOD`: The most requests, and
+    /// the period in seconds, see `Client.with_rate_limit`.
+    /// - `PROXY`, `PROXY_USERNAME`, `PROXY_PASSWORD` and `NO_PROXY`
+    /// (comma separated): See `Client.with_proxy`.
+    /// - `ENV_PROXY`: `true` to use `Client.with_env_proxy`.
+    /// - `CA`, `CLIENT_CERT`, `CLIENT_KEY` and `TLS_MIN_VERSION`: Paths
+    /// and the version, see `Client.with_tls`.
+    ///
+    /// ### Args:
+    ///
+    /// - prefix `str`:
+    /// The prefix of the variable names. Defaults to `PISTON_`.
+    ///
+    /// ### Returns:
+    ///
+    /// - `Client`: The new client.
+    ///
+    /// ### Raises:
+    ///
+    /// - `ValueError`: If a variable is malformed, or the settings
+    /// conflict.
+    #[staticmethod]
+    #[pyo3(
+        signature = (prefix = "PISTON_".to_string()),
+        text_signature = "(prefix: str = \"PISTON_\") -> Client"
+    )]
+    fn from_env(prefix: String) -> PyResult<Self> {
+        let settings = Settings::from_env(&prefix)?;
+        let source = format!("environment variables prefixed with {}", prefix);
+        Self::from_settings(settings, &source)
     }
 
-    /// **async**: Fetches the runtimes from Piston. This is an http request.
+    /// Creates a new client from a profile in a TOML config file.
+    ///
+    /// Profiles are tables under `profiles`, holding the settings
+    /// described in `Client.from_env` in lower case, where `urls` and
+    /// `no_proxy` are arrays. A profile may also set `key_env` to read
+    /// the api key from an environment variable. The top level `default`
+    /// names the profile used when none is given.
+    ///
+    /// ```toml
+    /// default = "public"
+    ///
+    /// [profiles.public]
+    /// url = "https://emkc.org/api/v2/piston"
+    ///
+    /// [profiles.prod]
+    /// urls = ["http://piston-1:2000/api/v2", "http://piston-2:2000/api/v2"]
+    /// key_env = "PISTON_PROD_KEY"
+    /// timeout = 30.0
+    /// rate_limit = 20
+    /// ```
+    ///
+    /// ### Args:
+    ///
+    /// - path `str`:
+    /// The path to the config file.
+    ///
+    /// - profile `str | None`:
+    /// The profile to use. Defaults to the `default` of the file, or
+    /// its only profile.
     ///
     /// ### Returns:
     ///
-    /// - `list[Runtime]`: The available Piston runtimes.
+    /// - `Client`: The new client.
     ///
     /// ### Raises:
     ///
-    /// - `RuntimeError`: If the request to Piston failed.
-    #[pyo3(text_signature = "(self) -> list[Runtime]")]
-    fn fetch_runtimes<'a>(&self, py: Python<'a>) -> PyResult<&'a PyAny> {
-        let client = self.inner.clone();
+    /// - `ValueError`: If the file could not be read or parsed, the
+    /// profile is missing, or its settings are malformed or conflict.
+    #[staticmethod]
+    #[pyo3(
+        signature = (path, profile = None),
+        text_signature = "(path: str, /, profile: str | None = None) -> Client"
+    )]
+    fn from_config(path: PathBuf, profile: Option<String>) -> PyResult<Self> {
+        let (settings, name) = Settings::from_file(&path, profile.as_deref())?;
+        let source = format!(
+            "profile {:?} in config {:?}",
+            name,
+            path.display().to_string()
+        );
+        Self::from_settings(settings, &source)
+    }
 
-        pyo3_asyncio::tokio::future_into_py_with_locals::<_, Vec<Runtime>>(
-            py,
-            pyo3_asyncio::tokio::get_current_locals(py)?,
-            async move {
-                match client.fetch_runtimes().await {
-                    Ok(runtimes) => Ok(Python::with_gil(|_| {
-                        runtimes
-                            .iter()
-                            .map(|r| Runtime::from_runtime(r.to_owned()))
-                            .collect()
-                    })),
-                    Err(e) => Err(Python::with_gil(|_| {
-                        pyo3::exceptions::PyRuntimeError::new_err(format!("{:?}", e))
-                    })),
-                }
-            },
-        )
+    /// Creates a copy of this client that records its requests to, or
+    /// replays them from, a JSON cassette file.
+    ///
+    /// Requests are matched on their method, path and JSON body, ignoring
+    /// key order and whitespace. The values of response headers that may
+    /// hold credentials, such as `Set-Cookie`, are masked when recorded.
+    ///
+    /// - `record` sends every request and records it, replacing any
+    /// existing cassette.
+    /// - `replay` never sends requests, and raises on any request that
+    /// is not in the cassette.
+    /// - `auto` replays recorded requests, and records new ones.
+    ///
+    /// ### Args:
+    ///
+    /// - path `str`:
+    /// The path to the cassette file.
+    ///
+    /// - mode `str`:
+    /// One of `record`, `replay` or `auto`. Defaults to `auto`.
+    ///
+    /// ### Returns:
+    ///
+    /// - `Client`: The new client.
+    ///
+    /// ### Raises:
+    ///
+    /// - `ValueError`: If the mode is unsupported, or the cassette is
+    /// missing in `replay` mode or malformed.
+    #[pyo3(
+        signature = (path, mode = "auto".to_string()),
+        text_signature = "(self, path: str, /, mode: str = \"auto\") -> Client"
+    )]
+    fn with_cassette(&self, path: PathBuf, mode: String) -> PyResult<Self> {
+        let cassette = Arc::new(Cassette::open(path, CassetteMode::parse(&mode)?)?);
+        let transport = Arc::new(CassetteTransport::new(
+            cassette,
+            self.inner.transport.clone(),
+        ));
+
+        let mut client = self.clone();
+        client.inner.cassette = Some(transport.clone());
+        client.inner.transport = transport;
+
+        Ok(client)
     }
 
-    /// **async**: Executes code using a given executor. This is an http request.
+    /// Creates a copy of this client that sends its requests through
+    /// a different transport, replacing any cassette in use.
+    ///
+    /// The transport can be a `FakeTransport`, or any object with an
+    /// `async send(method, path, body)` method returning a
+    /// `(status, body)` tuple. `path` is relative to the base url, and
+    /// `body` is `None` for requests without one.
     ///
     /// ### Args:
     ///
-    /// - executor `Executor`:
-    /// The executor to use for the request.
+    /// - transport `FakeTransport | Transport`:
+    /// The transport to use.
     ///
     /// ### Returns:
     ///
-    /// - `ExecResponse`: The response from Piston.
+    /// - `Client`: The new client.
     ///
     /// ### Raises:
     ///
-    /// - `RuntimeError`: If the request to Piston failed.
-    #[pyo3(text_signature = "(self, executor: Executor, /) -> ExecResponse")]
-    fn execute<'a>(&self, py: Python<'a>, executor: &Executor) -> PyResult<&'a PyAny> {
-        let client = self.inner.clone();
-        let exec = executor.convert();
+    /// - `TypeError`: If the transport has no `send` method.
+    #[pyo3(text_signature = "(self, transport: FakeTransport | Transport, /) -> Client")]
+    fn with_transport(&self, transport: &PyAny) -> PyResult<Self> {
+        let transport: Arc<dyn Transport> = match transport.extract::<FakeTransport>() {
+            Ok(fake) => Arc::new(fake),
+            Err(_) => Arc::new(PyTransport::new(transport)?),
+        };
 
-        pyo3_asyncio::tokio::future_into_py(py, async move {
-            match client.execute(&exec).await {
-                Ok(response) => Ok(Python::with_gil(|_| ExecResponse::from_response(response))),
-                Err(e) => Err(Python::with_gil(|_| {
-                    pyo3::exceptions::PyRuntimeError::new_err(format!("{:?}", e))
-                })),
-            }
-        })
+        let mut client = self.clone();
+        client.inner.cassette = None;
+        client.inner.balancer = None;
+        client.inner.custom_transport = true;
+        client.inner.transport = transport;
+
+        Ok(client)
+    }
+
+    /// Creates a copy of this client that sends its requests through a
+    /// proxy. Proxies added earlier take precedence, so this can be
+    /// called again to use a different proxy for `http` and `https`
+    /// urls.
+    ///
+    /// The proxy url can use the `http`, `https`, `socks5` or `socks5h`
+    /// scheme, where `socks5h` resolves hostnames through the proxy.
+    /// Credentials can also be given in the url itself.
+    ///
+    /// Once a proxy is added, the proxy environment variables are
+    /// ignored unless `Client.with_env_proxy` is also used. Proxies do
+    /// not apply to transports set with `Client.with_transport`.
+    ///
+    /// ### Args:
+    ///
+    /// - url `str`:
+    /// The url of the proxy.
+    ///
+    /// - scheme `str`:
+    /// Which requests to proxy, one of `all`, `http` or `https`, by
+    /// the scheme of the Piston url. Defaults to `all`.
+    ///
+    /// - username `str | None`:
+    /// The username to authenticate with. Defaults to `None`.
+    ///
+    /// - password `str | None`:
+    /// The password to authenticate with. Defaults to `None`.
+    ///
+    /// - no_proxy `list[str] | None`:
+    /// Hosts, domains or ip ranges such as `10.0.0.0/8` to connect to
+    /// directly. Defaults to `None`.
+    ///
+    /// ### Returns:
+    ///
+    /// - `Client`: The new client.
+    ///
+    /// ### Raises:
+    ///
+    /// - `ValueError`: If the url or scheme is unsupported, or only one
+    /// of username and password is given.
+    #[pyo3(
+        signature = (url, scheme = "all".to_string(), username = None, password = None, no_proxy = None),
+        text_signature = "(self, url: str, /, scheme: str = \"all\", username: str | None = None, password: str | None = None, no_proxy: list[str] | None = None) -> Client"
+    )]
+    fn with_proxy(
+        &self,
+        url: String,
+        scheme: String,
+        username: Option<String>,
+        password: Option<String>,
+        no_proxy: Option<Vec<String>>,
+    ) -> PyResult<Self> {
+        let credentials = match (username, password) {
+            (Some(username), Some(password)) => Some((username, password)),
+            (None, None) => None,
+            _ => {
+                return Err(PyValueError::new_err(
+                    "A proxy username and password must be given together",
+                ))
+            }
+        };
+
+        let proxy = ProxyOptions {
+            url,
+            scheme: ProxyScheme::parse(&scheme)?,
+            credentials,
+            no_proxy: no_proxy.map(|hosts| hosts.join(",")),
+        };
+        proxy.check()?;
+
+        let mut options = self.inner.options.clone();
+        options.proxies.push(proxy);
+
+        Ok(Self {
+            inner: self.inner.with_options(options)?,
+        })
+    }
+
+    /// Creates a copy of this client that uses the proxies configured
+    /// by the `HTTPS_PROXY`, `HTTP_PROXY`, `ALL_PROXY` and `NO_PROXY`
+    /// environment variables, in upper or lower case. The variables are
+    /// read when this is called.
+    ///
+    /// Clients without proxies already use the environment, but once a
+    /// proxy is added with `Client.with_proxy` it is ignored unless
+    /// this is called. Proxies added with `Client.with_proxy` take
+    /// precedence over the environment. Values without a scheme, such as
+    /// `proxy.corp:8080`, are used as http proxies.
+    ///
+    /// ### Returns:
+    ///
+    /// - `Client`: The new client.
+    ///
+    /// ### Raises:
+    ///
+    /// - `ValueError`: If a variable holds an unsupported proxy url.
+    #[pyo3(text_signature = "(self) -> Client")]
+    fn with_env_proxy(&self) -> PyResult<Self> {
+        let mut options = self.inner.options.clone();
+        options.env_proxy = true;
+
+        Ok(Self {
+            inner: self.inner.with_options(options)?,
+        })
+    }
+
+    /// Creates a copy of this client that connects to Piston over TLS
+    /// with different settings, replacing any set previously.
+    ///
+    /// PEM data can be given as `bytes`, or as the path of a file. The
+    /// client certificate may include its private key, in which case
+    /// `key` can be omitted.
+    ///
+    /// TLS settings do not apply to transports set with
+    /// `Client.with_transport`.
+    ///
+    /// ### Args:
+    ///
+    /// - ca `str | bytes | None`:
+    /// Root certificates to trust, alongside the built-in roots.
+    /// Defaults to `None`.
+    ///
+    /// - cert `str | bytes | None`:
+    /// A client certificate chain to present. Defaults to `None`.
+    ///
+    /// - key `str | bytes | None`:
+    /// The private key of the client certificate. Defaults to `None`.
+    ///
+    /// - min_version `str | None`:
+    /// The minimum TLS version, `1.2` or `1.3`. Defaults to `None`.
+    ///
+    /// - danger_accept_invalid_certs `bool`:
+    /// Skips verifying the certificate of Piston entirely, leaving the
+    /// connection open to interception. Only for local development.
+    /// Defaults to `False`.
+    ///
+    /// ### Returns:
+    ///
+    /// - `Client`: The new client.
+    ///
+    /// ### Raises:
+    ///
+    /// - `ValueError`: If a file could not be read, the PEM data is
+    /// invalid, a key is given without a certificate, or the version
+    /// is unsupported.
+    #[pyo3(
+        signature = (ca = None, cert = None, key = None, min_version = None, danger_accept_invalid_certs = false),
+        text_signature = "(self, ca: str | bytes | None = None, cert: str | bytes | None = None, key: str | bytes | None = None, min_version: str | None = None, danger_accept_invalid_certs: bool = False) -> Client"
+    )]
+    fn with_tls(
+        &self,
+        ca: Option<&PyAny>,
+        cert: Option<&PyAny>,
+        key: Option<&PyAny>,
+        min_version: Option<&str>,
+        danger_accept_invalid_certs: bool,
+    ) -> PyResult<Self> {
+        let identity = match (cert, key) {
+            (Some(cert), key) => {
+                let mut identity = http::read_pem(cert, "client certificate")?;
+                if let Some(key) = key {
+                    identity.push(b'\n');
+                    identity.extend(http::read_pem(key, "client key")?);
+                }
+
+                Some(identity)
+            }
+            (None, Some(_)) => {
+                return Err(PyValueError::new_err(
+                    "A client key requires a client certificate",
+                ))
+            }
+            (None, None) => None,
+        };
+
+        let mut options = self.inner.options.clone();
+        options.tls = TlsOptions {
+            ca: ca.map(|ca| http::read_pem(ca, "CA bundle")).transpose()?,
+            identity,
+            min_version: min_version.map(http::parse_tls_version).transpose()?,
+            accept_invalid_certs: danger_accept_invalid_certs,
+        };
+
+        Ok(Self {
+            inner: self.inner.with_options(options)?,
+        })
+    }
+
+    /// Creates a copy of this client that pools and configures its
+    /// connections differently, replacing any settings given
+    /// previously. Defaults match those of a new client.
+    ///
+    /// Pool settings do not apply to Unix sockets, or transports set
+    /// with `Client.with_transport`.
+    ///
+    /// ### Args:
+    ///
+    /// - max_idle_per_host `int | None`:
+    /// The most idle connections kept open to each host, or `None` for
+    /// no limit. Defaults to `None`.
+    ///
+    /// - idle_timeout `float | None`:
+    /// The number of seconds an idle connection is kept open, or `None`
+    /// to keep it open indefinitely. Defaults to `90.0`.
+    ///
+    /// - tcp_keepalive `float | None`:
+    /// The interval in seconds of TCP keep-alive probes, or `None` to
+    /// disable them. Defaults to `None`.
+    ///
+    /// - tcp_nodelay `bool`:
+    /// Whether to disable Nagle's algorithm. Defaults to `True`.
+    ///
+    /// - http2_prior_knowledge `bool`:
+    /// Whether to speak HTTP/2 without negotiating it first, for
+    /// servers known to support it. Defaults to `False`.
+    ///
+    /// - max_connections_per_host `int | None`:
+    /// The most requests sent to each host at once, or `None` for no
+    /// limit. Further requests wait for one to finish, so over HTTP/1.1
+    /// this bounds the connections opened to each host. Defaults to
+    /// `None`.
+    ///
+    /// ### Returns:
+    ///
+    /// - `Client`: The new client.
+    ///
+    /// ### Raises:
+    ///
+    /// - `ValueError`: If a duration is not positive, or
+    /// `max_connections_per_host` is 0.
+    #[pyo3(
+        signature = (max_idle_per_host = None, idle_timeout = 90.0, tcp_keepalive = None, tcp_nodelay = true, http2_prior_knowledge = false, max_connections_per_host = None),
+        text_signature = "(self, max_idle_per_host: int | None = None, idle_timeout: float | None = 90.0, tcp_keepalive: float | None = None, tcp_nodelay: bool = True, http2_prior_knowledge: bool = False, max_connections_per_host: int | None = None) -> Client"
+    )]
+    fn with_pool(
+        &self,
+        max_idle_per_host: Option<usize>,
+        idle_timeout: Option<f64>,
+        tcp_keepalive: Option<f64>,
+        tcp_nodelay: bool,
+        http2_prior_knowledge: bool,
+        max_connections_per_host: Option<usize>,
+    ) -> PyResult<Self> {
+        if max_connections_per_host == Some(0) {
+            return Err(PyValueError::new_err(
+                "max_connections_per_host must be at least 1",
+            ));
+        }
+
+        let mut options = self.inner.options.clone();
+        options.pool = PoolOptions {
+            max_idle_per_host,
+            max_connections_per_host,
+            idle_timeout: idle_timeout.map(checked_timeout).transpose()?,
+            tcp_keepalive: tcp_keepalive.map(checked_timeout).transpose()?,
+            tcp_nodelay,
+            http2_prior_knowledge,
+        };
+
+        Ok(Self {
+            inner: self.inner.with_options(options)?,
+        })
+    }
+
+    /// Creates a copy of this client that sends a header with every
+    /// request, replacing any existing value.
+    ///
+    /// The values of sensitive headers are masked in the repr of the
+    /// client and `Client.headers`. `Authorization`, `Proxy-Authorization`,
+    /// `Cookie`, `X-Api-Key` and `X-Auth-Token` are always sensitive.
+    ///
+    /// ### Args:
+    ///
+    /// - name `str`:
+    /// The name of the header, which is case insensitive.
+    ///
+    /// - value `str`:
+    /// The value of the header.
+    ///
+    /// - sensitive `bool`:
+    /// Whether to mask the value. Defaults to `False`.
+    ///
+    /// ### Returns:
+    ///
+    /// - `Client`: The new client.
+    ///
+    /// ### Raises:
+    ///
+    /// - `ValueError`: If the name or value is not a valid header.
+    #[pyo3(
+        signature = (name, value, sensitive = false),
+        text_signature = "(self, name: str, value: str, /, sensitive: bool = False) -> Client"
+    )]
+    fn with_header(&self, name: &str, value: String, sensitive: bool) -> PyResult<Self> {
+        let name = HeaderName::from_bytes(name.as_bytes())
+            .map_err(|_| PyValueError::new_err(format!("Invalid header name {:?}", name)))?
+            .to_string();
+
+        HeaderValue::from_str(&value)
+            .map_err(|_| PyValueError::new_err(format!("Invalid value for header {:?}", name)))?;
+
+        let mut client = self.clone();
+        if sensitive {
+            client.inner.sensitive.insert(name.clone());
+        }
+
+        client.inner.headers.insert(name, value);
+        Ok(client)
+    }
+
+    /// Creates a copy of this client that gets the `Authorization` value
+    /// for each request from an auth provider, replacing any api key.
+    ///
+    /// - A list of keys is used round-robin. A request receiving a 401
+    /// is retried once with the next key.
+    /// - A callable, regular or async, is called without arguments and
+    /// returns the value. It is called for the first request, then
+    /// again whenever a request using the value receives a 401, which
+    /// is retried once with the new value.
+    ///
+    /// The value is not exposed through `Client.headers`, hooks or the
+    /// repr of the client.
+    ///
+    /// ### Args:
+    ///
+    /// - provider `list[str] | Callable[[], str | Awaitable[str]]`:
+    /// The keys, or the callable.
+    ///
+    /// ### Returns:
+    ///
+    /// - `Client`: The new client.
+    ///
+    /// ### Raises:
+    ///
+    /// - `ValueError`: If the list of keys is empty.
+    /// - `TypeError`: If the provider is neither a list nor callable.
+    #[pyo3(
+        text_signature = "(self, provider: list[str] | Callable[[], str | Awaitable[str]], /) -> Client"
+    )]
+    fn with_auth(&self, provider: &PyAny) -> PyResult<Self> {
+        let mut client = self.clone();
+        client.inner.auth = Some(Arc::new(AuthProvider::new(provider)?));
+        client.inner.headers.remove("authorization");
+        Ok(client)
+    }
+
+    /// Creates a copy of this client that calls the given hooks around
+    /// every request it sends. Hooks run in the order they were added,
+    /// and each may be a regular or an async callable.
+    ///
+    /// - `before_request(request)` receives a `HookRequest` before it is
+    /// sent. It may change the request's headers or executor, or return
+    /// a new `Executor` to send instead.
+    /// - `after_response(request, response)` receives the request and a
+    /// `HookResponse` once a response has been received.
+    /// - `on_error(request, error)` receives the request and the
+    /// exception if the request failed, which is raised afterwards.
+    ///
+    /// An exception raised by a hook is raised by the request.
+    ///
+    /// ### Args:
+    ///
+    /// - before_request `Callable[[HookRequest], Executor | None] | None`:
+    /// Called before each request is sent.
+    ///
+    /// - after_response `Callable[[HookRequest, HookResponse], None] | None`:
+    /// Called after each response is received.
+    ///
+    /// - on_error `Callable[[HookRequest, Exception], None] | None`:
+    /// Called when a request fails.
+    ///
+    /// ### Returns:
+    ///
+    /// - `Client`: The new client.
+    ///
+    /// ### Raises:
+    ///
+    /// - `TypeError`: If a hook is not callable.
+    #[pyo3(
+        signature = (before_request = None, after_response = None, on_error = None),
+        text_signature = "(self, before_request: Callable | None = None, after_response: Callable | None = None, on_error: Callable | None = None) -> Client"
+    )]
+    fn add_hook(
+        &self,
+        before_request: Option<&PyAny>,
+        after_response: Option<&PyAny>,
+        on_error: Option<&PyAny>,
+    ) -> PyResult<Self> {
+        let callables = [
+            ("before_request", before_request),
+            ("after_response", after_response),
+            ("on_error", on_error),
+        ];
+
+        for (name, callable) in callables {
+            if callable.is_some_and(|c| !c.is_callable()) {
+                return Err(PyTypeError::new_err(format!("{} must be callable", name)));
+            }
+        }
+
+        let mut hooks = self.inner.hooks.as_ref().clone();
+        hooks.push(Hook {
+            before_request: before_request.map(|c| c.into()),
+            after_response: after_response.map(|c| c.into()),
+            on_error: on_error.map(|c| c.into()),
+        });
+
+        let mut client = self.clone();
+        client.inner.hooks = Arc::new(hooks);
+
+        Ok(client)
+    }
+
+    /// Creates a copy of this client that starts at most `max_requests`
+    /// requests in any `per` seconds, such as the 5 requests a second
+    /// allowed by the public api. Requests over the limit wait for
+    /// their turn, which is not included in the timeout.
+    ///
+    /// The limit is shared by copies made from the new client, but not
+    /// with this one.
+    ///
+    /// ### Args:
+    ///
+    /// - max_requests `int | None`:
+    /// The most requests to start in the period, or `None` for no
+    /// limit.
+    ///
+    /// - per `float`:
+    /// The length of the period in seconds. Defaults to `1.0`.
+    ///
+    /// ### Returns:
+    ///
+    /// - `Client`: The new client.
+    ///
+    /// ### Raises:
+    ///
+    /// - `ValueError`: If `max_requests` is 0, or the period is not
+    /// positive.
+    #[pyo3(
+        signature = (max_requests, per = 1.0),
+        text_signature = "(self, max_requests: int | None, /, per: float = 1.0) -> Client"
+    )]
+    fn with_rate_limit(&self, max_requests: Option<u32>, per: f64) -> PyResult<Self> {
+        if max_requests == Some(0) {
+            return Err(PyValueError::new_err("max_requests must be at least 1"));
+        }
+
+        if !(per.is_finite() && per > 0.0) {
+            return Err(PyValueError::new_err(format!(
+                "The rate limit period must be a positive number of seconds, got {}",
+                per
+            )));
+        }
+
+        let mut client = self.clone();
+        client.inner.rate_limit = max_requests.map(|max_requests| {
+            Arc::new(RateLimiter::new(max_requests, Duration::from_secs_f64(per)))
+        });
+
+        Ok(client)
+    }
+
+    /// Creates a copy of this client that gives in-flight requests a
+    /// different number of seconds to complete when it is closed.
+    ///
+    /// ### Args:
+    ///
+    /// - grace_period `float`:
+    /// The number of seconds to wait before aborting requests.
+    ///
+    /// ### Returns:
+    ///
+    /// - `Client`: The new client.
+    ///
+    /// ### Raises:
+    ///
+    /// - `ValueError`: If the grace period is negative.
+    #[pyo3(text_signature = "(self, grace_period: float, /) -> Client")]
+    fn with_grace_period(&self, grace_period: f64) -> PyResult<Self> {
+        let mut client = self.clone();
+        client.inner.grace_period = checked_grace_period(grace_period)?;
+        Ok(client)
+    }
+
+    /// Creates a copy of this client that gives up on requests that
+    /// take longer than the timeout, including any retries on other
+    /// backends. Hooks are not included in the time.
+    ///
+    /// ### Args:
+    ///
+    /// - timeout `float | None`:
+    /// The number of seconds to wait for each request, or `None` to
+    /// wait indefinitely.
+    ///
+    /// ### Returns:
+    ///
+    /// - `Client`: The new client.
+    ///
+    /// ### Raises:
+    ///
+    /// - `ValueError`: If the timeout is not positive.
+    #[pyo3(text_signature = "(self, timeout: float | None, /) -> Client")]
+    fn with_timeout(&self, timeout: Option<f64>) -> PyResult<Self> {
+        let mut client = self.clone();
+        client.inner.timeout = timeout.map(checked_timeout).transpose()?;
+        Ok(client)
+    }
+
+    /// Creates a copy of this client that compresses the executors it
+    /// sends, for Piston instances behind a proxy that accepts
+    /// compressed requests. Responses compressed with gzip or zstd are
+    /// always decoded, and those in other encodings are passed through
+    /// as is, with a warning.
+    ///
+    /// Compression does not apply to transports set with
+    /// `Client.with_transport`.
+    ///
+    /// ### Args:
+    ///
+    /// - encoding `str | None`:
+    /// How to compress executors, one of `"gzip"` or `"zstd"`, or
+    /// `None` to send them as is. Defaults to `"gzip"`.
+    ///
+    /// - min_size `int`:
+    /// The size in bytes executors must reach to be compressed.
+    /// Defaults to `1024`.
+    ///
+    /// - decompress `bool`:
+    /// Whether to ask for compressed responses. Defaults to `True`.
+    ///
+    /// ### Returns:
+    ///
+    /// - `Client`: The new client.
+    ///
+    /// ### Raises:
+    ///
+    /// - `ValueError`: If the encoding is not supported.
+    #[pyo3(
+        signature = (encoding = "gzip".to_string(), min_size = 1024, decompress = true),
+        text_signature = "(self, encoding: str | None = \"gzip\", min_size: int = 1024, decompress: bool = True) -> Client"
+    )]
+    fn with_compression(
+        &self,
+        encoding: Option<String>,
+        min_size: usize,
+        decompress: bool,
+    ) -> PyResult<Self> {
+        let mut client = self.clone();
+        client.inner.compression = Compression {
+            request: encoding.as_deref().map(Encoding::parse).transpose()?,
+            min_size,
+            decompress,
+        };
+
+        Ok(client)
+    }
+
+    /// Creates a copy of this client that stops reading responses once
+    /// they exceed a number of bytes, protecting memory from programs
+    /// that print without end.
+    ///
+    /// Execute responses are cut off at the limit, recovering what was
+    /// read and marking the results that were cut off with
+    /// `ExecResult.truncated`. Other responses over the limit fail.
+    ///
+    /// ### Args:
+    ///
+    /// - max_response_bytes `int | None`:
+    /// The most bytes of each response to read, after decompression,
+    /// or `None` for no limit.
+    ///
+    /// - truncate `bool`:
+    /// Whether to cut off execute responses over the limit, rather
+    /// than fail. Defaults to `True`.
+    ///
+    /// ### Returns:
+    ///
+    /// - `Client`: The new client.
+    ///
+    /// ### Raises:
+    ///
+    /// - `ValueError`: If the limit is zero.
+    #[pyo3(
+        signature = (max_response_bytes, truncate = true),
+        text_signature = "(self, max_response_bytes: int | None, truncate: bool = True) -> Client"
+    )]
+    fn with_max_response_bytes(
+        &self,
+        max_response_bytes: Option<usize>,
+        truncate: bool,
+    ) -> PyResult<Self> {
+        if max_response_bytes == Some(0) {
+            return Err(PyValueError::new_err(
+                "max_response_bytes must be a positive number of bytes",
+            ));
+        }
+
+        let mut client = self.clone();
+        client.inner.max_response_bytes = max_response_bytes;
+        client.inner.truncate = truncate;
+        Ok(client)
+    }
+
+    /// Creates a copy of this client that keeps the body of each
+    /// execute response in `ExecResponse.raw_json`. Bodies are dropped
+    /// once parsed otherwise, as they repeat the output of the program.
+    ///
+    /// ### Args:
+    ///
+    /// - enabled `bool`:
+    /// Whether to keep the bodies. Defaults to `True`.
+    ///
+    /// ### Returns:
+    ///
+    /// - `Client`: The new client.
+    #[pyo3(
+        signature = (enabled = true),
+        text_signature = "(self, enabled: bool = True) -> Client"
+    )]
+    fn with_raw_responses(&self, enabled: bool) -> Self {
+        let mut client = self.clone();
+        client.inner.raw_responses = enabled;
+        client
+    }
+
+    fn __aenter__<'a>(slf: &'a PyCell<Self>, py: Python<'a>) -> PyResult<&'a PyAny> {
+        slf.borrow().inner.lifecycle.check()?;
+        let client: Py<Self> = slf.into();
+
+        pyo3_asyncio::tokio::future_into_py(py, async move { Ok(client) })
+    }
+
+    fn __aexit__<'a>(
+        &self,
+        py: Python<'a>,
+        _exc_type: &PyAny,
+        _exc_value: &PyAny,
+        _traceback: &PyAny,
+    ) -> PyResult<&'a PyAny> {
+        self.aclose(py, None)
+    }
+
+    /// **async**: Closes the client, and every copy of it made with the
+    /// `with_*` and `add_hook` methods.
+    ///
+    /// New requests are rejected immediately, while in-flight requests
+    /// are given the grace period to complete before being aborted.
+    /// Pooled connections are then released. Closing a closed client
+    /// starts nothing new, but waits until the first close has finished
+    /// with its in-flight requests.
+    ///
+    /// The client can also be used as an async context manager, which
+    /// closes it on exit.
+    ///
+    /// ### Args:
+    ///
+    /// - grace_period `float | None`:
+    /// The number of seconds to wait before aborting requests. Defaults
+    /// to the grace period of the client, see `Client.with_grace_period`.
+    ///
+    /// ### Raises:
+    ///
+    /// - `ValueError`: If the grace period is negative.
+    #[pyo3(
+        signature = (grace_period = None),
+        text_signature = "(self, grace_period: float | None = None) -> None"
+    )]
+    fn aclose<'a>(&self, py: Python<'a>, grace_period: Option<f64>) -> PyResult<&'a PyAny> {
+        let grace_period = checked_grace_period(grace_period.unwrap_or(self.inner.grace_period))?;
+        let client = self.inner.clone();
+
+        pyo3_asyncio::tokio::future_into_py(py, async move {
+            client
+                .lifecycle
+                .close(Duration::from_secs_f64(grace_period))
+                .await;
+
+            client.transport.close();
+            Ok(())
+        })
+    }
+
+    /// `bool`: Whether the client has been closed.
+    #[getter]
+    fn closed(&self) -> bool {
+        self.inner.lifecycle.is_closed()
+    }
+
+    /// `float`: The number of seconds in-flight requests are given to
+    /// complete when the client is closed.
+    #[getter]
+    fn grace_period(&self) -> f64 {
+        self.inner.grace_period
+    }
+
+    /// `float | None`: The number of seconds to wait for each request,
+    /// or `None` to wait indefinitely.
+    #[getter]
+    fn timeout(&self) -> Option<f64> {
+        self.inner.timeout.map(|t| t.as_secs_f64())
+    }
+
+    /// `int | None`: The most bytes of each response read, or `None`
+    /// for no limit.
+    #[getter]
+    fn max_response_bytes(&self) -> Option<usize> {
+        self.inner.max_response_bytes
+    }
+
+    /// `tuple[int, float] | None`: The most requests started in a
+    /// period, and its length in seconds, or `None` for no limit.
+    #[getter]
+    fn rate_limit(&self) -> Option<(u32, f64)> {
+        self.inner
+            .rate_limit
+            .as_ref()
+            .map(|limit| (limit.max_requests, limit.period.as_secs_f64()))
+    }
+
+    /// `str`: The base url for the Piston v2 api.
+    #[getter]
+    fn url(&self) -> String {
+        self.inner.url.clone()
+    }
+
+    /// `dict[str, str]`: The headers being sent with requests, with the
+    /// values of sensitive headers masked. See `Client.reveal_headers`.
+    #[getter]
+    fn headers(&self) -> HashMap<String, String> {
+        self.inner.redacted_headers()
+    }
+
+    /// Gets the headers being sent with requests, without masking the
+    /// values of sensitive headers such as the api key.
+    ///
+    /// Values from an auth provider are never included, see
+    /// `Client.with_auth`.
+    ///
+    /// ### Returns:
+    ///
+    /// - `dict[str, str]`: The headers.
+    #[pyo3(text_signature = "(self) -> dict[str, str]")]
+    fn reveal_headers(&self) -> HashMap<String, String> {
+        self.inner.headers.clone()
+    }
+
+    /// `str | None`: A description of the auth provider in use, such as
+    /// `keys(2)` or `callable`, if any.
+    #[getter]
+    fn auth(&self) -> Option<String> {
+        self.inner.auth.as_ref().map(|auth| auth.name())
+    }
+
+    /// `tuple[str, str] | None`: The path and mode of the cassette in
+    /// use, if any.
+    #[getter]
+    fn cassette(&self) -> Option<(String, &'static str)> {
+        self.inner.cassette.as_ref().map(|t| {
+            let path = t.cassette.path().to_string_lossy().to_string();
+            (path, t.cassette.mode().name())
+        })
+    }
+
+    /// `list[BackendStatus] | None`: The health of each backend, for
+    /// clients created with `Client.with_urls`.
+    #[getter]
+    fn backends(&self) -> Option<Vec<BackendStatus>> {
+        self.inner.balancer.as_ref().map(|b| b.statuses())
+    }
+
+    /// `str`: The name of the transport requests are sent through.
+    #[getter]
+    fn transport(&self) -> String {
+        self.inner.transport.name()
+    }
+
+    /// Takes a snapshot of the requests sent by this client, and every
+    /// copy of it made with the `with_*` and `add_hook` methods.
+    ///
+    /// ### Returns:
+    ///
+    /// - `ClientStats`: The snapshot.
+    #[pyo3(text_signature = "(self) -> ClientStats")]
+    fn stats(&self) -> ClientStats {
+        self.inner.metrics.snapshot()
+    }
+
+    /// Takes a snapshot of the connections used by this client, and the
+    /// copies sharing its connection pool. Copies made with `with_proxy`,
+    /// `with_tls`, `with_pool` or `with_env_proxy` have their own pool.
+    ///
+    /// ### Returns:
+    ///
+    /// - `PoolStats | None`: The snapshot, or `None` if the client does
+    /// not connect over tcp.
+    #[pyo3(text_signature = "(self) -> PoolStats | None")]
+    fn pool_stats(&self) -> Option<PoolStats> {
+        self.inner.transport.pool_stats()
+    }
+
+    /// Renders the stats of this client in the Prometheus text
+    /// exposition format, for serving from a `/metrics` endpoint.
+    ///
+    /// ### Args:
+    ///
+    /// - prefix `str`:
+    /// The prefix of every metric name. Defaults to `piston_rspy`.
+    ///
+    /// ### Returns:
+    ///
+    /// - `str`: The rendered metrics.
+    #[pyo3(
+        signature = (prefix = "piston_rspy".to_string()),
+        text_signature = "(self, prefix: str = \"piston_rspy\") -> str"
+    )]
+    fn export_prometheus(&self, prefix: String) -> String {
+        self.inner.metrics.prometheus(&prefix)
+    }
+
+    /// **async**: Fetches the runtimes from Piston. This is an http request.
+    ///
+    /// ### Returns:
+    ///
+    /// - `list[Runtime]`: The available Piston runtimes.
+    ///
+    /// ### Raises:
+    ///
+    /// - `RuntimeError`: If the request to Piston failed, or the client
+    /// is closed.
+    #[pyo3(text_signature = "(self) -> list[Runtime]")]
+    fn fetch_runtimes<'a>(&self, py: Python<'a>) -> PyResult<&'a PyAny> {
+        self.inner.lifecycle.check()?;
+        let client = self.inner.clone();
+
+        pyo3_asyncio::tokio::future_into_py_with_locals::<_, Vec<Runtime>>(
+            py,
+            pyo3_asyncio::tokio::get_current_locals(py)?,
+            async move {
+                let runtimes = client.lifecycle.run(client.fetch_runtimes()).await?;
+                Ok(runtimes.into_iter().map(Runtime::from_runtime).collect())
+            },
+        )
+    }
+
+    /// **async**: Probes Piston, checking that it is reachable and that
+    /// its runtimes can be parsed. These are http requests, which are
+    /// not counted in `Client.stats` and do not run hooks.
+    ///
+    /// Probes skip any cassette, and each backend of a client made with
+    /// `Client.with_urls` is probed on its own. Failing to reach Piston
+    /// does not raise, it is reported instead.
+    ///
+    /// ### Args:
+    ///
+    /// - timeout `float`:
+    /// The number of seconds to wait for each request. Defaults to `5.0`.
+    ///
+    /// ### Returns:
+    ///
+    /// - `HealthReport`: The health of the Piston instance.
+    ///
+    /// ### Raises:
+    ///
+    /// - `ValueError`: If the timeout is not positive.
+    /// - `RuntimeError`: If the client is closed.
+    #[pyo3(
+        signature = (timeout = 5.0),
+        text_signature = "(self, timeout: float = 5.0) -> HealthReport"
+    )]
+    fn health<'a>(&self, py: Python<'a>, timeout: f64) -> PyResult<&'a PyAny> {
+        let timeout = checked_timeout(timeout)?;
+        self.inner.lifecycle.check()?;
+        let client = self.inner.clone();
+
+        pyo3_asyncio::tokio::future_into_py_with_locals::<_, HealthReport>(
+            py,
+            pyo3_asyncio::tokio::get_current_locals(py)?,
+            async move {
+                let mut headers = client.headers.clone();
+                if let Some(auth) = &client.auth {
+                    headers.insert("authorization".to_string(), auth.get().await?);
+                }
+
+                // Probes go straight to each instance, rather than being
+                // replayed from a cassette or spread by the balancer.
+                let base = match &client.cassette {
+                    Some(cassette) => cassette.inner().clone(),
+                    None => client.transport.clone(),
+                };
+
+                let probe = |transport, url| Probe {
+                    transport,
+                    url,
+                    headers: &headers,
+                    timeout,
+                };
+
+                client
+                    .lifecycle
+                    .run(async {
+                        Ok(match &client.balancer {
+                            Some(balancer) => {
+                                let probes: Vec<_> = balancer
+                                    .backends()
+                                    .map(|(url, transport)| probe(transport, url))
+                                    .collect();
+
+                                let reports = join_all(probes.iter().map(Probe::run)).await;
+                                HealthReport::balanced(reports)
+                            }
+                            None => probe(base.as_ref(), &client.url).run().await,
+                        })
+                    })
+                    .await
+            },
+        )
+    }
+
+    /// **async**: Executes code using a given executor. This is an http request.
+    ///
+    /// Cancelling the awaiting task aborts the request.
+    ///
+    /// ### Args:
+    ///
+    /// - executor `Executor`:
+    /// The executor to use for the request.
+    ///
+    /// - cancel_token `CancelToken | None`:
+    /// A token that aborts the request when cancelled. Defaults to `None`.
+    ///
+    /// ### Returns:
+    ///
+    /// - `ExecResponse`: The response from Piston.
+    ///
+    /// ### Raises:
+    ///
+    /// - `ValueError`: If the executor failed validation, see
+    /// `Executor.validate`.
+    /// - `RuntimeError`: If the request to Piston failed, or the client
+    /// is closed.
+    /// - `asyncio.CancelledError`: If the cancel token was cancelled.
+    #[pyo3(
+        signature = (executor, cancel_token = None),
+        text_signature = "(self, executor: Executor, /, cancel_token: CancelToken | None = None) -> ExecResponse"
+    )]
+    fn execute<'a>(
+        &self,
+        py: Python<'a>,
+        executor: &Executor,
+        cancel_token: Option<CancelToken>,
+    ) -> PyResult<&'a PyAny> {
+        self.inner.lifecycle.check()?;
+        executor.check()?;
+
+        let client = self.inner.clone();
+        let exec = executor.clone();
+
+        pyo3_asyncio::tokio::future_into_py(py, async move {
+            let execute = client.lifecycle.run(client.execute(exec));
+            match cancel_token {
+                Some(token) => token.run(execute).await,
+                None => execute.await,
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client() -> Client {
+        Client::create(DEFAULT_URL, None).unwrap()
+    }
+
+    #[test]
+    fn requests_are_sent_over_http_by_default() {
+        assert_eq!(client().transport(), "reqwest");
+    }
+
+    #[test]
+    fn unix_sockets_use_the_unix_transport() {
+        let path = PathBuf::from("/tmp/piston.sock");
+        let client = Client::with_unix_socket(path, "/api/v2/".to_string(), None).unwrap();
+
+        assert_eq!(client.transport(), "unix");
+        assert_eq!(client.inner.url, "unix:///tmp/piston.sock/api/v2");
+    }
+
+    #[test]
+    fn fake_transports_are_used_directly() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let fake = Py::new(py, FakeTransport::default()).unwrap();
+            let client = client().with_transport(fake.as_ref(py)).unwrap();
+
+            assert_eq!(client.transport(), "fake");
+            assert!(client.inner.custom_transport);
+        });
+    }
+
+    #[test]
+    fn python_transports_need_a_send_method() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let module = PyModule::from_code(
+                py,
+                "class Echo:\n    async def send(self, method, path, body):\n        return 200, ''\n\nclass Silent:\n    send = None\n",
+                "transports.py",
+                "transports",
+            )
+            .unwrap();
+
+            let echo = module.getattr("Echo").unwrap().call0().unwrap();
+            assert_eq!(client().with_transport(echo).unwrap().transport(), "Echo");
+
+            let silent = module.getattr("Silent").unwrap().call0().unwrap();
+            let err = client().with_transport(silent).err().unwrap();
+            assert!(err.is_instance_of::<PyTypeError>(py));
+        });
     }
 }
@@ -0,0 +1,297 @@
+use pyo3::exceptions::{PyRuntimeError, PyValueError};
+use pyo3::prelude::*;
+use std::io::{Read, Write};
+
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use log::warn;
+
+/// The value of `Accept-Encoding` sent when decompression is enabled.
+pub const ACCEPT_ENCODING: &str = "gzip, zstd";
+
+/// A content encoding supported for request and response bodies.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Encoding {
+    Gzip,
+    Zstd,
+}
+
+impl Encoding {
+    /// Parses the encoding from its Python name.
+    pub fn parse(encoding: &str) -> PyResult<Self> {
+        match encoding {
+            "gzip" => Ok(Self::Gzip),
+            "zstd" => Ok(Self::Zstd),
+            _ => Err(PyValueError::new_err(format!(
+                "Unsupported encoding {:?}, expected one of [\"gzip\", \"zstd\"]",
+                encoding
+            ))),
+        }
+    }
+
+    /// Parses the encoding from a `Content-Encoding` token.
+    fn from_header(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "gzip" | "x-gzip" => Some(Self::Gzip),
+            "zstd" => Some(Self::Zstd),
+            _ => None,
+        }
+    }
+
+    /// The name of the encoding, as used in `Content-Encoding`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Gzip => "gzip",
+            Self::Zstd => "zstd",
+        }
+    }
+
+    fn encode(&self, data: &[u8]) -> std::io::Result<Vec<u8>> {
+        match self {
+            Self::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+            Self::Zstd => zstd::encode_all(data, zstd::DEFAULT_COMPRESSION_LEVEL),
+        }
+    }
+
+    /// Decodes data, reading at most one byte more than `limit`. Data
+    /// that was cut off is decoded as far as possible.
+    fn decode(&self, data: &[u8], limit: Option<usize>, partial: bool) -> std::io::Result<Vec<u8>> {
+        let decoder: Box<dyn Read + '_> = match self {
+            Self::Gzip => Box::new(GzDecoder::new(data)),
+            Self::Zstd => Box::new(zstd::stream::read::Decoder::new(data)?),
+        };
+
+        let mut decoded = Vec::new();
+        let limit = limit.map_or(u64::MAX, |limit| limit as u64 + 1);
+        match decoder.take(limit).read_to_end(&mut decoded) {
+            Ok(_) => Ok(decoded),
+            // What was read before the end of the data is kept.
+            Err(_) if partial => Ok(decoded),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// How a client compresses request bodies, and whether it asks for
+/// compressed responses.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Compression {
+    /// The encoding of request bodies, or `None` to send them as is.
+    pub request: Option<Encoding>,
+    /// The smallest body that is compressed, in bytes.
+    pub min_size: usize,
+    /// Whether to send `Accept-Encoding`.
+    pub decompress: bool,
+}
+
+impl Compression {
+    /// Encodes a request body on a blocking thread, returning it and the
+    /// encoding used, if it was compressed.
+    pub async fn encode(&self, body: &str) -> PyResult<(Vec<u8>, Option<Encoding>)> {
+        let encoding = match self.request {
+            Some(encoding) if body.len() >= self.min_size => encoding,
+            _ => return Ok((body.as_bytes().to_vec(), None)),
+        };
+
+        let data = body.as_bytes().to_vec();
+        let encoded = blocking(move || encoding.encode(&data))
+            .await?
+            .map_err(|e| {
+                PyRuntimeError::new_err(format!(
+                    "Unable to {} request body: {}",
+                    encoding.name(),
+                    e
+                ))
+            })?;
+
+        Ok((encoded, Some(encoding)))
+    }
+}
+
+/// Runs a compression task on a blocking thread, so that it does not
+/// hold up other requests.
+async fn blocking<T: Send + 'static>(task: impl FnOnce() -> T + Send + 'static) -> PyResult<T> {
+    tokio::task::spawn_blocking(task)
+        .await
+        .map_err(|e| PyRuntimeError::new_err(format!("Compression task failed: {}", e)))
+}
+
+/// The encodings listed in a `Content-Encoding`, in the order they
+/// must be undone. Returns `None` if any of them is not supported.
+fn parse_content_encoding(content_encoding: &str) -> Option<Vec<Encoding>> {
+    content_encoding
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty() && !name.eq_ignore_ascii_case("identity"))
+        .rev()
+        .map(Encoding::from_header)
+        .collect()
+}
+
+/// Decodes a response body according to its `Content-Encoding` on a
+/// blocking thread, stopping once it exceeds `limit` bytes. Responses
+/// are decoded whether or not they were asked for, and bodies with an
+/// unsupported encoding are passed through as is.
+///
+/// Returns the body, and whether it was cut off. A body that was
+/// already cut off is decoded as far as possible.
+pub async fn decode(
+    content_encoding: Option<&str>,
+    body: Vec<u8>,
+    limit: Option<usize>,
+    partial: bool,
+) -> PyResult<(Vec<u8>, bool)> {
+    let encodings = match content_encoding {
+        Some(content_encoding) => match parse_content_encoding(content_encoding) {
+            Some(encodings) => encodings,
+            None => {
+                warn!(
+                    "Unsupported response encoding {:?}, passing the body through undecoded",
+                    content_encoding
+                );
+                Vec::new()
+            }
+        },
+        None => Vec::new(),
+    };
+
+    let (mut decoded, partial) = match encodings.is_empty() {
+        true => (body, partial),
+        false => {
+            blocking(move || {
+                // A layer cut off at the limit leaves the next one
+                // partial too.
+                encodings
+                    .into_iter()
+                    .try_fold((body, partial), |(body, partial), encoding| {
+                        let decoded = encoding.decode(&body, limit, partial).map_err(|e| {
+                            PyRuntimeError::new_err(format!(
+                                "Unable to decode {} response: {}",
+                                encoding.name(),
+                                e
+                            ))
+                        })?;
+
+                        let cut = limit.is_some_and(|limit| decoded.len() > limit);
+                        Ok::<_, PyErr>((decoded, partial || cut))
+                    })
+            })
+            .await??
+        }
+    };
+
+    match limit {
+        Some(limit) if decoded.len() > limit => {
+            decoded.truncate(limit);
+            Ok((decoded, true))
+        }
+        _ => Ok((decoded, partial)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BODY: &str = "{\"language\":\"python\",\"version\":\"3.10.0\",\"files\":[]}";
+
+    fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    fn compression(encoding: Encoding) -> Compression {
+        Compression {
+            request: Some(encoding),
+            ..Compression::default()
+        }
+    }
+
+    #[test]
+    fn bodies_round_trip() {
+        for encoding in [Encoding::Gzip, Encoding::Zstd] {
+            let (encoded, used) = block_on(compression(encoding).encode(BODY)).unwrap();
+            assert_eq!(used, Some(encoding));
+            assert_ne!(encoded, BODY.as_bytes());
+
+            let (decoded, truncated) =
+                block_on(decode(Some(encoding.name()), encoded, None, false)).unwrap();
+            assert_eq!(decoded, BODY.as_bytes());
+            assert!(!truncated);
+        }
+    }
+
+    #[test]
+    fn small_bodies_are_sent_as_is() {
+        let compression = Compression {
+            min_size: BODY.len() + 1,
+            ..compression(Encoding::Gzip)
+        };
+
+        let (encoded, used) = block_on(compression.encode(BODY)).unwrap();
+        assert_eq!(used, None);
+        assert_eq!(encoded, BODY.as_bytes());
+    }
+
+    #[test]
+    fn stacked_encodings_are_undone_in_reverse() {
+        let gzip = Encoding::Gzip.encode(BODY.as_bytes()).unwrap();
+        let both = Encoding::Zstd.encode(&gzip).unwrap();
+
+        let (decoded, _) =
+            block_on(decode(Some("gzip, identity, zstd"), both, None, false)).unwrap();
+        assert_eq!(decoded, BODY.as_bytes());
+    }
+
+    #[test]
+    fn content_encodings_are_parsed() {
+        assert_eq!(parse_content_encoding("identity"), Some(vec![]));
+        assert_eq!(
+            parse_content_encoding("X-Gzip, ZSTD"),
+            Some(vec![Encoding::Zstd, Encoding::Gzip])
+        );
+        assert_eq!(parse_content_encoding("gzip, br"), None);
+    }
+
+    #[test]
+    fn unknown_encodings_are_passed_through() {
+        for content_encoding in ["br", "deflate", "gzip, br"] {
+            let body = b"not decoded".to_vec();
+            let (decoded, truncated) =
+                block_on(decode(Some(content_encoding), body.clone(), None, false)).unwrap();
+
+            assert_eq!(decoded, body);
+            assert!(!truncated);
+        }
+    }
+
+    #[test]
+    fn decoded_bodies_are_limited() {
+        let encoded = Encoding::Gzip.encode(BODY.as_bytes()).unwrap();
+        let (decoded, truncated) =
+            block_on(decode(Some("gzip"), encoded, Some(10), false)).unwrap();
+
+        assert_eq!(decoded, &BODY.as_bytes()[..10]);
+        assert!(truncated);
+    }
+
+    #[test]
+    fn cut_off_bodies_are_decoded_as_far_as_possible() {
+        let body = BODY.repeat(100);
+        let mut encoded = Encoding::Zstd.encode(body.as_bytes()).unwrap();
+        encoded.truncate(encoded.len() - 8);
+
+        let err = block_on(decode(Some("zstd"), encoded.clone(), None, false));
+        assert!(err.is_err());
+
+        let (decoded, truncated) = block_on(decode(Some("zstd"), encoded, None, true)).unwrap();
+        assert!(body.as_bytes().starts_with(&decoded));
+        assert!(truncated);
+    }
+}
@@ -0,0 +1,212 @@
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use std::collections::BTreeMap;
+use std::env;
+use std::path::{Path, PathBuf};
+
+use log::debug;
+use serde::Deserialize;
+
+/// The settings a `Client` can be created from, read from environment
+/// variables or a profile in a config file.
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Settings {
+    pub url: Option<String>,
+    pub urls: Option<Vec<String>>,
+    pub strategy: Option<String>,
+    pub max_failures: Option<u32>,
+    pub cooldown: Option<f64>,
+    pub unix_socket: Option<PathBuf>,
+    pub key: Option<String>,
+    /// The name of an environment variable holding the key, so that
+    /// config files need not contain it.
+    pub key_env: Option<String>,
+    pub timeout: Option<f64>,
+    pub grace_period: Option<f64>,
+    pub max_response_bytes: Option<usize>,
+    pub rate_limit: Option<u32>,
+    pub rate_limit_period: Option<f64>,
+    pub proxy: Option<String>,
+    pub proxy_username: Option<String>,
+    pub proxy_password: Option<String>,
+    pub no_proxy: Option<Vec<String>>,
+    pub env_proxy: Option<bool>,
+    pub ca: Option<PathBuf>,
+    pub client_cert: Option<PathBuf>,
+    pub client_key: Option<PathBuf>,
+    pub tls_min_version: Option<String>,
+}
+
+/// A TOML config file holding named profiles.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    /// The profile used when none is given.
+    default: Option<String>,
+    #[serde(default)]
+    profiles: BTreeMap<String, Settings>,
+}
+
+/// Reads and parses environment variables with a common prefix.
+struct EnvReader<'a> {
+    prefix: &'a str,
+}
+
+impl EnvReader<'_> {
+    fn string(&self, name: &str) -> PyResult<Option<String>> {
+        let var = format!("{}{}", self.prefix, name);
+
+        match env::var(&var) {
+            Ok(value) if value.trim().is_empty() => Ok(None),
+            Ok(value) => Ok(Some(value.trim().to_string())),
+            Err(env::VarError::NotPresent) => Ok(None),
+            Err(env::VarError::NotUnicode(_)) => Err(PyValueError::new_err(format!(
+                "{} is not valid unicode",
+                var
+            ))),
+        }
+    }
+
+    fn list(&self, name: &str) -> PyResult<Option<Vec<String>>> {
+        Ok(self.string(name)?.map(|value| {
+            value
+                .split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(str::to_string)
+                .collect()
+        }))
+    }
+
+    fn parse<T: std::str::FromStr>(&self, name: &str, expected: &str) -> PyResult<Option<T>> {
+        self.string(name)?
+            .map(|value| {
+                value.parse().map_err(|_| {
+                    PyValueError::new_err(format!(
+                        "{}{} must be {}, got {:?}",
+                        self.prefix, name, expected, value
+                    ))
+                })
+            })
+            .transpose()
+    }
+
+    fn bool(&self, name: &str) -> PyResult<Option<bool>> {
+        self.string(name)?
+            .map(|value| match value.to_lowercase().as_str() {
+                "1" | "true" | "yes" | "on" => Ok(true),
+                "0" | "false" | "no" | "off" => Ok(false),
+                _ => Err(PyValueError::new_err(format!(
+                    "{}{} must be a boolean such as true or false, got {:?}",
+                    self.prefix, name, value
+                ))),
+            })
+            .transpose()
+    }
+}
+
+impl Settings {
+    /// Reads the settings from environment variables named after each
+    /// setting in upper case, with the given prefix.
+    pub fn from_env(prefix: &str) -> PyResult<Self> {
+        let env = EnvReader { prefix };
+
+        Ok(Self {
+            url: env.string("URL")?,
+            urls: env.list("URLS")?,
+            strategy: env.string("STRATEGY")?,
+            max_failures: env.parse("MAX_FAILURES", "a non-negative integer")?,
+            cooldown: env.parse("COOLDOWN", "a number of seconds")?,
+            unix_socket: env.string("UNIX_SOCKET")?.map(PathBuf::from),
+            key: env.string("KEY")?,
+            key_env: None,
+            timeout: env.parse("TIMEOUT", "a number of seconds")?,
+            grace_period: env.parse("GRACE_PERIOD", "a number of seconds")?,
+            max_response_bytes: env.parse("MAX_RESPONSE_BYTES", "a number of bytes")?,
+            rate_limit: env.parse("RATE_LIMIT", "a number of requests")?,
+            rate_limit_period: env.parse("RATE_LIMIT_PERIOD", "a number of seconds")?,
+            proxy: env.string("PROXY")?,
+            proxy_username: env.string("PROXY_USERNAME")?,
+            proxy_password: env.string("PROXY_PASSWORD")?,
+            no_proxy: env.list("NO_PROXY")?,
+            env_proxy: env.bool("ENV_PROXY")?,
+            ca: env.string("CA")?.map(PathBuf::from),
+            client_cert: env.string("CLIENT_CERT")?.map(PathBuf::from),
+            client_key: env.string("CLIENT_KEY")?.map(PathBuf::from),
+            tls_min_version: env.string("TLS_MIN_VERSION")?,
+        })
+    }
+
+    /// Loads the settings from a profile in a TOML config file. Without
+    /// a profile, the default of the file is used, or its only profile.
+    ///
+    /// Returns the settings, and the name of the profile.
+    pub fn from_file(path: &Path, profile: Option<&str>) -> PyResult<(Self, String)> {
+        let content = std::fs::read_to_string(path).map_err(|e| {
+            PyValueError::new_err(format!(
+                "Unable to read config {:?}: {}",
+                path.display().to_string(),
+                e
+            ))
+        })?;
+
+        let mut config: ConfigFile = toml::from_str(&content).map_err(|e| {
+            PyValueError::new_err(format!(
+                "Invalid config {:?}: {}",
+                path.display().to_string(),
+                e
+            ))
+        })?;
+
+        let names: Vec<_> = config.profiles.keys().cloned().collect();
+        let name = match (profile, config.default.as_deref(), names.as_slice()) {
+            (Some(profile), _, _) => profile.to_string(),
+            (None, Some(default), _) => default.to_string(),
+            (None, None, [only]) => only.clone(),
+            (None, None, []) => {
+                return Err(PyValueError::new_err(format!(
+                    "No profiles found in config {:?}",
+                    path.display().to_string()
+                )))
+            }
+            (None, None, _) => {
+                return Err(PyValueError::new_err(format!(
+                    "No profile given, and config {:?} sets no default, expected one of {:?}",
+                    path.display().to_string(),
+                    names
+                )))
+            }
+        };
+
+        debug!("Loading profile {:?} from {}", name, path.display());
+
+        match config.profiles.remove(&name) {
+            Some(settings) => Ok((settings, name)),
+            None => Err(PyValueError::new_err(format!(
+                "Profile {:?} not found in config {:?}, expected one of {:?}",
+                name,
+                path.display().to_string(),
+                names
+            ))),
+        }
+    }
+
+    /// The api key, read from the environment if `key_env` is set.
+    pub fn key(&self) -> PyResult<Option<String>> {
+        match (&self.key, &self.key_env) {
+            (Some(_), Some(_)) => Err(PyValueError::new_err(
+                "Only one of key and key_env can be set",
+            )),
+            (Some(key), None) => Ok(Some(key.clone())),
+            (None, Some(var)) => match env::var(var) {
+                Ok(key) if !key.is_empty() => Ok(Some(key)),
+                _ => Err(PyValueError::new_err(format!(
+                    "key_env names {}, which is not set",
+                    var
+                ))),
+            },
+            (None, None) => Ok(None),
+        }
+    }
+}
@@ -1,12 +1,20 @@
-use pyo3::exceptions::PyTypeError;
+use pyo3::exceptions::{PyFileExistsError, PyRuntimeError, PyTypeError, PyValueError};
 use pyo3::prelude::*;
+use pyo3::types::PyType;
+use std::collections::{HashMap, HashSet};
+use std::path::{Component, Path, PathBuf};
 
 use piston_rs::ExecResponse as ExecResponse_;
 use piston_rs::ExecResult as ExecResult_;
 use piston_rs::Executor as Executor_;
 
+use super::archive::{self, ArchiveLimits, ArchiveSource};
+use super::models;
 use super::File;
 
+/// The name of the manifest written by `Executor.export_to_directory`.
+const MANIFEST_NAME: &str = "piston_manifest.json";
+
 /// The result of code execution returned by Piston.
 ///
 /// **NOTE**:
@@ -17,6 +25,7 @@ use super::File;
 #[derive(Clone)]
 pub struct ExecResult {
     inner: ExecResult_,
+    truncated: bool,
 }
 
 impl ExecResult {
@@ -36,6 +45,7 @@ impl ExecResult {
                 code,
                 signal,
             },
+            truncated: false,
         }
     }
 
@@ -43,6 +53,7 @@ impl ExecResult {
     pub fn from_result(result: &ExecResult_) -> Self {
         Self {
             inner: result.clone(),
+            truncated: false,
         }
     }
 }
@@ -93,6 +104,15 @@ impl ExecResult {
         self.inner.signal.clone()
     }
 
+    /// `bool`: Whether the response was cut off at the
+    /// `max_response_bytes` of the client before this result ended.
+    /// Its output is then incomplete, and its code and signal may be
+    /// missing.
+    #[getter]
+    fn truncated(&self) -> bool {
+        self.truncated
+    }
+
     /// Whether or not the execution was ok.
     ///
     /// ### Returns:
@@ -114,6 +134,18 @@ impl ExecResult {
     }
 }
 
+/// The http exchange an `ExecResponse` was received through.
+#[derive(Clone, Default)]
+pub struct Exchange {
+    /// The body as received, if the client keeps raw responses.
+    pub raw_json: Option<String>,
+    pub headers: HashMap<String, String>,
+    /// The id sent with the request in `X-Request-Id`.
+    pub request_id: String,
+    /// The seconds taken to receive the response.
+    pub elapsed: f64,
+}
+
 /// A response from the Piston api when sending a request to execute code.
 ///
 /// **NOTE**:
@@ -124,12 +156,41 @@ impl ExecResult {
 #[derive(Clone)]
 pub struct ExecResponse {
     inner: ExecResponse_,
+    backend: Option<String>,
+    /// Whether the run and compile results were cut off, if the
+    /// response was.
+    truncated: Option<(bool, bool)>,
+    exchange: Exchange,
 }
 
 impl ExecResponse {
     /// Creates a new ExecResponse from a `piston_rs.ExecResponse`.
     pub fn from_response(response: ExecResponse_) -> Self {
-        Self { inner: response }
+        Self {
+            inner: response,
+            backend: None,
+            truncated: None,
+            exchange: Exchange::default(),
+        }
+    }
+
+    /// Sets the http exchange the response was received through.
+    pub fn with_exchange(mut self, exchange: Exchange) -> Self {
+        self.exchange = exchange;
+        self
+    }
+
+    /// Marks the response as cut off, along with the run and compile
+    /// results if given.
+    pub fn with_truncated(mut self, run: bool, compile: bool) -> Self {
+        self.truncated = Some((run, compile));
+        self
+    }
+
+    /// Sets the url of the backend that served the response.
+    pub fn with_backend(mut self, backend: String) -> Self {
+        self.backend = Some(backend);
+        self
     }
 }
 
@@ -164,14 +225,20 @@ impl ExecResponse {
     /// `ExecResult`: The result Piston sends detailing execution.
     #[getter]
     fn run(&self) -> ExecResult {
-        ExecResult::from_result(&self.inner.run)
+        let mut result = ExecResult::from_result(&self.inner.run);
+        result.truncated = self.truncated.is_some_and(|t| t.0);
+        result
     }
 
     /// `ExecResult` | `None`: The optional result Piston sends detailing compilation.
     /// This will be `None` for non-compiled languages.
     #[getter]
     fn compile(&self) -> Option<ExecResult> {
-        self.inner.compile.as_ref().map(ExecResult::from_result)
+        self.inner.compile.as_ref().map(|compile| {
+            let mut result = ExecResult::from_result(compile);
+            result.truncated = self.truncated.is_some_and(|t| t.1);
+            result
+        })
     }
 
     /// `int`: The response status returned by Piston.
@@ -180,6 +247,52 @@ impl ExecResponse {
         self.inner.status
     }
 
+    /// `str | None`: The base url of the Piston instance that served
+    /// the response.
+    #[getter]
+    fn backend(&self) -> Option<String> {
+        self.backend.clone()
+    }
+
+    /// `bool`: Whether the response was cut off at the
+    /// `max_response_bytes` of the client. A language or version that
+    /// could not be read is left empty.
+    #[getter]
+    fn truncated(&self) -> bool {
+        self.truncated.is_some()
+    }
+
+    /// `str | None`: The body of the response as received from Piston,
+    /// after any decompression. This may not be JSON if the request
+    /// failed. `None` unless the client was created with
+    /// `Client.with_raw_responses`.
+    #[getter]
+    fn raw_json(&self) -> Option<String> {
+        self.exchange.raw_json.clone()
+    }
+
+    /// `dict[str, str]`: The headers of the response by lower case
+    /// name, including any rate limit headers. Empty for transports
+    /// set with `Client.with_transport`.
+    #[getter]
+    fn headers(&self) -> HashMap<String, String> {
+        self.exchange.headers.clone()
+    }
+
+    /// `str`: The id generated for the request, and sent with it in the
+    /// `X-Request-Id` header.
+    #[getter]
+    fn request_id(&self) -> String {
+        self.exchange.request_id.clone()
+    }
+
+    /// `float`: The number of seconds taken to receive the response,
+    /// measured by the client. Hooks are not included.
+    #[getter]
+    fn elapsed(&self) -> f64 {
+        self.exchange.elapsed
+    }
+
     /// Whether or not the request to Piston succeeded.
     ///
     /// ### Returns:
@@ -208,20 +321,63 @@ impl ExecResponse {
 ///
 /// - For `compile_memory_limit` and `run_memory_limit` -1 can be used
 /// to signify no limit.
+/// - The executor is validated before it is sent to Piston, see
+/// `Executor.validate`.
 #[pyclass]
 #[derive(Clone)]
 #[pyo3(
-    text_signature = "(language: str = \"\", version: str = \"*\", files: list[File] = [], stdin: str = \"\", args: list[str] = [], compile_timeout: int = 10000, run_timeout: int = 3000, compile_memory_limit: int = -1, run_memory_limit: int = -1, /) -> Executor"
+    text_signature = "(language: str = \"\", version: str = \"*\", files: list[File] = [], stdin: str = \"\", args: list[str] = [], compile_timeout: int = 10000, run_timeout: int = 3000, compile_memory_limit: int = -1, run_memory_limit: int = -1, max_total_size: int | None = None, /) -> Executor"
 )]
 pub struct Executor {
     inner: Executor_,
+    max_total_size: Option<usize>,
 }
 
 impl Executor {
+    /// Creates a new Executor from a `piston_rs.Executor`.
+    pub fn from_executor(executor: Executor_) -> Self {
+        Self {
+            inner: executor,
+            max_total_size: None,
+        }
+    }
+
     /// Converts the Executor into a piston_rs.Executor.
     pub fn convert(&self) -> Executor_ {
         self.inner.clone()
     }
+
+    /// Validates the files of the executor, and their total size.
+    pub fn check(&self) -> PyResult<()> {
+        let mut names = HashSet::new();
+        let mut total_size = 0;
+
+        for file in self.inner.files.iter() {
+            File::check(file, None)?;
+
+            // Unnamed files are given unique names by Piston.
+            let name: PathBuf = Path::new(&file.name)
+                .components()
+                .filter(|c| c != &Component::CurDir)
+                .collect();
+            if !file.name.is_empty() && !names.insert(name) {
+                return Err(PyValueError::new_err(format!(
+                    "Duplicate file name {:?}",
+                    file.name
+                )));
+            }
+
+            total_size += file.content.len();
+        }
+
+        match self.max_total_size {
+            Some(max) if total_size > max => Err(PyValueError::new_err(format!(
+                "Files total {} bytes, exceeding the maximum of {} bytes",
+                total_size, max
+            ))),
+            _ => Ok(()),
+        }
+    }
 }
 
 #[pymethods]
@@ -236,7 +392,8 @@ impl Executor {
         compile_timeout = 10000,
         run_timeout = 3000,
         compile_memory_limit = -1,
-        run_memory_limit = -1
+        run_memory_limit = -1,
+        max_total_size = None
     ))]
     #[allow(clippy::too_many_arguments)]
     fn new(
@@ -249,8 +406,10 @@ impl Executor {
         run_timeout: isize,
         compile_memory_limit: isize,
         run_memory_limit: isize,
+        max_total_size: Option<usize>,
     ) -> Self {
         Self {
+            max_total_size,
             inner: Executor_ {
                 language,
                 version,
@@ -275,7 +434,7 @@ impl Executor {
 
     /// `str`: The language to use for execution.
     #[getter]
-    fn language(&self) -> String {
+    pub fn language(&self) -> String {
         self.inner.language.clone()
     }
 
@@ -349,7 +508,7 @@ impl Executor {
     /// `int`: The maximum allowed time for execution in milliseconds.
     #[getter]
     fn run_timeout(&self) -> isize {
-        self.inner.compile_timeout
+        self.inner.run_timeout
     }
 
     #[setter(run_timeout)]
@@ -379,6 +538,227 @@ impl Executor {
         self.inner.run_memory_limit = limit;
     }
 
+    /// Creates a new `Executor` from the files in a zip, tar or
+    /// gzipped tar archive.
+    ///
+    /// Entries are extracted in memory and keep their relative names.
+    /// Binary entries are base64 encoded.
+    ///
+    /// ### Args:
+    ///
+    /// - source `str | bytes`:
+    /// The path to the archive, or the raw archive bytes.
+    ///
+    /// - main `str | None`:
+    /// The name of the file to move to the front of the files list, so
+    /// that Piston treats it as the main file. Defaults to `None`.
+    ///
+    /// - max_entries `int`:
+    /// The maximum number of files to extract. Defaults to `1000`.
+    ///
+    /// - max_size `int`:
+    /// The maximum total uncompressed size in bytes. Defaults to
+    /// `10485760` (10 MiB).
+    ///
+    /// ### Returns:
+    ///
+    /// - `Executor`: The new executor.
+    ///
+    /// ### Raises:
+    ///
+    /// - `ValueError`: If the archive is unrecognized, exceeds a limit,
+    /// contains unsafe paths, or `main` is not in the archive.
+    /// - `RuntimeError`: If the archive could not be read.
+    #[classmethod]
+    #[pyo3(
+        signature = (source, main = None, max_entries = archive::DEFAULT_MAX_ENTRIES, max_size = archive::DEFAULT_MAX_SIZE),
+        text_signature = "(cls, source: str | bytes, main: str | None = None, max_entries: int = 1000, max_size: int = 10485760) -> Executor"
+    )]
+    fn from_archive(
+        _cls: &PyType,
+        source: ArchiveSource,
+        main: Option<String>,
+        max_entries: usize,
+        max_size: u64,
+    ) -> PyResult<Self> {
+        let limits = ArchiveLimits {
+            max_entries,
+            max_size,
+        };
+
+        let mut files = archive::extract(source, limits)?;
+
+        if let Some(main) = main {
+            match files.iter().position(|f| f.name == main) {
+                Some(index) => {
+                    let file = files.remove(index);
+                    files.insert(0, file);
+                }
+                None => {
+                    return Err(PyValueError::new_err(format!(
+                        "Main file {:?} was not found in the archive",
+                        main
+                    )))
+                }
+            }
+        }
+
+        let mut inner = Executor_::new();
+        inner.set_files(files);
+
+        Ok(Self {
+            inner,
+            max_total_size: None,
+        })
+    }
+
+    /// Writes the executor's files to a directory on disk, decoding
+    /// them according to their encoding, so a submission can be
+    /// reproduced locally.
+    ///
+    /// A `piston_manifest.json` file containing the language, version,
+    /// stdin, args and file names is written alongside the files.
+    /// Unnamed files are written as `file{index}.code`, matching Piston.
+    ///
+    /// ### Args:
+    ///
+    /// - path `str`:
+    /// The directory to write to. It is created if it does not exist.
+    ///
+    /// - overwrite `bool`:
+    /// Whether or not existing files may be overwritten. Defaults to
+    /// `False`.
+    ///
+    /// ### Raises:
+    ///
+    /// - `ValueError`: If a file name is absolute or contains `..`, or
+    /// a file's content is not valid for its encoding.
+    /// - `FileExistsError`: If a file already exists and `overwrite` is
+    /// `False`, or two files would be written to the same path.
+    /// - `RuntimeError`: If a file could not be written.
+    #[pyo3(
+        signature = (path, overwrite = false),
+        text_signature = "(self, path: str, /, overwrite: bool = False) -> None"
+    )]
+    fn export_to_directory(&self, path: String, overwrite: bool) -> PyResult<()> {
+        self.check()?;
+
+        let root = Path::new(&path);
+        let mut names = Vec::with_capacity(self.inner.files.len());
+        let mut contents = Vec::with_capacity(self.inner.files.len());
+
+        // The paths written so far, normalized, and the directories
+        // containing them. Files may not share a path, or be written
+        // where another file's directory goes.
+        let mut written: HashSet<PathBuf> = HashSet::new();
+        let mut directories: HashSet<PathBuf> = HashSet::new();
+
+        // Validate everything up front so nothing is written on failure.
+        for (i, file) in self.inner.files.iter().enumerate() {
+            let name = match file.name.as_str() {
+                "" => format!("file{}.code", i),
+                name => name.to_string(),
+            };
+
+            let normalized: PathBuf = Path::new(&name)
+                .components()
+                .filter(|c| c != &Component::CurDir)
+                .collect();
+
+            if normalized == Path::new(MANIFEST_NAME) {
+                return Err(PyValueError::new_err(format!(
+                    "File name {:?} is reserved for the manifest",
+                    name
+                )));
+            }
+
+            let collides = normalized.as_os_str().is_empty()
+                || directories.contains(&normalized)
+                || normalized.ancestors().any(|a| written.contains(a));
+            if collides {
+                return Err(PyFileExistsError::new_err(format!(
+                    "{:?} collides with another file written to {:?}",
+                    name, path
+                )));
+            }
+
+            directories.extend(normalized.ancestors().skip(1).map(Path::to_path_buf));
+            written.insert(normalized);
+
+            contents.push(models::decode(&file.content, &file.encoding)?);
+            names.push(name);
+        }
+
+        if !overwrite {
+            for name in names.iter().map(String::as_str).chain([MANIFEST_NAME]) {
+                if root.join(name).exists() {
+                    return Err(PyFileExistsError::new_err(format!(
+                        "{:?} already exists in {:?}",
+                        name, path
+                    )));
+                }
+            }
+        }
+
+        let manifest = serde_json::json!({
+            "language": self.inner.language,
+            "version": self.inner.version,
+            "stdin": self.inner.stdin,
+            "args": self.inner.args,
+            "files": names,
+        });
+
+        let write = |name: &str, data: &[u8]| {
+            let target = root.join(name);
+
+            if let Some(parent) = target.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+
+            std::fs::write(target, data)
+        };
+
+        for (name, data) in names.iter().zip(contents) {
+            write(name, &data).map_err(|e| {
+                PyRuntimeError::new_err(format!("Unable to write {:?}: {:?}", name, e.to_string()))
+            })?;
+        }
+
+        let manifest = serde_json::to_vec_pretty(&manifest).unwrap();
+        write(MANIFEST_NAME, &manifest).map_err(|e| {
+            PyRuntimeError::new_err(format!(
+                "Unable to write {:?}: {:?}",
+                MANIFEST_NAME,
+                e.to_string()
+            ))
+        })
+    }
+
+    /// `int | None`: The maximum total size of all file content in
+    /// bytes, or `None` for no limit.
+    #[getter]
+    fn max_total_size(&self) -> Option<usize> {
+        self.max_total_size
+    }
+
+    #[setter(max_total_size)]
+    fn max_total_size_setter(&mut self, size: Option<usize>) {
+        self.max_total_size = size;
+    }
+
+    /// Validates the executor before it is sent to Piston. This is
+    /// called automatically by `Client.execute`.
+    ///
+    /// ### Raises:
+    ///
+    /// - `ValueError`: If a file name is absolute, contains `..` or NUL
+    /// bytes, is duplicated, a file has an unsupported encoding, or the
+    /// files exceed `max_total_size`.
+    #[pyo3(text_signature = "(self) -> None")]
+    fn validate(&self) -> PyResult<()> {
+        self.check()
+    }
+
     /// Copies the executor, leaving the existing one unchanged.
     ///
     /// ### Returns:
@@ -396,6 +776,7 @@ impl Executor {
     #[pyo3(text_signature = "(self) -> None")]
     fn reset(&mut self) {
         self.inner.reset();
+        self.max_total_size = None;
     }
 
     /// Sets the language to use for execution.
@@ -609,4 +990,87 @@ impl Executor {
         slf.inner.run_memory_limit = limit;
         slf
     }
+
+    /// Sets the maximum total size of all file content in bytes.
+    ///
+    /// ### Args:
+    ///
+    /// - size `int | None`:
+    /// The size to set, or `None` for no limit.
+    ///
+    /// ### Returns:
+    ///
+    /// - `Executor`: The executor, for chained method calls.
+    #[pyo3(text_signature = "(self, size: int | None, /) -> Executor")]
+    fn set_max_total_size(mut slf: PyRefMut<Self>, size: Option<usize>) -> PyRefMut<Self> {
+        slf.max_total_size = size;
+        slf
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use piston_rs::File as File_;
+
+    fn executor(names: &[&str], max_total_size: Option<usize>) -> Executor {
+        let files = names
+            .iter()
+            .map(|name| File_ {
+                name: name.to_string(),
+                content: "1234".to_string(),
+                encoding: "utf8".to_string(),
+            })
+            .collect();
+
+        Executor {
+            inner: Executor_ {
+                files,
+                ..Default::default()
+            },
+            max_total_size,
+        }
+    }
+
+    #[test]
+    fn relative_names_are_accepted() {
+        assert!(
+            executor(&["main.py", "./lib/util.py", "lib/./data.txt"], None)
+                .check()
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn unsafe_names_are_rejected() {
+        for name in [
+            "../main.py",
+            "lib/../../main.py",
+            "/etc/passwd",
+            "main\0.py",
+        ] {
+            assert!(executor(&[name], None).check().is_err(), "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn duplicate_names_are_rejected() {
+        assert!(executor(&["main.py", "main.py"], None).check().is_err());
+        assert!(executor(&["lib/a.py", "./lib/a.py"], None).check().is_err());
+        assert!(executor(&["", ""], None).check().is_ok());
+    }
+
+    #[test]
+    fn total_size_is_limited() {
+        assert!(executor(&["a", "b"], Some(8)).check().is_ok());
+        assert!(executor(&["a", "b"], Some(7)).check().is_err());
+        assert!(executor(&["a", "b"], None).check().is_ok());
+    }
+
+    #[test]
+    fn unknown_encodings_are_rejected() {
+        let mut executor = executor(&["a"], None);
+        executor.inner.files[0].encoding = "base32".to_string();
+        assert!(executor.check().is_err());
+    }
 }
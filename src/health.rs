@@ -0,0 +1,315 @@
+use pyo3::prelude::*;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use log::debug;
+use piston_rs::Runtime as Runtime_;
+use reqwest::{Method, Url};
+use serde::Deserialize;
+
+use super::compression::Compression;
+use super::transport::{Transport, TransportRequest, TransportResponse};
+
+/// The body self-hosted Piston instances send from their root.
+#[derive(Deserialize)]
+struct RootMessage {
+    message: String,
+}
+
+/// A fixed `Sec-WebSocket-Key`, only used to ask whether the server
+/// would upgrade the connection.
+const WEBSOCKET_KEY: &str = "dGhlIHNhbXBsZSBub25jZQ==";
+
+/// The path of the root of a self-hosted instance, whose api lives
+/// under `/api/v2`, relative to the base url.
+fn root_path(url: &str) -> Option<String> {
+    let url = Url::parse(url).ok()?;
+    let path = url.path();
+    let api = path.trim_end_matches('/');
+    api.strip_suffix("/api/v2")?;
+
+    // The empty segments left by trailing slashes are stepped out of
+    // too.
+    Some("/..".repeat(2 + path.len() - api.len()))
+}
+
+/// Probes a single Piston instance through its own transport, beneath
+/// any cassette or balancer.
+pub struct Probe<'a> {
+    pub transport: &'a dyn Transport,
+    pub url: &'a str,
+    pub headers: &'a HashMap<String, String>,
+    pub timeout: Duration,
+}
+
+impl Probe<'_> {
+    /// Sends a GET request, giving up after the timeout.
+    async fn get(&self, path: &str, extra: &[(&str, &str)]) -> Result<TransportResponse, String> {
+        let mut headers = self.headers.clone();
+        for (key, value) in extra {
+            headers.insert(key.to_string(), value.to_string());
+        }
+
+        let request = TransportRequest {
+            method: Method::GET,
+            path: path.to_string(),
+            headers,
+            body: None,
+            compression: Compression::default(),
+            max_response_bytes: None,
+        };
+
+        match tokio::time::timeout(self.timeout, self.transport.send(&request)).await {
+            Ok(Ok(response)) => Ok(response),
+            Ok(Err(e)) => Err(Python::with_gil(|py| e.value(py).to_string())),
+            Err(_) => Err(format!("Timed out after {:.1?}", self.timeout)),
+        }
+    }
+
+    /// The version reported by the root of a self-hosted instance,
+    /// which can only be reached over http.
+    async fn version(&self) -> Option<String> {
+        // Other transports may not resolve a path outside the api.
+        self.transport.http()?;
+
+        let response = self.get(&root_path(self.url)?, &[]).await.ok()?;
+        if !(200..300).contains(&response.status) {
+            return None;
+        }
+
+        let root: RootMessage = serde_json::from_str(&response.body).ok()?;
+
+        root.message
+            .strip_prefix("Piston v")
+            .map(|version| version.to_string())
+    }
+
+    /// Checks the optional parts of the api the instance supports.
+    async fn features(&self) -> Vec<String> {
+        let mut features = Vec::new();
+
+        if let Ok(response) = self.get("/packages", &[]).await {
+            let parsed = serde_json::from_str::<Vec<serde_json::Value>>(&response.body);
+            if response.status == 200 && parsed.is_ok() {
+                features.push("packages".to_string());
+            }
+        }
+
+        let upgrade = [
+            ("connection", "upgrade"),
+            ("upgrade", "websocket"),
+            ("sec-websocket-version", "13"),
+            ("sec-websocket-key", WEBSOCKET_KEY),
+        ];
+
+        if let Ok(response) = self.get("/connect", &upgrade).await {
+            if response.status == 101 {
+                features.push("websocket".to_string());
+            }
+        }
+
+        features
+    }
+
+    /// Probes the instance, never failing.
+    pub async fn run(&self) -> HealthReport {
+        let start = Instant::now();
+        let result = self.get("/runtimes", &[]).await;
+        let latency = start.elapsed().as_secs_f64();
+
+        let mut report = HealthReport {
+            url: self.url.to_string(),
+            ..HealthReport::default()
+        };
+
+        let response = match result {
+            Ok(response) => response,
+            Err(e) => {
+                debug!("Health check of {} failed: {}", self.url, e);
+                report.error = Some(e);
+                return report;
+            }
+        };
+
+        report.reachable = true;
+        report.status = Some(response.status);
+        report.latency = Some(latency);
+
+        if response.status != 200 {
+            report.error = Some(format!("Unexpected status {}", response.status));
+            return report;
+        }
+
+        match serde_json::from_str::<Vec<Runtime_>>(&response.body) {
+            Ok(runtimes) => report.runtimes = Some(runtimes.len()),
+            Err(e) => {
+                report.error = Some(format!("Unable to parse runtimes: {}", e));
+                return report;
+            }
+        }
+
+        report.version = self.version().await;
+        report.features = self.features().await;
+        report
+    }
+}
+
+/// The result of probing a Piston instance with `Client.health`.
+///
+/// **NOTE**:
+///
+/// - This object cannot be instantiated, and is immutable.
+/// - It can only be created with a call to `Client.health`.
+#[pyclass]
+#[derive(Clone, Default)]
+pub struct HealthReport {
+    url: String,
+    reachable: bool,
+    status: Option<u16>,
+    latency: Option<f64>,
+    runtimes: Option<usize>,
+    version: Option<String>,
+    features: Vec<String>,
+    error: Option<String>,
+    backends: Vec<HealthReport>,
+}
+
+impl HealthReport {
+    /// Combines the reports of the backends of a balanced client. The
+    /// first healthy backend, in the order given, is reported on, or
+    /// the first backend if none are healthy.
+    pub fn balanced(backends: Vec<HealthReport>) -> Self {
+        let mut report = backends
+            .iter()
+            .find(|backend| backend.healthy())
+            .or_else(|| backends.first())
+            .cloned()
+            .unwrap_or_default();
+
+        report.backends = backends;
+        report
+    }
+}
+
+#[pymethods]
+impl HealthReport {
+    fn __repr__(&self) -> PyResult<String> {
+        Ok(format!(
+            "HealthReport {{ url: \"{}\", healthy: {}, latency: {:?}, runtimes: {:?}, version: {:?}, features: {:?}, error: {:?}, backends: {} }}",
+            self.url,
+            self.healthy(),
+            self.latency,
+            self.runtimes,
+            self.version,
+            self.features,
+            self.error,
+            self.backends.len(),
+        ))
+    }
+
+    fn __str__(&self) -> PyResult<String> {
+        self.__repr__()
+    }
+
+    /// `str`: The base url of the instance that was probed.
+    #[getter]
+    fn url(&self) -> String {
+        self.url.clone()
+    }
+
+    /// `bool`: Whether the instance responded at all.
+    #[getter]
+    fn reachable(&self) -> bool {
+        self.reachable
+    }
+
+    /// `bool`: Whether the instance responded with runtimes that could
+    /// be parsed.
+    #[getter]
+    fn healthy(&self) -> bool {
+        self.runtimes.is_some()
+    }
+
+    /// `int | None`: The status of the runtimes response, if reachable.
+    #[getter]
+    fn status(&self) -> Option<u16> {
+        self.status
+    }
+
+    /// `float | None`: The time the runtimes request took in seconds,
+    /// if reachable.
+    #[getter]
+    fn latency(&self) -> Option<f64> {
+        self.latency
+    }
+
+    /// `int | None`: The number of runtimes available, if healthy.
+    #[getter]
+    fn runtimes(&self) -> Option<usize> {
+        self.runtimes
+    }
+
+    /// `str | None`: The Piston version of the instance, if it could
+    /// be detected. Only self-hosted instances reached over http report
+    /// their version.
+    #[getter]
+    fn version(&self) -> Option<String> {
+        self.version.clone()
+    }
+
+    /// `list[str]`: The optional features the instance supports, out of
+    /// `packages` and `websocket`.
+    #[getter]
+    fn features(&self) -> Vec<String> {
+        self.features.clone()
+    }
+
+    /// `str | None`: Why the instance is not healthy, if it is not.
+    #[getter]
+    fn error(&self) -> Option<String> {
+        self.error.clone()
+    }
+
+    /// `list[HealthReport]`: The report of each backend of a client
+    /// made with `Client.with_urls`, in the order given. The other
+    /// properties describe the first healthy backend, or the first
+    /// backend if none are healthy. Empty for other clients.
+    #[getter]
+    fn backends(&self) -> Vec<HealthReport> {
+        self.backends.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The url a transport requests for the root path.
+    fn root(url: &str) -> Option<String> {
+        let path = root_path(url)?;
+        Some(Url::parse(&format!("{}{}", url, path)).unwrap().to_string())
+    }
+
+    #[test]
+    fn root_paths_resolve_to_the_instance_root() {
+        assert_eq!(
+            root("http://localhost:2000/api/v2").as_deref(),
+            Some("http://localhost:2000/")
+        );
+        assert_eq!(
+            root("http://localhost:2000/api/v2//").as_deref(),
+            Some("http://localhost:2000/")
+        );
+        assert_eq!(
+            root("https://example.com/piston/api/v2/").as_deref(),
+            Some("https://example.com/piston/")
+        );
+    }
+
+    #[test]
+    fn root_paths_need_a_self_hosted_api() {
+        assert_eq!(root_path("https://emkc.org/api/v3/piston"), None);
+        assert_eq!(root_path("http://localhost:2000/api/v2/extra"), None);
+        assert_eq!(root_path("not a url"), None);
+    }
+}
@@ -0,0 +1,256 @@
+use pyo3::prelude::*;
+use std::collections::{HashMap, HashSet};
+
+use piston_rs::Executor as Executor_;
+
+use super::callback::call_maybe_async;
+use super::client::{is_sensitive, redact_headers, REDACTED};
+use super::Executor;
+
+/// A set of callables invoked around every request a `Client` sends.
+#[derive(Clone)]
+pub struct Hook {
+    pub before_request: Option<PyObject>,
+    pub after_response: Option<PyObject>,
+    pub on_error: Option<PyObject>,
+}
+
+/// A view of a request about to be sent to Piston, passed to hooks.
+///
+/// `before_request` hooks may modify the `executor` in place, or
+/// replace it or the `headers` by assigning to them. The values of
+/// sensitive headers are masked, see `HookRequest.reveal_headers`.
+///
+/// **NOTE**:
+///
+/// - This object cannot be instantiated.
+#[pyclass]
+#[derive(Clone)]
+pub struct HookRequest {
+    method: String,
+    path: String,
+    url: String,
+    headers: HashMap<String, String>,
+    /// Headers marked sensitive, besides `SENSITIVE_HEADERS`.
+    sensitive: HashSet<String>,
+    executor: Option<Py<Executor>>,
+}
+
+impl HookRequest {
+    pub fn new(
+        method: &str,
+        path: &str,
+        url: &str,
+        headers: HashMap<String, String>,
+        sensitive: HashSet<String>,
+        executor: Option<Py<Executor>>,
+    ) -> Self {
+        Self {
+            method: method.to_string(),
+            path: path.to_string(),
+            url: url.to_string(),
+            headers,
+            sensitive,
+            executor,
+        }
+    }
+
+    /// The headers and validated executor to send, after any changes
+    /// made by hooks.
+    pub fn prepare(&self, py: Python) -> PyResult<(HashMap<String, String>, Option<Executor_>)> {
+        let executor = match &self.executor {
+            Some(executor) => {
+                let executor = executor.borrow(py);
+                executor.check()?;
+                Some(executor.convert())
+            }
+            None => None,
+        };
+
+        Ok((self.headers.clone(), executor))
+    }
+}
+
+#[pymethods]
+impl HookRequest {
+    fn __repr__(&self) -> PyResult<String> {
+        Ok(format!(
+            "HookRequest {{ method: \"{}\", url: \"{}{}\" }}",
+            self.method, self.url, self.path
+        ))
+    }
+
+    fn __str__(&self) -> PyResult<String> {
+        self.__repr__()
+    }
+
+    /// `str`: The http method of the request.
+    #[getter]
+    fn method(&self) -> String {
+        self.method.clone()
+    }
+
+    /// `str`: The path of the request, relative to the base url.
+    #[getter]
+    fn path(&self) -> String {
+        self.path.clone()
+    }
+
+    /// `str`: The base url the request is sent to.
+    #[getter]
+    fn url(&self) -> String {
+        self.url.clone()
+    }
+
+    /// `dict[str, str]`: The headers sent with the request, with the
+    /// values of sensitive headers masked. Masked values assigned back
+    /// keep their original value.
+    #[getter]
+    fn headers(&self) -> HashMap<String, String> {
+        redact_headers(&self.headers, &self.sensitive)
+    }
+
+    #[setter(headers)]
+    fn headers_setter(&mut self, headers: HashMap<String, String>) {
+        self.headers = headers
+            .into_iter()
+            .map(|(key, value)| {
+                let masked = value == REDACTED && is_sensitive(&key, &self.sensitive);
+                match masked {
+                    true => {
+                        let original = self.headers.get(&key).cloned().unwrap_or(value);
+                        (key, original)
+                    }
+                    false => (key, value),
+                }
+            })
+            .collect();
+    }
+
+    /// Gets the headers sent with the request, without masking the
+    /// values of sensitive headers such as the api key.
+    ///
+    /// ### Returns:
+    ///
+    /// - `dict[str, str]`: The headers.
+    #[pyo3(text_signature = "(self) -> dict[str, str]")]
+    fn reveal_headers(&self) -> HashMap<String, String> {
+        self.headers.clone()
+    }
+
+    /// `Executor | None`: The executor being sent, or `None` for
+    /// requests without one.
+    #[getter]
+    fn executor(&self) -> Option<Py<Executor>> {
+        self.executor.clone()
+    }
+
+    #[setter(executor)]
+    fn executor_setter(&mut self, executor: Py<Executor>) {
+        self.executor = Some(executor);
+    }
+}
+
+/// A view of a response received from Piston, passed to hooks.
+///
+/// **NOTE**:
+///
+/// - This object cannot be instantiated, and is immutable.
+#[pyclass]
+#[derive(Clone)]
+pub struct HookResponse {
+    status: u16,
+    body: String,
+    elapsed: f64,
+}
+
+impl HookResponse {
+    pub fn new(status: u16, body: String, elapsed: f64) -> Self {
+        Self {
+            status,
+            body,
+            elapsed,
+        }
+    }
+}
+
+#[pymethods]
+impl HookResponse {
+    fn __repr__(&self) -> PyResult<String> {
+        Ok(format!(
+            "HookResponse {{ status: {}, elapsed: {} }}",
+            self.status, self.elapsed
+        ))
+    }
+
+    fn __str__(&self) -> PyResult<String> {
+        self.__repr__()
+    }
+
+    /// `int`: The http status of the response.
+    #[getter]
+    fn status(&self) -> u16 {
+        self.status
+    }
+
+    /// `str`: The raw body of the response.
+    #[getter]
+    fn body(&self) -> String {
+        self.body.clone()
+    }
+
+    /// `float`: The time the request took, in seconds.
+    #[getter]
+    fn elapsed(&self) -> f64 {
+        self.elapsed
+    }
+}
+
+/// Runs the `before_request` hooks in order. A hook returning an
+/// `Executor` replaces the executor being sent.
+pub async fn before_request(hooks: &[Hook], request: &Py<HookRequest>) -> PyResult<()> {
+    for hook in hooks.iter() {
+        if let Some(callable) = &hook.before_request {
+            let args = Python::with_gil(|py| (request.clone_ref(py),));
+            let result = call_maybe_async(callable, args).await?;
+
+            Python::with_gil(|py| {
+                if let Ok(executor) = result.extract::<Py<Executor>>(py) {
+                    request.borrow_mut(py).executor = Some(executor);
+                }
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// Runs the `after_response` hooks in order.
+pub async fn after_response(
+    hooks: &[Hook],
+    request: &Py<HookRequest>,
+    response: HookResponse,
+) -> PyResult<()> {
+    let response = Python::with_gil(|py| Py::new(py, response))?;
+
+    for hook in hooks.iter() {
+        if let Some(callable) = &hook.after_response {
+            let args = Python::with_gil(|py| (request.clone_ref(py), response.clone_ref(py)));
+            call_maybe_async(callable, args).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Runs the `on_error` hooks in order.
+pub async fn on_error(hooks: &[Hook], request: &Py<HookRequest>, error: &PyErr) -> PyResult<()> {
+    for hook in hooks.iter() {
+        if let Some(callable) = &hook.on_error {
+            let args = Python::with_gil(|py| (request.clone_ref(py), error.clone_ref(py)));
+            call_maybe_async(callable, args).await?;
+        }
+    }
+
+    Ok(())
+}
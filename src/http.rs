@@ -0,0 +1,332 @@
+use pyo3::exceptions::{PyRuntimeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use log::warn;
+use reqwest::tls::{Certificate, Identity, Version};
+use reqwest::{NoProxy, Proxy};
+
+/// Which requests a proxy is used for, by the scheme of their url.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProxyScheme {
+    All,
+    Http,
+    Https,
+}
+
+impl ProxyScheme {
+    /// Parses the scheme from its Python name.
+    pub fn parse(scheme: &str) -> PyResult<Self> {
+        match scheme {
+            "all" => Ok(Self::All),
+            "http" => Ok(Self::Http),
+            "https" => Ok(Self::Https),
+            _ => Err(PyValueError::new_err(format!(
+                "Unsupported proxy scheme {:?}, expected one of [\"all\", \"http\", \"https\"]",
+                scheme
+            ))),
+        }
+    }
+}
+
+/// A proxy requests are sent through.
+#[derive(Clone, Debug)]
+pub struct ProxyOptions {
+    pub url: String,
+    pub scheme: ProxyScheme,
+    pub credentials: Option<(String, String)>,
+    pub no_proxy: Option<String>,
+}
+
+impl ProxyOptions {
+    /// Checks that the proxy url is one reqwest can connect through.
+    pub fn check(&self) -> PyResult<()> {
+        let supported = ["http://", "https://", "socks5://", "socks5h://"];
+        let url = self.url.to_ascii_lowercase();
+        if supported.iter().any(|s| url.starts_with(s)) {
+            return Ok(());
+        }
+
+        Err(PyValueError::new_err(format!(
+            "Unsupported proxy url {:?}, expected an http, https, socks5 or socks5h url",
+            self.url
+        )))
+    }
+
+    fn build(&self) -> PyResult<Proxy> {
+        let proxy = match self.scheme {
+            ProxyScheme::All => Proxy::all(&self.url),
+            ProxyScheme::Http => Proxy::http(&self.url),
+            ProxyScheme::Https => Proxy::https(&self.url),
+        }
+        .map_err(|e| PyValueError::new_err(format!("Invalid proxy url {:?}: {}", self.url, e)))?;
+
+        let proxy = match &self.credentials {
+            Some((username, password)) => proxy.basic_auth(username, password),
+            None => proxy,
+        };
+
+        let no_proxy = self.no_proxy.as_deref().and_then(NoProxy::from_string);
+        Ok(proxy.no_proxy(no_proxy))
+    }
+}
+
+/// Reads the first of the given environment variables that is set.
+fn var(names: &[&str]) -> Option<String> {
+    names
+        .iter()
+        .find_map(|name| env::var(name).ok())
+        .filter(|value| !value.is_empty())
+}
+
+/// A proxy url read from the environment. Like reqwest and curl, urls
+/// without a scheme, such as `proxy.corp:8080`, are http proxies.
+fn env_proxy_url(url: String) -> String {
+    match url.contains("://") {
+        true => url,
+        false => format!("http://{}", url),
+    }
+}
+
+/// The proxies configured by the `HTTP_PROXY`, `HTTPS_PROXY`,
+/// `ALL_PROXY` and `NO_PROXY` environment variables, in either case.
+fn env_proxies() -> PyResult<Vec<ProxyOptions>> {
+    let no_proxy = var(&["NO_PROXY", "no_proxy"]);
+    let vars = [
+        (ProxyScheme::Https, ["HTTPS_PROXY", "https_proxy"]),
+        (ProxyScheme::Http, ["HTTP_PROXY", "http_proxy"]),
+        (ProxyScheme::All, ["ALL_PROXY", "all_proxy"]),
+    ];
+
+    vars.into_iter()
+        .filter_map(|(scheme, names)| var(&names).map(|url| (scheme, url)))
+        .map(|(scheme, url)| {
+            let proxy = ProxyOptions {
+                url: env_proxy_url(url),
+                scheme,
+                credentials: None,
+                no_proxy: no_proxy.clone(),
+            };
+
+            proxy.check().map(|_| proxy)
+        })
+        .collect()
+}
+
+/// Parses a minimum TLS version, out of those rustls supports.
+pub fn parse_tls_version(version: &str) -> PyResult<Version> {
+    match version {
+        "1.2" => Ok(Version::TLS_1_2),
+        "1.3" => Ok(Version::TLS_1_3),
+        _ => Err(PyValueError::new_err(format!(
+            "Unsupported TLS version {:?}, expected one of [\"1.2\", \"1.3\"]",
+            version
+        ))),
+    }
+}
+
+/// Reads PEM data given as `bytes`, or as the path of a file.
+pub fn read_pem(value: &PyAny, name: &str) -> PyResult<Vec<u8>> {
+    if let Ok(bytes) = value.downcast::<PyBytes>() {
+        return Ok(bytes.as_bytes().to_vec());
+    }
+
+    let path: PathBuf = value.extract()?;
+    read_pem_file(&path, name)
+}
+
+/// Reads PEM data from a file.
+pub fn read_pem_file(path: &Path, name: &str) -> PyResult<Vec<u8>> {
+    std::fs::read(path).map_err(|e| {
+        PyValueError::new_err(format!(
+            "Unable to read {} from {:?}: {}",
+            name,
+            path.display().to_string(),
+            e
+        ))
+    })
+}
+
+/// How a client verifies Piston, and identifies itself, over TLS.
+#[derive(Clone, Debug, Default)]
+pub struct TlsOptions {
+    /// PEM encoded root certificates to trust, alongside the built-in
+    /// roots.
+    pub ca: Option<Vec<u8>>,
+    /// A PEM encoded client certificate chain and private key.
+    pub identity: Option<Vec<u8>>,
+    pub min_version: Option<Version>,
+    pub accept_invalid_certs: bool,
+}
+
+impl TlsOptions {
+    fn configure(&self, mut builder: reqwest::ClientBuilder) -> PyResult<reqwest::ClientBuilder> {
+        if let Some(ca) = &self.ca {
+            let certificates = Certificate::from_pem_bundle(ca)
+                .map_err(|e| PyValueError::new_err(format!("Invalid CA bundle: {}", e)))?;
+
+            if certificates.is_empty() {
+                return Err(PyValueError::new_err(
+                    "Invalid CA bundle: no PEM certificates found",
+                ));
+            }
+
+            for certificate in certificates {
+                builder = builder.add_root_certificate(certificate);
+            }
+        }
+
+        if let Some(identity) = &self.identity {
+            let identity = Identity::from_pem(identity).map_err(|e| {
+                PyValueError::new_err(format!("Invalid client certificate or key: {}", e))
+            })?;
+
+            builder = builder.identity(identity);
+        }
+
+        if let Some(version) = self.min_version {
+            builder = builder.min_tls_version(version);
+        }
+
+        if self.accept_invalid_certs {
+            warn!("TLS certificate verification is disabled, do not use this in production");
+            builder = builder.danger_accept_invalid_certs(true);
+        }
+
+        Ok(builder)
+    }
+}
+
+/// How a client pools and configures its connections.
+#[derive(Clone, Debug)]
+pub struct PoolOptions {
+    /// The most idle connections kept per host, or `None` for no limit.
+    pub max_idle_per_host: Option<usize>,
+    /// The most requests sent to each host at once, or `None` for no
+    /// limit. Enforced by the transports, as reqwest has no such limit.
+    pub max_connections_per_host: Option<usize>,
+    pub idle_timeout: Option<Duration>,
+    pub tcp_keepalive: Option<Duration>,
+    pub tcp_nodelay: bool,
+    pub http2_prior_knowledge: bool,
+}
+
+impl Default for PoolOptions {
+    /// The defaults of reqwest.
+    fn default() -> Self {
+        Self {
+            max_idle_per_host: None,
+            max_connections_per_host: None,
+            idle_timeout: Some(Duration::from_secs(90)),
+            tcp_keepalive: None,
+            tcp_nodelay: true,
+            http2_prior_knowledge: false,
+        }
+    }
+}
+
+impl PoolOptions {
+    fn configure(&self, mut builder: reqwest::ClientBuilder) -> reqwest::ClientBuilder {
+        if let Some(max_idle) = self.max_idle_per_host {
+            builder = builder.pool_max_idle_per_host(max_idle);
+        }
+
+        if self.http2_prior_knowledge {
+            builder = builder.http2_prior_knowledge();
+        }
+
+        builder
+            .pool_idle_timeout(self.idle_timeout)
+            .tcp_keepalive(self.tcp_keepalive)
+            .tcp_nodelay(self.tcp_nodelay)
+    }
+}
+
+/// How a client connects to Piston over http. Shared by the transports
+/// a client creates, and rebuilt whenever it changes.
+#[derive(Clone, Debug, Default)]
+pub struct HttpOptions {
+    /// Proxies to use, in order of precedence.
+    pub proxies: Vec<ProxyOptions>,
+    /// Whether to fall back to the proxies configured by environment
+    /// variables.
+    pub env_proxy: bool,
+    pub tls: TlsOptions,
+    pub pool: PoolOptions,
+}
+
+impl HttpOptions {
+    /// Builds a reqwest client using these options.
+    pub fn build(&self) -> PyResult<reqwest::Client> {
+        let mut proxies = self.proxies.clone();
+        if self.env_proxy {
+            proxies.extend(env_proxies()?);
+        }
+
+        // Without proxies of our own, reqwest reads the environment as
+        // usual. Otherwise it must not, so that the environment is only
+        // used when asked for, behind the proxies given.
+        let mut builder = reqwest::Client::builder();
+        if !proxies.is_empty() {
+            builder = builder.no_proxy();
+        }
+
+        for proxy in proxies.iter() {
+            builder = builder.proxy(proxy.build()?);
+        }
+
+        builder = self.pool.configure(builder);
+        self.tls
+            .configure(builder)?
+            .build()
+            .map_err(|e| PyRuntimeError::new_err(format!("Unable to create http client: {}", e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn proxy(url: &str) -> ProxyOptions {
+        ProxyOptions {
+            url: url.to_string(),
+            scheme: ProxyScheme::All,
+            credentials: None,
+            no_proxy: None,
+        }
+    }
+
+    #[test]
+    fn env_proxies_default_to_http() {
+        assert_eq!(
+            env_proxy_url("proxy.corp:8080".to_string()),
+            "http://proxy.corp:8080"
+        );
+        assert_eq!(
+            env_proxy_url("socks5://proxy.corp:1080".to_string()),
+            "socks5://proxy.corp:1080"
+        );
+        assert!(proxy(&env_proxy_url("proxy.corp:8080".to_string()))
+            .check()
+            .is_ok());
+    }
+
+    #[test]
+    fn proxies_need_a_supported_scheme() {
+        for url in [
+            "http://proxy:8080",
+            "HTTPS://proxy:8443",
+            "socks5://proxy:1080",
+            "socks5h://proxy:1080",
+        ] {
+            assert!(proxy(url).check().is_ok(), "{}", url);
+        }
+
+        for url in ["proxy.corp:8080", "ftp://proxy:21", ""] {
+            assert!(proxy(url).check().is_err(), "{}", url);
+        }
+    }
+}
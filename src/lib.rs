@@ -1,24 +1,67 @@
 use pyo3::prelude::*;
 
+mod archive;
+mod auth;
+mod balancer;
+mod callback;
+mod cancel;
+mod cassette;
 mod client;
+mod compression;
+mod config;
 mod executor;
+mod health;
+mod hooks;
+mod http;
+mod lifecycle;
+mod logging;
+mod metrics;
 mod models;
+mod partial;
+mod pool;
+mod ratelimit;
+mod testing;
+mod transport;
 
+pub use balancer::BackendStatus;
+pub use cancel::CancelToken;
 pub use client::Client;
 pub use executor::ExecResponse;
 pub use executor::ExecResult;
 pub use executor::Executor;
+pub use health::HealthReport;
+pub use hooks::HookRequest;
+pub use hooks::HookResponse;
+pub use metrics::ClientStats;
 pub use models::File;
 pub use models::Runtime;
+pub use pool::PoolStats;
+pub use transport::FakeRequest;
+pub use transport::FakeTransport;
 
 #[pymodule]
-pub fn piston_rspy(_py: Python, m: &PyModule) -> PyResult<()> {
+pub fn piston_rspy(py: Python, m: &PyModule) -> PyResult<()> {
     m.add_class::<Runtime>()?;
     m.add_class::<File>()?;
     m.add_class::<ExecResult>()?;
     m.add_class::<ExecResponse>()?;
     m.add_class::<Executor>()?;
     m.add_class::<Client>()?;
+    m.add_class::<CancelToken>()?;
+    m.add_class::<BackendStatus>()?;
+    m.add_class::<FakeTransport>()?;
+    m.add_class::<FakeRequest>()?;
+    m.add_class::<HookRequest>()?;
+    m.add_class::<HookResponse>()?;
+    m.add_class::<ClientStats>()?;
+    m.add_class::<HealthReport>()?;
+    m.add_class::<PoolStats>()?;
+    m.add_function(wrap_pyfunction!(logging::set_log_level, m)?)?;
+    logging::register(py)?;
+
+    let testing = PyModule::new(py, "testing")?;
+    testing::register(testing)?;
+    m.add_submodule(testing)?;
 
     Ok(())
 }
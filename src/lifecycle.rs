@@ -0,0 +1,181 @@
+use pyo3::exceptions::PyRuntimeError;
+use pyo3::prelude::*;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::Duration;
+
+use log::{info, warn};
+use tokio::sync::Notify;
+
+use super::cancel::CancelToken;
+
+/// The default number of seconds in-flight requests are given to
+/// complete when a client is closed.
+pub const DEFAULT_GRACE_PERIOD: f64 = 10.0;
+
+/// Tracks the in-flight requests of a client, and whether it has been
+/// closed. Shared by every clone of a `Client`.
+#[derive(Default)]
+pub struct Lifecycle {
+    closed: AtomicBool,
+    in_flight: AtomicUsize,
+    idle: Notify,
+    abort: CancelToken,
+}
+
+/// Marks a request as in-flight until dropped.
+struct InFlight<'a>(&'a Lifecycle);
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        if self.0.in_flight.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.0.idle.notify_waiters();
+        }
+    }
+}
+
+impl Lifecycle {
+    /// Whether the client has been closed.
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+
+    /// The number of requests currently in-flight.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::SeqCst)
+    }
+
+    /// Raises if the client has been closed.
+    pub fn check(&self) -> PyResult<()> {
+        match self.is_closed() {
+            true => Err(PyRuntimeError::new_err("Client is closed")),
+            false => Ok(()),
+        }
+    }
+
+    /// Runs a request, tracking it as in-flight. The request is aborted
+    /// if the client is closed and it outlives the grace period.
+    pub async fn run<F, T>(&self, future: F) -> PyResult<T>
+    where
+        F: Future<Output = PyResult<T>>,
+    {
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        let _in_flight = InFlight(self);
+
+        // Checked after registering, so that a concurrent close either
+        // rejects the request or waits for it.
+        self.check()?;
+
+        self.abort
+            .run_or(future, || {
+                PyRuntimeError::new_err("Client was closed before the request completed")
+            })
+            .await
+    }
+
+    /// Waits until no requests are in-flight.
+    async fn drained(&self) {
+        loop {
+            let mut notified = std::pin::pin!(self.idle.notified());
+            notified.as_mut().enable();
+
+            if self.in_flight() == 0 {
+                return;
+            }
+
+            notified.await;
+        }
+    }
+
+    /// Closes the client, rejecting new requests, then waits up to the
+    /// grace period for in-flight requests before aborting them.
+    ///
+    /// Closing an already closed client waits for the first close to
+    /// finish draining.
+    pub async fn close(&self, grace_period: Duration) {
+        if self.closed.swap(true, Ordering::SeqCst) {
+            return self.drained().await;
+        }
+
+        let pending = self.in_flight();
+        if pending > 0 {
+            info!("Closing client, draining {} in-flight requests", pending);
+        }
+
+        if tokio::time::timeout(grace_period, self.drained())
+            .await
+            .is_err()
+        {
+            warn!(
+                "Aborting {} requests still in-flight after {:.1?}",
+                self.in_flight(),
+                grace_period
+            );
+
+            self.abort.cancel();
+            self.drained().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use tokio::sync::oneshot;
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    #[test]
+    fn closed_clients_reject_requests() {
+        let lifecycle = Lifecycle::default();
+        block_on(async {
+            lifecycle.close(Duration::ZERO).await;
+            assert!(lifecycle.run(async { Ok(()) }).await.is_err());
+        });
+    }
+
+    #[test]
+    fn closing_again_waits_for_the_first_close() {
+        let lifecycle = Arc::new(Lifecycle::default());
+        block_on(async {
+            let (done, wait) = oneshot::channel::<()>();
+            let request = tokio::spawn({
+                let lifecycle = lifecycle.clone();
+                async move {
+                    lifecycle
+                        .run(async {
+                            let _ = wait.await;
+                            Ok(())
+                        })
+                        .await
+                }
+            });
+            tokio::task::yield_now().await;
+
+            let first = tokio::spawn({
+                let lifecycle = lifecycle.clone();
+                async move { lifecycle.close(Duration::from_secs(60)).await }
+            });
+            tokio::task::yield_now().await;
+            assert!(lifecycle.is_closed());
+
+            let second =
+                tokio::time::timeout(Duration::from_millis(50), lifecycle.close(Duration::ZERO));
+            assert!(second.await.is_err());
+            assert_eq!(lifecycle.in_flight(), 1);
+
+            done.send(()).unwrap();
+            lifecycle.close(Duration::ZERO).await;
+            first.await.unwrap();
+            assert!(request.await.unwrap().is_ok());
+            assert_eq!(lifecycle.in_flight(), 0);
+        });
+    }
+}
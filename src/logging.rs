@@ -0,0 +1,206 @@
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::{PyCFunction, PyDict, PyTuple};
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+/// The name of the Python logger events are sent to.
+const LOGGER_NAME: &str = "piston_rspy";
+
+/// The Python level used for `trace` events, below `logging.DEBUG`.
+const TRACE: u32 = 5;
+
+/// Forwards `log` records to Python's `logging` module.
+///
+/// Records from this crate go to the `piston_rspy` logger, and records
+/// from dependencies such as reqwest go to children of it, for example
+/// `piston_rspy.reqwest.connect`.
+struct PyLogger;
+
+static LOGGER: PyLogger = PyLogger;
+
+/// The Python logging level for a `log` level.
+fn python_level(level: Level) -> u32 {
+    match level {
+        Level::Error => 40,
+        Level::Warn => 30,
+        Level::Info => 20,
+        Level::Debug => 10,
+        Level::Trace => TRACE,
+    }
+}
+
+/// The `log` level filter for a Python logging level.
+fn level_filter(level: u32) -> LevelFilter {
+    match level {
+        l if l <= TRACE => LevelFilter::Trace,
+        l if l <= 10 => LevelFilter::Debug,
+        l if l <= 20 => LevelFilter::Info,
+        l if l <= 30 => LevelFilter::Warn,
+        _ => LevelFilter::Error,
+    }
+}
+
+/// The name of the Python logger for a record target.
+fn logger_name(target: &str) -> String {
+    let target = target.replace("::", ".");
+
+    if target == LOGGER_NAME || target.starts_with("piston_rspy.") {
+        target
+    } else {
+        format!("{}.{}", LOGGER_NAME, target)
+    }
+}
+
+impl Log for PyLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        // Records can be emitted from runtime threads while the
+        // interpreter is shutting down, when the GIL can't be taken.
+        if !self.enabled(record.metadata()) || unsafe { pyo3::ffi::Py_IsInitialized() } == 0 {
+            return;
+        }
+
+        Python::with_gil(|py| {
+            let emit = || -> PyResult<()> {
+                let logger = py
+                    .import("logging")?
+                    .call_method1("getLogger", (logger_name(record.target()),))?;
+
+                let level = python_level(record.level());
+                if logger.call_method1("isEnabledFor", (level,))?.is_true()? {
+                    logger.call_method1("log", (level, record.args().to_string()))?;
+                }
+
+                Ok(())
+            };
+
+            // Logging must never raise into the code being logged.
+            if let Err(e) = emit() {
+                e.print(py);
+            }
+        });
+    }
+
+    fn flush(&self) {}
+}
+
+/// Skips creating records in the Rust layer that no `piston_rspy`
+/// logger is enabled for, following the effective levels of the
+/// loggers in Python.
+fn sync_max_level(py: Python) -> PyResult<()> {
+    let logging = py.import("logging")?;
+    let manager = logging.getattr("Logger")?.getattr("manager")?;
+    let mut level: u32 = logging
+        .call_method1("getLogger", (LOGGER_NAME,))?
+        .call_method0("getEffectiveLevel")?
+        .extract()?;
+
+    // Child loggers may be enabled below the level of the parent.
+    let prefix = format!("{}.", LOGGER_NAME);
+    for (name, logger) in manager.getattr("loggerDict")?.downcast::<PyDict>()? {
+        let is_child = name.extract::<&str>().is_ok_and(|n| n.starts_with(&prefix));
+        if is_child && logger.hasattr("getEffectiveLevel")? {
+            level = level.min(logger.call_method0("getEffectiveLevel")?.extract()?);
+        }
+    }
+
+    log::set_max_level(level_filter(level));
+    Ok(())
+}
+
+/// Sets the level of the `piston_rspy` logger, and skips creating
+/// records below it in the Rust layer.
+///
+/// This is the same as setting the level through `logging`, which is
+/// also followed by the Rust layer unless `logging` could not be hooked
+/// into when the extension was imported.
+///
+/// ### Args:
+///
+/// - level `int | str`:
+/// A `logging` level, such as `logging.DEBUG` or `"INFO"`.
+///
+/// ### Raises:
+///
+/// - `ValueError`: If the level is unknown.
+#[pyfunction]
+#[pyo3(text_signature = "(level: int | str, /) -> None")]
+pub fn set_log_level(py: Python, level: &PyAny) -> PyResult<()> {
+    py.import("logging")?
+        .call_method1("getLogger", (LOGGER_NAME,))?
+        .call_method1("setLevel", (level,))
+        .map_err(|e| PyValueError::new_err(e.value(py).to_string()))?;
+
+    sync_max_level(py)
+}
+
+/// Installs the logger, and registers the `TRACE` level name with
+/// `logging`. Python's logging configuration decides which records
+/// are emitted, and records below the effective level of every
+/// `piston_rspy` logger (`WARNING` by default) are not created.
+///
+/// Like other libraries, a `NullHandler` is attached so that records
+/// are not printed unless the application configures logging.
+pub fn register(py: Python) -> PyResult<()> {
+    let logging = py.import("logging")?;
+    logging.call_method1("addLevelName", (TRACE, "TRACE"))?;
+    logging
+        .call_method1("getLogger", (LOGGER_NAME,))?
+        .call_method1("addHandler", (logging.getattr("NullHandler")?.call0()?,))?;
+
+    if log::set_logger(&LOGGER).is_err() {
+        return Ok(());
+    }
+
+    // The levels are followed on a best effort basis, and never stop
+    // the extension from being imported.
+    if let Err(e) = sync_max_level(py) {
+        log::set_max_level(LevelFilter::Warn);
+        log::warn!("Could not read the level of the piston_rspy logger: {}", e);
+    }
+
+    if let Err(e) = follow_levels(py) {
+        log::debug!(
+            "Levels set through logging are not followed, use set_log_level instead: {}",
+            e
+        );
+    }
+
+    Ok(())
+}
+
+/// Updates the Rust layer whenever a level changes through `logging`.
+///
+/// `logging` clears the cache of its manager whenever a level changes,
+/// so levels set with `setLevel`, `basicConfig` or `dictConfig` are
+/// followed by hooking into it. This relies on a private method, so
+/// `set_log_level` is the fallback if it is missing.
+fn follow_levels(py: Python) -> PyResult<()> {
+    let manager = py
+        .import("logging")?
+        .getattr("Logger")?
+        .getattr("manager")?;
+    let clear_cache: PyObject = manager.getattr("_clear_cache")?.into();
+    let hook = PyCFunction::new_closure(
+        py,
+        Some("_clear_cache"),
+        None,
+        move |args: &PyTuple, kwargs: Option<&PyDict>| -> PyResult<PyObject> {
+            let py = args.py();
+            let result = clear_cache.call(py, args, kwargs)?;
+
+            // Logging must never raise into the code configuring it.
+            if let Err(e) = sync_max_level(py) {
+                e.print(py);
+            }
+
+            Ok(result)
+        },
+    )?;
+
+    manager.setattr("_clear_cache", hook)
+}
@@ -0,0 +1,390 @@
+use pyo3::prelude::*;
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt::Write;
+use std::sync::Mutex;
+
+/// The number of recent latencies percentiles are computed from.
+const LATENCY_WINDOW: usize = 1000;
+
+/// The upper bounds, in seconds, of the latency histogram buckets.
+const LATENCY_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// The most languages executions are counted for separately. Any more
+/// are counted under `OTHER_LANGUAGE`, so that languages sent by
+/// callers cannot grow the metrics without bound.
+const MAX_LANGUAGES: usize = 100;
+
+/// The language executions past `MAX_LANGUAGES` are counted under.
+const OTHER_LANGUAGE: &str = "other";
+
+/// How an execution ended.
+#[derive(Clone, Copy, Debug)]
+pub enum Outcome {
+    /// Piston ran the code, and it exited with code 0.
+    Success,
+    /// Piston ran the code, and it exited with another code or a signal.
+    Failure,
+    /// Piston responded with a non-200 status.
+    Rejected,
+    /// No usable response was received.
+    Error,
+}
+
+impl Outcome {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Success => "success",
+            Self::Failure => "failure",
+            Self::Rejected => "rejected",
+            Self::Error => "error",
+        }
+    }
+}
+
+#[derive(Default)]
+struct MetricsState {
+    requests: u64,
+    errors: u64,
+    statuses: BTreeMap<u16, u64>,
+    executions: BTreeMap<String, BTreeMap<&'static str, u64>>,
+    bytes_sent: u64,
+    bytes_received: u64,
+    latencies: VecDeque<f64>,
+    buckets: [u64; LATENCY_BUCKETS.len()],
+    latency_sum: f64,
+}
+
+/// Request metrics, shared by every clone of a `Client`.
+#[derive(Default)]
+pub struct Metrics {
+    state: Mutex<MetricsState>,
+}
+
+impl Metrics {
+    /// Records a request, with its status if a response was received.
+    pub fn record_request(&self, status: Option<u16>, elapsed: f64, sent: usize, received: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.requests += 1;
+        state.bytes_sent += sent as u64;
+        state.bytes_received += received as u64;
+
+        match status {
+            Some(status) => *state.statuses.entry(status).or_default() += 1,
+            None => state.errors += 1,
+        }
+
+        if state.latencies.len() == LATENCY_WINDOW {
+            state.latencies.pop_front();
+        }
+
+        state.latencies.push_back(elapsed);
+        state.latency_sum += elapsed;
+
+        for (i, bound) in LATENCY_BUCKETS.iter().enumerate() {
+            if elapsed <= *bound {
+                state.buckets[i] += 1;
+            }
+        }
+    }
+
+    /// Records the outcome of an execution.
+    pub fn record_execution(&self, language: &str, outcome: Outcome) {
+        let mut state = self.state.lock().unwrap();
+        let language = match state.executions.contains_key(language) {
+            false if state.executions.len() >= MAX_LANGUAGES => OTHER_LANGUAGE,
+            _ => language,
+        };
+
+        *state
+            .executions
+            .entry(language.to_string())
+            .or_default()
+            .entry(outcome.name())
+            .or_default() += 1;
+    }
+
+    /// Takes a snapshot of the current metrics.
+    pub fn snapshot(&self) -> ClientStats {
+        let state = self.state.lock().unwrap();
+
+        let mut latencies: Vec<f64> = state.latencies.iter().copied().collect();
+        latencies.sort_by(|a, b| a.total_cmp(b));
+
+        let percentile = |p: f64| {
+            let rank = (p * latencies.len() as f64).ceil() as usize;
+            latencies.get(rank.max(1) - 1).copied()
+        };
+
+        ClientStats {
+            requests: state.requests,
+            errors: state.errors,
+            statuses: state.statuses.clone(),
+            executions: state
+                .executions
+                .iter()
+                .map(|(language, outcomes)| {
+                    let outcomes = outcomes.iter().map(|(k, v)| (k.to_string(), *v)).collect();
+                    (language.clone(), outcomes)
+                })
+                .collect(),
+            latency_p50: percentile(0.50),
+            latency_p95: percentile(0.95),
+            latency_p99: percentile(0.99),
+            bytes_sent: state.bytes_sent,
+            bytes_received: state.bytes_received,
+        }
+    }
+
+    /// Renders the metrics in the Prometheus text exposition format.
+    pub fn prometheus(&self, prefix: &str) -> String {
+        let state = self.state.lock().unwrap();
+        let mut out = String::new();
+
+        let header = |out: &mut String, name: &str, kind: &str, help: &str| {
+            let _ = writeln!(out, "# HELP {}_{} {}", prefix, name, help);
+            let _ = writeln!(out, "# TYPE {}_{} {}", prefix, name, kind);
+        };
+
+        header(
+            &mut out,
+            "requests_total",
+            "counter",
+            "Requests sent to Piston, by http status.",
+        );
+        for (status, count) in state.statuses.iter() {
+            let _ = writeln!(
+                out,
+                "{}_requests_total{{status=\"{}\"}} {}",
+                prefix, status, count
+            );
+        }
+
+        header(
+            &mut out,
+            "request_errors_total",
+            "counter",
+            "Requests that failed without a response.",
+        );
+        let _ = writeln!(out, "{}_request_errors_total {}", prefix, state.errors);
+
+        header(
+            &mut out,
+            "executions_total",
+            "counter",
+            "Executions, by language and outcome.",
+        );
+        for (language, outcomes) in state.executions.iter() {
+            for (outcome, count) in outcomes.iter() {
+                let _ = writeln!(
+                    out,
+                    "{}_executions_total{{language=\"{}\",outcome=\"{}\"}} {}",
+                    prefix,
+                    escape_label(language),
+                    outcome,
+                    count
+                );
+            }
+        }
+
+        header(
+            &mut out,
+            "request_duration_seconds",
+            "histogram",
+            "The time requests to Piston took.",
+        );
+        for (bound, count) in LATENCY_BUCKETS.iter().zip(state.buckets.iter()) {
+            let _ = writeln!(
+                out,
+                "{}_request_duration_seconds_bucket{{le=\"{}\"}} {}",
+                prefix, bound, count
+            );
+        }
+        let _ = writeln!(
+            out,
+            "{}_request_duration_seconds_bucket{{le=\"+Inf\"}} {}",
+            prefix, state.requests
+        );
+        let _ = writeln!(
+            out,
+            "{}_request_duration_seconds_sum {}",
+            prefix, state.latency_sum
+        );
+        let _ = writeln!(
+            out,
+            "{}_request_duration_seconds_count {}",
+            prefix, state.requests
+        );
+
+        header(
+            &mut out,
+            "sent_bytes_total",
+            "counter",
+            "Bytes of request bodies sent, after compression.",
+        );
+        let _ = writeln!(out, "{}_sent_bytes_total {}", prefix, state.bytes_sent);
+
+        header(
+            &mut out,
+            "received_bytes_total",
+            "counter",
+            "Bytes of response bodies received, before decompression.",
+        );
+        let _ = writeln!(
+            out,
+            "{}_received_bytes_total {}",
+            prefix, state.bytes_received
+        );
+
+        out
+    }
+}
+
+/// Escapes a Prometheus label value.
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// A snapshot of the requests sent by a `Client` and all of its
+/// copies.
+///
+/// **NOTE**:
+///
+/// - This object cannot be instantiated, and is immutable.
+/// - It can only be created with a call to `Client.stats`.
+#[pyclass]
+#[derive(Clone)]
+pub struct ClientStats {
+    requests: u64,
+    errors: u64,
+    statuses: BTreeMap<u16, u64>,
+    executions: BTreeMap<String, BTreeMap<String, u64>>,
+    latency_p50: Option<f64>,
+    latency_p95: Option<f64>,
+    latency_p99: Option<f64>,
+    bytes_sent: u64,
+    bytes_received: u64,
+}
+
+#[pymethods]
+impl ClientStats {
+    fn __repr__(&self) -> PyResult<String> {
+        Ok(format!(
+            "ClientStats {{ requests: {}, errors: {}, statuses: {:?}, latency_p50: {:?}, bytes_sent: {}, bytes_received: {} }}",
+            self.requests,
+            self.errors,
+            self.statuses,
+            self.latency_p50,
+            self.bytes_sent,
+            self.bytes_received,
+        ))
+    }
+
+    fn __str__(&self) -> PyResult<String> {
+        self.__repr__()
+    }
+
+    /// `int`: The number of requests sent.
+    #[getter]
+    fn requests(&self) -> u64 {
+        self.requests
+    }
+
+    /// `int`: The number of requests that failed without a response.
+    #[getter]
+    fn errors(&self) -> u64 {
+        self.errors
+    }
+
+    /// `dict[int, int]`: The number of responses with each http status.
+    #[getter]
+    fn statuses(&self) -> BTreeMap<u16, u64> {
+        self.statuses.clone()
+    }
+
+    /// `dict[str, dict[str, int]]`: The number of executions for each
+    /// language, by outcome. Outcomes are `success`, `failure` (a
+    /// non-zero exit code or a signal), `rejected` (a non-200 status)
+    /// and `error` (no usable response). Executions past the first 100
+    /// languages are counted under `other`.
+    #[getter]
+    fn executions(&self) -> BTreeMap<String, BTreeMap<String, u64>> {
+        self.executions.clone()
+    }
+
+    /// `float | None`: The median latency in seconds, over the most
+    /// recent 1000 requests.
+    #[getter]
+    fn latency_p50(&self) -> Option<f64> {
+        self.latency_p50
+    }
+
+    /// `float | None`: The 95th percentile latency in seconds, over the
+    /// most recent 1000 requests.
+    #[getter]
+    fn latency_p95(&self) -> Option<f64> {
+        self.latency_p95
+    }
+
+    /// `float | None`: The 99th percentile latency in seconds, over the
+    /// most recent 1000 requests.
+    #[getter]
+    fn latency_p99(&self) -> Option<f64> {
+        self.latency_p99
+    }
+
+    /// `int`: The number of request body bytes sent, after compression.
+    #[getter]
+    fn bytes_sent(&self) -> u64 {
+        self.bytes_sent
+    }
+
+    /// `int`: The number of response body bytes received, before
+    /// decompression.
+    #[getter]
+    fn bytes_received(&self) -> u64 {
+        self.bytes_received
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn languages_are_capped() {
+        let metrics = Metrics::default();
+        for i in 0..MAX_LANGUAGES + 10 {
+            metrics.record_execution(&format!("language-{}", i), Outcome::Rejected);
+        }
+        metrics.record_execution("language-0", Outcome::Success);
+
+        let stats = metrics.snapshot();
+        assert_eq!(stats.executions.len(), MAX_LANGUAGES + 1);
+        assert_eq!(stats.executions[OTHER_LANGUAGE]["rejected"], 10);
+        assert_eq!(stats.executions["language-0"]["success"], 1);
+    }
+
+    #[test]
+    fn bytes_are_counted_in_prometheus() {
+        let metrics = Metrics::default();
+        metrics.record_request(Some(200), 0.02, 10, 25);
+        metrics.record_request(None, 0.5, 5, 0);
+
+        let text = metrics.prometheus("piston");
+        assert!(text.contains("piston_sent_bytes_total 15\n"));
+        assert!(text.contains("piston_received_bytes_total 25\n"));
+        assert!(text.contains("piston_requests_total{status=\"200\"} 1\n"));
+        assert!(text.contains("piston_request_errors_total 1\n"));
+        assert!(text.contains("piston_request_duration_seconds_bucket{le=\"0.025\"} 1\n"));
+    }
+
+    #[test]
+    fn labels_are_escaped() {
+        assert_eq!(escape_label("a\"b\\c\nd"), "a\\\"b\\\\c\\nd");
+    }
+}
@@ -1,8 +1,125 @@
+use pyo3::exceptions::{PyRuntimeError, PyValueError};
 use pyo3::prelude::*;
+use std::path::{Component, Path};
 
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use log::debug;
 use piston_rs::File as File_;
 use piston_rs::Runtime as Runtime_;
-use pyo3::types::PyType;
+use pyo3::types::{PyBytes, PyType};
+
+/// The file encodings Piston accepts.
+pub const ENCODINGS: [&str; 3] = ["utf8", "base64", "hex"];
+
+/// Ensures the encoding is one that Piston accepts.
+pub fn validate_encoding(encoding: &str) -> PyResult<()> {
+    if ENCODINGS.contains(&encoding) {
+        Ok(())
+    } else {
+        Err(PyValueError::new_err(format!(
+            "Unsupported encoding {:?}, expected one of {:?}",
+            encoding, ENCODINGS
+        )))
+    }
+}
+
+/// Encodes raw bytes into file content using the given encoding.
+pub fn encode(data: Vec<u8>, encoding: &str) -> PyResult<String> {
+    validate_encoding(encoding)?;
+
+    match encoding {
+        "base64" => Ok(BASE64.encode(data)),
+        "hex" => Ok(hex::encode(data)),
+        _ => String::from_utf8(data)
+            .map_err(|_| PyValueError::new_err("Data is not valid utf8, use base64 or hex")),
+    }
+}
+
+/// Decodes file content into raw bytes using the given encoding.
+pub fn decode(content: &str, encoding: &str) -> PyResult<Vec<u8>> {
+    validate_encoding(encoding)?;
+
+    match encoding {
+        "base64" => BASE64
+            .decode(content)
+            .map_err(|e| PyValueError::new_err(format!("Invalid base64 content: {}", e))),
+        "hex" => hex::decode(content)
+            .map_err(|e| PyValueError::new_err(format!("Invalid hex content: {}", e))),
+        _ => Ok(content.as_bytes().to_vec()),
+    }
+}
+
+/// Ensures a file name is a relative path that stays inside the
+/// directory it is placed in.
+pub fn validate_name(name: &str) -> PyResult<()> {
+    if name.contains('\0') {
+        return Err(PyValueError::new_err(format!(
+            "File name {:?} must not contain NUL bytes",
+            name
+        )));
+    }
+
+    let unsafe_component = Path::new(name)
+        .components()
+        .any(|c| !matches!(c, Component::Normal(_) | Component::CurDir));
+
+    if unsafe_component {
+        return Err(PyValueError::new_err(format!(
+            "File name {:?} must be a relative path without '..'",
+            name
+        )));
+    }
+
+    Ok(())
+}
+
+/// Generates the error raised when loading a file from disk fails.
+fn load_error(details: &str) -> PyErr {
+    PyRuntimeError::new_err(format!("{:?}", details))
+}
+
+/// Parses the name of a file from its path.
+fn file_name(path: &Path) -> PyResult<String> {
+    match path.file_name() {
+        Some(n) => Ok(n.to_string_lossy().to_string()),
+        None => Err(load_error("Unable to parse file name")),
+    }
+}
+
+/// Reads a file on disk, returning its name and raw bytes.
+fn read_file(path: &str) -> PyResult<(String, Vec<u8>)> {
+    let path = Path::new(path);
+
+    if !path.is_file() {
+        return Err(load_error("File does not exist, or is a directory"));
+    }
+
+    let name = file_name(path)?;
+
+    match std::fs::read(path) {
+        Ok(data) => Ok((name, data)),
+        Err(err) => Err(load_error(&err.to_string())),
+    }
+}
+
+/// Reads a file on disk without blocking, returning its name and raw
+/// bytes.
+async fn aread_file(path: String) -> PyResult<(String, Vec<u8>)> {
+    let path = Path::new(&path);
+
+    match tokio::fs::metadata(path).await {
+        Ok(metadata) if metadata.is_file() => {}
+        _ => return Err(load_error("File does not exist, or is a directory")),
+    }
+
+    let name = file_name(path)?;
+
+    match tokio::fs::read(path).await {
+        Ok(data) => Ok((name, data)),
+        Err(err) => Err(load_error(&err.to_string())),
+    }
+}
 
 /// A runtime available to be used by Piston.
 ///
@@ -24,6 +141,11 @@ impl Runtime {
     pub fn from_runtime(runtime: Runtime_) -> Self {
         Self { inner: runtime }
     }
+
+    /// Converts the `Runtime` into a `piston_rs.Runtime`.
+    pub fn convert(&self) -> Runtime_ {
+        self.inner.clone()
+    }
 }
 
 #[pymethods]
@@ -94,6 +216,42 @@ impl File {
         }
     }
 
+    /// Generates a new `File` from raw bytes. Text is stored as utf8,
+    /// while binary data is base64 encoded.
+    pub fn from_data(name: String, data: Vec<u8>) -> Self {
+        let (content, encoding) = match String::from_utf8(data) {
+            Ok(text) if !text.contains('\0') => (text, "utf8"),
+            Ok(text) => (BASE64.encode(text), "base64"),
+            Err(err) => (BASE64.encode(err.into_bytes()), "base64"),
+        };
+
+        debug!("Loaded file {:?} as {}", name, encoding);
+
+        Self {
+            inner: File_ {
+                name,
+                content,
+                encoding: encoding.to_string(),
+            },
+        }
+    }
+
+    /// Validates the name, encoding and size of a `piston_rs.File`.
+    pub fn check(file: &File_, max_size: Option<usize>) -> PyResult<()> {
+        validate_name(&file.name)?;
+        validate_encoding(&file.encoding)?;
+
+        match max_size {
+            Some(max) if file.content.len() > max => Err(PyValueError::new_err(format!(
+                "File {:?} is {} bytes, exceeding the maximum of {} bytes",
+                file.name,
+                file.content.len(),
+                max
+            ))),
+            _ => Ok(()),
+        }
+    }
+
     /// Converts the `File` into a `piston_rs.File`.
     pub fn convert(&self) -> File_ {
         File_ {
@@ -108,14 +266,16 @@ impl File {
 impl File {
     #[new]
     #[pyo3(signature = (name="".to_string(), content = "".to_string(), encoding = "utf8".to_string()))]
-    fn new(name: String, content: String, encoding: String) -> Self {
-        Self {
+    fn new(name: String, content: String, encoding: String) -> PyResult<Self> {
+        validate_encoding(&encoding)?;
+
+        Ok(Self {
             inner: File_ {
                 name,
                 content,
                 encoding,
             },
-        }
+        })
     }
 
     fn __repr__(&self) -> PyResult<String> {
@@ -148,15 +308,25 @@ impl File {
         self.inner.content = content;
     }
 
-    /// `str`: The encoding of the file.
+    /// `bytes`: The content of the file, decoded according to its
+    /// encoding.
+    #[getter]
+    fn content_bytes<'a>(&self, py: Python<'a>) -> PyResult<&'a PyBytes> {
+        let data = decode(&self.inner.content, &self.inner.encoding)?;
+        Ok(PyBytes::new(py, &data))
+    }
+
+    /// `str`: The encoding of the file. One of `utf8`, `base64` or `hex`.
     #[getter]
     fn encoding(&self) -> String {
         self.inner.encoding.clone()
     }
 
     #[setter(encoding)]
-    fn encoding_setter(&mut self, encoding: String) {
+    fn encoding_setter(&mut self, encoding: String) -> PyResult<()> {
+        validate_encoding(&encoding)?;
         self.inner.encoding = encoding;
+        Ok(())
     }
 
     /// Sets the name of the file.
@@ -201,14 +371,60 @@ impl File {
     /// ### Returns:
     ///
     /// - `File`: The file, for chained method calls.
+    ///
+    /// ### Raises:
+    ///
+    /// - `ValueError`: If the encoding is not supported by Piston.
     #[pyo3(text_signature = "(self, encoding: str, /) -> File")]
-    fn set_encoding(mut slf: PyRefMut<Self>, encoding: String) -> PyRefMut<Self> {
+    fn set_encoding(mut slf: PyRefMut<Self>, encoding: String) -> PyResult<PyRefMut<Self>> {
+        validate_encoding(&encoding)?;
         slf.inner.encoding = encoding;
-        slf
+        Ok(slf)
+    }
+
+    /// Creates a new `File` from raw bytes.
+    ///
+    /// ### Args:
+    ///
+    /// - name `str`:
+    /// The name of the file.
+    ///
+    /// - data `bytes`:
+    /// The raw content of the file.
+    ///
+    /// - encoding `str`:
+    /// The encoding to store the content with, one of `utf8`, `base64`
+    /// or `hex`. Defaults to `base64`.
+    ///
+    /// ### Returns:
+    ///
+    /// - `File`: The new file.
+    ///
+    /// ### Raises:
+    ///
+    /// - `ValueError`: If the encoding is not supported by Piston, or
+    /// the data is not valid utf8 when using the `utf8` encoding.
+    #[classmethod]
+    #[pyo3(
+        signature = (name, data, encoding = "base64".to_string()),
+        text_signature = "(cls, name: str, data: bytes, /, encoding: str = \"base64\") -> File"
+    )]
+    fn from_bytes(_cls: &PyType, name: String, data: &[u8], encoding: String) -> PyResult<Self> {
+        let content = encode(data.to_vec(), &encoding)?;
+
+        Ok(Self {
+            inner: File_ {
+                name,
+                content,
+                encoding,
+            },
+        })
     }
 
     /// Creates a new `File` from an existing file on disk.
     ///
+    /// Binary files are automatically base64 encoded.
+    ///
     /// ### Args:
     ///
     /// - path `str`:
@@ -220,17 +436,16 @@ impl File {
     #[classmethod]
     #[pyo3(text_signature = "(cls, path: str, /) -> File")]
     fn load_from(_cls: &PyType, path: String) -> PyResult<Self> {
-        match File_::load_from(path.as_str()) {
-            Ok(file) => Ok(Python::with_gil(|_| Self { inner: file })),
-            Err(err) => Err(Python::with_gil(|_| {
-                pyo3::exceptions::PyRuntimeError::new_err(format!("{:?}", err.details))
-            })),
-        }
+        let (name, data) = read_file(&path)?;
+        Ok(Self::from_data(name, data))
     }
 
     /// Sets the content of the file to the contents of an existing
     /// file on disk.
     ///
+    /// Binary files are automatically base64 encoded, and the encoding
+    /// of the file is updated to match.
+    ///
     /// ### Args:
     ///
     /// - path `str`:
@@ -241,17 +456,125 @@ impl File {
     /// - `File`: The file, for chained method calls.
     #[pyo3(text_signature = "(self, path: str, /) -> File")]
     fn load_content_from(mut slf: PyRefMut<Self>, path: String) -> PyResult<PyRefMut<Self>> {
-        let file = slf.inner.clone();
-
-        match file.load_content_from(path.as_str()) {
-            Ok(file) => {
-                slf.inner.content = file.content;
-                Ok(slf)
-            }
-            Err(err) => Err(Python::with_gil(|_| {
-                pyo3::exceptions::PyRuntimeError::new_err(format!("{:?}", err.details))
-            })),
-        }
+        let (_, data) = read_file(&path)?;
+        let file = Self::from_data(String::new(), data);
+
+        slf.inner.content = file.inner.content;
+        slf.inner.encoding = file.inner.encoding;
+        Ok(slf)
+    }
+
+    /// Validates the file before it is sent to Piston.
+    ///
+    /// ### Args:
+    ///
+    /// - max_size `int | None`:
+    /// The maximum size of the content in bytes. Defaults to `None`,
+    /// meaning no limit.
+    ///
+    /// ### Raises:
+    ///
+    /// - `ValueError`: If the name is absolute, contains `..` or NUL
+    /// bytes, the encoding is unsupported, or the content is too large.
+    #[pyo3(
+        signature = (max_size = None),
+        text_signature = "(self, /, max_size: int | None = None) -> None"
+    )]
+    fn validate(&self, max_size: Option<usize>) -> PyResult<()> {
+        Self::check(&self.inner, max_size)
+    }
+
+    /// Writes the content of the file to disk, decoding it according
+    /// to its encoding. This is the inverse of `File.load_from`.
+    ///
+    /// Any existing file at the path is overwritten.
+    ///
+    /// ### Args:
+    ///
+    /// - path `str`:
+    /// The path to write the file to.
+    ///
+    /// ### Raises:
+    ///
+    /// - `ValueError`: If the content is not valid for its encoding.
+    /// - `RuntimeError`: If the file could not be written.
+    #[pyo3(text_signature = "(self, path: str, /) -> None")]
+    fn save_to(&self, path: String) -> PyResult<()> {
+        let data = decode(&self.inner.content, &self.inner.encoding)?;
+
+        std::fs::write(&path, data).map_err(|e| {
+            PyRuntimeError::new_err(format!("Unable to write {:?}: {:?}", path, e.to_string()))
+        })
+    }
+
+    /// **async**: Creates a new `File` from an existing file on disk,
+    /// without blocking the event loop.
+    ///
+    /// Binary files are automatically base64 encoded.
+    ///
+    /// ### Args:
+    ///
+    /// - path `str`:
+    /// The path to the file.
+    ///
+    /// ### Returns:
+    ///
+    /// - `File`: The new file.
+    ///
+    /// ### Raises:
+    ///
+    /// - `RuntimeError`: If the file could not be read.
+    #[classmethod]
+    #[pyo3(text_signature = "(cls, path: str, /) -> File")]
+    fn aload_from<'a>(_cls: &PyType, py: Python<'a>, path: String) -> PyResult<&'a PyAny> {
+        pyo3_asyncio::tokio::future_into_py(py, async move {
+            let (name, data) = aread_file(path).await?;
+            Ok(Self::from_data(name, data))
+        })
+    }
+
+    /// **async**: Sets the content of the file to the contents of an
+    /// existing file on disk, without blocking the event loop.
+    ///
+    /// Binary files are automatically base64 encoded, and the encoding
+    /// of the file is updated to match.
+    ///
+    /// ### Args:
+    ///
+    /// - path `str`:
+    /// The path to the file.
+    ///
+    /// ### Returns:
+    ///
+    /// - `File`: The file, for chained method calls.
+    ///
+    /// ### Raises:
+    ///
+    /// - `RuntimeError`: If the file could not be read, or is in use
+    /// elsewhere when its content is updated.
+    #[pyo3(text_signature = "(self, path: str, /) -> File")]
+    fn aload_content_from<'a>(
+        slf: &PyCell<Self>,
+        py: Python<'a>,
+        path: String,
+    ) -> PyResult<&'a PyAny> {
+        let slf: Py<Self> = slf.into();
+
+        pyo3_asyncio::tokio::future_into_py(py, async move {
+            let (_, data) = aread_file(path).await?;
+            let file = Self::from_data(String::new(), data);
+
+            Python::with_gil(|py| {
+                let mut this = slf.as_ref(py).try_borrow_mut().map_err(|e| {
+                    PyRuntimeError::new_err(format!("Unable to update file: {}", e))
+                })?;
+                this.inner.content = file.inner.content;
+                this.inner.encoding = file.inner.encoding;
+                Ok::<_, PyErr>(())
+            })?;
+
+            Ok(slf)
+        })
     }
 
     /// Copies the file, leaving the existing one unchanged.
@@ -0,0 +1,314 @@
+/// A container open at some point of a JSON document.
+#[derive(Clone)]
+struct Frame {
+    /// Whether the container is an object, rather than an array.
+    object: bool,
+    /// Whether the next string in an object is a key.
+    expect_key: bool,
+    /// The last key read in an object.
+    key: Option<String>,
+}
+
+/// A JSON document recovered from a prefix of it.
+pub struct Partial {
+    pub json: String,
+    /// The keys leading to the value that was cut off, from the root.
+    /// Values in arrays have an empty key.
+    pub path: Vec<String>,
+}
+
+/// Recovers as much of a JSON document as possible from a prefix of
+/// it, such as a response body cut off at `max_response_bytes`.
+///
+/// The prefix is cut back to the last complete value, or the last
+/// complete character of a string value, and any open strings and
+/// containers are closed. Returns `None` if nothing can be recovered.
+pub fn complete(prefix: &str) -> Option<Partial> {
+    let mut frames: Vec<Frame> = Vec::new();
+    let mut in_string = false;
+    let mut in_key = false;
+    let mut key = String::new();
+    let mut escape = false;
+    let mut unicode: Option<String> = None;
+    let mut scalar = false;
+
+    // Where the prefix can be cut, and the containers open there. The
+    // containers are only copied outside of strings, as they do not
+    // change within one.
+    let mut cut: Option<(usize, bool)> = None;
+    let mut cut_frames: Vec<Frame> = Vec::new();
+
+    for (i, c) in prefix.char_indices() {
+        let end = i + c.len_utf8();
+
+        if in_string {
+            if let Some(hex) = unicode.as_mut() {
+                hex.push(c);
+                if hex.len() < 4 {
+                    continue;
+                }
+
+                // A high surrogate is only valid with the low one after it.
+                let high =
+                    u16::from_str_radix(hex, 16).is_ok_and(|u| (0xd800..0xdc00).contains(&u));
+                unicode = None;
+                if !in_key && !high {
+                    cut = Some((end, true));
+                }
+            } else if escape {
+                escape = false;
+                if c == 'u' {
+                    unicode = Some(String::new());
+                } else if !in_key {
+                    cut = Some((end, true));
+                }
+            } else if c == '\\' {
+                escape = true;
+            } else if c == '"' {
+                in_string = false;
+                if in_key {
+                    if let Some(frame) = frames.last_mut() {
+                        frame.key = Some(std::mem::take(&mut key));
+                    }
+                } else {
+                    cut = Some((end, false));
+                    cut_frames = frames.clone();
+                }
+            } else if in_key {
+                key.push(c);
+            } else {
+                cut = Some((end, true));
+            }
+
+            continue;
+        }
+
+        if scalar && !(c.is_ascii_alphanumeric() || matches!(c, '.' | '+' | '-')) {
+            scalar = false;
+            cut = Some((i, false));
+            cut_frames = frames.clone();
+        }
+
+        match c {
+            '{' | '[' => {
+                frames.push(Frame {
+                    object: c == '{',
+                    expect_key: c == '{',
+                    key: None,
+                });
+                cut = Some((end, false));
+                cut_frames = frames.clone();
+            }
+            '}' | ']' => {
+                frames.pop();
+                cut = Some((end, false));
+                cut_frames = frames.clone();
+            }
+            '"' => {
+                in_string = true;
+                in_key = frames.last().is_some_and(|f| f.object && f.expect_key);
+                if !in_key {
+                    cut = Some((end, true));
+                    cut_frames = frames.clone();
+                }
+            }
+            ':' => {
+                if let Some(frame) = frames.last_mut() {
+                    frame.expect_key = false;
+                }
+            }
+            ',' => {
+                if let Some(frame) = frames.last_mut() {
+                    frame.expect_key = frame.object;
+                }
+            }
+            c if c.is_whitespace() => (),
+            _ => scalar = true,
+        }
+    }
+
+    let (end, in_string) = cut?;
+    let mut json = prefix[..end].to_string();
+    if in_string {
+        json.push('"');
+    }
+
+    for frame in cut_frames.iter().rev() {
+        json.push(if frame.object { '}' } else { ']' });
+    }
+
+    // The key of the innermost container only leads to the value cut
+    // off if it is a string.
+    let depth = match in_string {
+        true => cut_frames.len(),
+        false => cut_frames.len().saturating_sub(1),
+    };
+
+    let path = cut_frames[..depth]
+        .iter()
+        .map(|frame| frame.key.clone().unwrap_or_default())
+        .collect();
+
+    Some(Partial { json, path })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Completes a prefix, checking that the result is valid JSON.
+    fn check(prefix: &str) -> (String, Vec<String>) {
+        let partial = complete(prefix).expect("nothing recovered");
+        if let Err(e) = serde_json::from_str::<serde_json::Value>(&partial.json) {
+            panic!(
+                "{:?} completed to invalid {:?}: {}",
+                prefix, partial.json, e
+            );
+        }
+
+        (partial.json, partial.path)
+    }
+
+    fn path(keys: &[&str]) -> Vec<String> {
+        keys.iter().map(|key| key.to_string()).collect()
+    }
+
+    #[test]
+    fn nothing_to_recover() {
+        assert!(complete("").is_none());
+        assert!(complete("  ").is_none());
+        assert!(complete("12").is_none());
+    }
+
+    #[test]
+    fn cut_inside_string() {
+        assert_eq!(
+            check(r#"{"stdout": "hel"#),
+            (r#"{"stdout": "hel"}"#.to_string(), path(&["stdout"]))
+        );
+        assert_eq!(
+            check(r#"{"stdout": ""#),
+            (r#"{"stdout": ""}"#.to_string(), path(&["stdout"]))
+        );
+        assert_eq!(
+            check("[\"h\u{e9}ll\u{f6}"),
+            ("[\"h\u{e9}ll\u{f6}\"]".to_string(), path(&[""]))
+        );
+    }
+
+    #[test]
+    fn cut_inside_key() {
+        assert_eq!(check(r#"{"std"#), ("{}".to_string(), path(&[])));
+        assert_eq!(
+            check(r#"{"a": "b", "std"#),
+            (r#"{"a": "b"}"#.to_string(), path(&[]))
+        );
+        assert_eq!(
+            check(r#"{"a": "b", "stdout":"#),
+            (r#"{"a": "b"}"#.to_string(), path(&[]))
+        );
+    }
+
+    #[test]
+    fn cut_inside_escape() {
+        assert_eq!(
+            check(r#"{"s": "a\"#),
+            (r#"{"s": "a"}"#.to_string(), path(&["s"]))
+        );
+        assert_eq!(
+            check(r#"{"s": "a\n"#),
+            (r#"{"s": "a\n"}"#.to_string(), path(&["s"]))
+        );
+        assert_eq!(
+            check(r#"{"s": "a\""#),
+            (r#"{"s": "a\""}"#.to_string(), path(&["s"]))
+        );
+        assert_eq!(
+            check(r#"{"s": "a\u00e"#),
+            (r#"{"s": "a"}"#.to_string(), path(&["s"]))
+        );
+        assert_eq!(
+            check(r#"{"s": "aé"#),
+            (r#"{"s": "aé"}"#.to_string(), path(&["s"]))
+        );
+    }
+
+    #[test]
+    fn cut_inside_surrogate_pair() {
+        assert_eq!(check(r#"["x\ud83d"#), (r#"["x"]"#.to_string(), path(&[""])));
+        assert_eq!(
+            check(r#"["x\ud83d\"#),
+            (r#"["x"]"#.to_string(), path(&[""]))
+        );
+        assert_eq!(
+            check(r#"["x\ud83d\ude0"#),
+            (r#"["x"]"#.to_string(), path(&[""]))
+        );
+        assert_eq!(check(r#"["x😀"#), (r#"["x😀"]"#.to_string(), path(&[""])));
+    }
+
+    #[test]
+    fn cut_inside_scalar() {
+        assert_eq!(check("[1, 23"), ("[1]".to_string(), path(&[])));
+        assert_eq!(check(r#"{"code": 0"#), ("{}".to_string(), path(&[])));
+        assert_eq!(
+            check(r#"{"code": -1.5e3,"#),
+            (r#"{"code": -1.5e3}"#.to_string(), path(&[]))
+        );
+        assert_eq!(
+            check(r#"{"signal": null, "code": tr"#),
+            (r#"{"signal": null}"#.to_string(), path(&[]))
+        );
+    }
+
+    #[test]
+    fn cut_inside_nested_containers() {
+        assert_eq!(
+            check(r#"{"run": {"stdout": "ab", "stderr": [1, {"x": "y"#),
+            (
+                r#"{"run": {"stdout": "ab", "stderr": [1, {"x": "y"}]}}"#.to_string(),
+                path(&["run", "stderr", "", "x"])
+            )
+        );
+        assert_eq!(
+            check(r#"{"run": {"a": 1}, "b": ["#),
+            (r#"{"run": {"a": 1}, "b": []}"#.to_string(), path(&["b"]))
+        );
+        assert_eq!(
+            check(r#"[[], [{}, ["#),
+            ("[[], [{}, []]]".to_string(), path(&["", ""]))
+        );
+    }
+
+    /// Piston sends the compile and run results before the language
+    /// and version, so any cut before the end loses them.
+    #[test]
+    fn piston_response() {
+        let body = concat!(
+            r#"{"compile":{"stdout":"","stderr":"","code":0,"signal":null,"output":""},"#,
+            r#""run":{"stdout":"héllo\n","stderr":"","code":0,"signal":null,"output":"héllo\n"},"#,
+            r#""language":"python","version":"3.10.0"}"#
+        );
+
+        for (i, _) in body.char_indices().skip(1) {
+            check(&body[..i]);
+        }
+
+        let cut = body.find(r#"llo\n","stderr""#).unwrap();
+        let (json, keys) = check(&body[..cut]);
+        assert_eq!(keys, path(&["run", "stdout"]));
+        assert!(json.ends_with(r#""run":{"stdout":"hé"}}"#));
+
+        let cut = body.find("thon").unwrap();
+        let (json, keys) = check(&body[..cut]);
+        assert_eq!(keys, path(&["language"]));
+        assert!(json.ends_with(r#""language":"py"}"#));
+
+        let cut = body.find(r#","version""#).unwrap();
+        let (json, keys) = check(&body[..cut]);
+        assert_eq!(keys, path(&[]));
+        assert!(json.ends_with(r#""language":"python"}"#));
+
+        assert_eq!(check(body), (body.to_string(), path(&[])));
+    }
+}
@@ -0,0 +1,347 @@
+use pyo3::prelude::*;
+use std::collections::{BTreeMap, HashMap};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use hyper::client::connect::HttpInfo;
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+use super::http::PoolOptions;
+
+/// The most connections remembered, beyond which the least recently
+/// used is forgotten.
+const MAX_TRACKED: usize = 1024;
+
+/// When a connection delivered its first and latest responses.
+struct Seen {
+    first: Instant,
+    last: Instant,
+}
+
+#[derive(Default)]
+struct PoolState {
+    /// The connections seen, by local and remote address.
+    seen: HashMap<(SocketAddr, SocketAddr), Seen>,
+    connections: u64,
+    requests: u64,
+    reused: u64,
+    versions: BTreeMap<String, u64>,
+}
+
+impl PoolState {
+    /// Forgets the least recently used connection.
+    fn evict(&mut self) {
+        let oldest = self
+            .seen
+            .iter()
+            .min_by_key(|(_, seen)| seen.last)
+            .map(|(key, _)| *key);
+
+        if let Some(key) = oldest {
+            self.seen.remove(&key);
+        }
+    }
+}
+
+/// Tracks the connections used by a reqwest client, and limits the
+/// requests it sends at once.
+///
+/// Reqwest does not expose its pool, so connections are told apart by
+/// their local and remote address. A response counts as reused when its
+/// connection had already delivered a response before the request was
+/// sent, so requests sharing a new HTTP/2 connection are not counted as
+/// reused. A connection idle for longer than the idle timeout is taken
+/// to be closed, but a connection closed early by the server whose local
+/// port is reused within the timeout is still counted once.
+pub struct PoolCounters {
+    state: Mutex<PoolState>,
+    in_flight: AtomicUsize,
+    idle_timeout: Option<Duration>,
+    /// Limits the requests in-flight, if `max_connections_per_host` is set.
+    limit: Option<Semaphore>,
+}
+
+/// Marks a request as in-flight until dropped, holding its place within
+/// the limit of the pool.
+pub struct InFlight<'a> {
+    counters: &'a PoolCounters,
+    started: Instant,
+    _permit: Option<SemaphorePermit<'a>>,
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.counters.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl InFlight<'_> {
+    /// Records the connection a response was received on.
+    pub fn record(&self, response: &reqwest::Response) {
+        let counters = self.counters;
+        let mut guard = counters.state.lock().unwrap();
+        let state = &mut *guard;
+        state.requests += 1;
+
+        let version = format!("{:?}", response.version());
+        *state.versions.entry(version).or_default() += 1;
+
+        let info = match response.extensions().get::<HttpInfo>() {
+            Some(info) => info,
+            None => return,
+        };
+
+        let now = Instant::now();
+        let key = (info.local_addr(), info.remote_addr());
+
+        if let Some(seen) = state.seen.get_mut(&key) {
+            let closed = counters
+                .idle_timeout
+                .is_some_and(|timeout| seen.last + timeout < self.started);
+
+            if !closed {
+                let reused = seen.first < self.started;
+                seen.last = now;
+
+                if reused {
+                    state.reused += 1;
+                }
+
+                return;
+            }
+        } else if state.seen.len() >= MAX_TRACKED {
+            state.evict();
+        }
+
+        state.connections += 1;
+        state.seen.insert(
+            key,
+            Seen {
+                first: now,
+                last: now,
+            },
+        );
+    }
+}
+
+impl PoolCounters {
+    pub fn new(options: &PoolOptions) -> Self {
+        Self {
+            state: Mutex::default(),
+            in_flight: AtomicUsize::new(0),
+            idle_timeout: options.idle_timeout,
+            limit: options.max_connections_per_host.map(Semaphore::new),
+        }
+    }
+
+    /// Waits for a place within the limit of the pool, if any, then
+    /// marks a request as in-flight.
+    pub async fn start(&self) -> InFlight<'_> {
+        let permit = match &self.limit {
+            // The semaphore is never closed.
+            Some(limit) => limit.acquire().await.ok(),
+            None => None,
+        };
+
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+
+        InFlight {
+            counters: self,
+            started: Instant::now(),
+            _permit: permit,
+        }
+    }
+
+    /// Takes a snapshot of the counters.
+    pub fn snapshot(&self) -> PoolStats {
+        let state = self.state.lock().unwrap();
+
+        PoolStats {
+            connections: state.connections,
+            requests: state.requests,
+            reused: state.reused,
+            in_flight: self.in_flight.load(Ordering::SeqCst),
+            versions: state.versions.clone(),
+        }
+    }
+}
+
+/// A snapshot of the connections used by a `Client` and the copies
+/// sharing its connection pool.
+///
+/// **NOTE**:
+///
+/// - This object cannot be instantiated, and is immutable.
+/// - It can only be created with a call to `Client.pool_stats`.
+#[pyclass]
+#[derive(Clone, Default)]
+pub struct PoolStats {
+    connections: u64,
+    requests: u64,
+    reused: u64,
+    in_flight: usize,
+    versions: BTreeMap<String, u64>,
+}
+
+impl PoolStats {
+    /// Combines the stats of two pools.
+    pub fn merge(mut self, other: PoolStats) -> Self {
+        self.connections += other.connections;
+        self.requests += other.requests;
+        self.reused += other.reused;
+        self.in_flight += other.in_flight;
+
+        for (version, count) in other.versions {
+            *self.versions.entry(version).or_default() += count;
+        }
+
+        self
+    }
+}
+
+#[pymethods]
+impl PoolStats {
+    fn __repr__(&self) -> PyResult<String> {
+        Ok(format!(
+            "PoolStats {{ connections: {}, requests: {}, reused: {}, in_flight: {}, versions: {:?} }}",
+            self.connections, self.requests, self.reused, self.in_flight, self.versions,
+        ))
+    }
+
+    fn __str__(&self) -> PyResult<String> {
+        self.__repr__()
+    }
+
+    /// `int`: The number of connections opened.
+    #[getter]
+    fn connections(&self) -> u64 {
+        self.connections
+    }
+
+    /// `int`: The number of responses received.
+    #[getter]
+    fn requests(&self) -> u64 {
+        self.requests
+    }
+
+    /// `int`: The number of responses received on a connection that had
+    /// already delivered a response when the request was sent.
+    #[getter]
+    fn reused(&self) -> u64 {
+        self.reused
+    }
+
+    /// `float | None`: The fraction of responses received on a
+    /// connection that was reused, if any were received.
+    #[getter]
+    fn reuse_ratio(&self) -> Option<f64> {
+        match self.requests {
+            0 => None,
+            requests => Some(self.reused as f64 / requests as f64),
+        }
+    }
+
+    /// `int`: The number of requests sent whose response has not been
+    /// read in full.
+    #[getter]
+    fn in_flight(&self) -> usize {
+        self.in_flight
+    }
+
+    /// `dict[str, int]`: The number of responses received with each
+    /// http version, such as `HTTP/1.1` or `HTTP/2.0`.
+    #[getter]
+    fn versions(&self) -> BTreeMap<String, u64> {
+        self.versions.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::FutureExt;
+
+    fn counters(max_connections_per_host: Option<usize>) -> PoolCounters {
+        PoolCounters::new(&PoolOptions {
+            max_connections_per_host,
+            ..PoolOptions::default()
+        })
+    }
+
+    fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    #[test]
+    fn requests_are_in_flight_until_dropped() {
+        let counters = counters(None);
+        let first = block_on(counters.start());
+        let second = block_on(counters.start());
+        assert_eq!(counters.snapshot().in_flight, 2);
+
+        drop(first);
+        assert_eq!(counters.snapshot().in_flight, 1);
+
+        drop(second);
+        assert_eq!(counters.snapshot().in_flight, 0);
+    }
+
+    #[test]
+    fn requests_over_the_limit_wait_for_a_place() {
+        let counters = counters(Some(2));
+        let first = counters.start().now_or_never().unwrap();
+        let _second = counters.start().now_or_never().unwrap();
+
+        let mut third = Box::pin(counters.start());
+        assert!((&mut third).now_or_never().is_none());
+        assert_eq!(counters.snapshot().in_flight, 2);
+
+        drop(first);
+        let _third = third.now_or_never().unwrap();
+        assert_eq!(counters.snapshot().in_flight, 2);
+    }
+
+    #[test]
+    fn responses_are_counted_by_version() {
+        let counters = counters(None);
+        let in_flight = block_on(counters.start());
+
+        for version in [hyper::Version::HTTP_11, hyper::Version::HTTP_2] {
+            let mut response = hyper::Response::new(String::new());
+            *response.version_mut() = version;
+            in_flight.record(&reqwest::Response::from(response));
+        }
+
+        let stats = counters.snapshot();
+        assert_eq!(stats.requests, 2);
+        assert_eq!(stats.connections, 0);
+        assert_eq!(stats.versions["HTTP/1.1"], 1);
+        assert_eq!(stats.versions["HTTP/2.0"], 1);
+    }
+
+    #[test]
+    fn stats_are_merged() {
+        let mut versions = BTreeMap::new();
+        versions.insert("HTTP/1.1".to_string(), 2);
+
+        let stats = PoolStats {
+            connections: 1,
+            requests: 2,
+            reused: 1,
+            in_flight: 1,
+            versions,
+        };
+        let merged = stats.clone().merge(stats);
+
+        assert_eq!(merged.connections, 2);
+        assert_eq!(merged.requests, 4);
+        assert_eq!(merged.reuse_ratio(), Some(0.5));
+        assert_eq!(merged.in_flight, 2);
+        assert_eq!(merged.versions["HTTP/1.1"], 4);
+    }
+}
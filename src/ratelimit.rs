@@ -0,0 +1,134 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use log::debug;
+use tokio::time::Instant;
+
+/// Limits how many requests a client starts within a sliding window,
+/// making requests over the limit wait for a slot. Shared by every
+/// clone of a `Client` with the same limit.
+pub struct RateLimiter {
+    pub max_requests: u32,
+    pub period: Duration,
+    /// When each request in the current window started.
+    started: Mutex<VecDeque<Instant>>,
+}
+
+impl RateLimiter {
+    pub fn new(max_requests: u32, period: Duration) -> Self {
+        Self {
+            max_requests,
+            period,
+            started: Mutex::new(VecDeque::with_capacity(max_requests as usize)),
+        }
+    }
+
+    /// Waits until a request can start without exceeding the limit.
+    pub async fn acquire(&self) {
+        loop {
+            let until = {
+                let now = Instant::now();
+                let mut started = self.started.lock().unwrap();
+
+                while started.front().is_some_and(|t| *t + self.period <= now) {
+                    started.pop_front();
+                }
+
+                if started.len() < self.max_requests as usize {
+                    started.push_back(now);
+                    return;
+                }
+
+                started[0] + self.period
+            };
+
+            debug!(
+                "Rate limit of {} requests per {:.1?} reached, waiting {:.1?}",
+                self.max_requests,
+                self.period,
+                until.saturating_duration_since(Instant::now())
+            );
+
+            tokio::time::sleep_until(until).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::future::Future;
+    use std::sync::Arc;
+
+    /// Runs a future with the clock paused, so that waits advance it
+    /// instantly.
+    fn block_on<F: Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .start_paused(true)
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    #[test]
+    fn requests_under_the_limit_do_not_wait() {
+        block_on(async {
+            let limiter = RateLimiter::new(3, Duration::from_secs(10));
+            let start = Instant::now();
+
+            for _ in 0..3 {
+                limiter.acquire().await;
+            }
+
+            assert_eq!(start.elapsed(), Duration::ZERO);
+        });
+    }
+
+    #[test]
+    fn requests_over_the_limit_wait_for_the_oldest() {
+        block_on(async {
+            let limiter = RateLimiter::new(2, Duration::from_secs(10));
+            let start = Instant::now();
+
+            limiter.acquire().await;
+            tokio::time::advance(Duration::from_secs(4)).await;
+            limiter.acquire().await;
+
+            limiter.acquire().await;
+            assert_eq!(start.elapsed(), Duration::from_secs(10));
+
+            // The window slides, so the next slot frees up when the
+            // second request leaves it.
+            limiter.acquire().await;
+            assert_eq!(start.elapsed(), Duration::from_secs(14));
+        });
+    }
+
+    #[test]
+    fn waiting_requests_are_spread_over_windows() {
+        block_on(async {
+            let limiter = Arc::new(RateLimiter::new(2, Duration::from_secs(1)));
+            let start = Instant::now();
+
+            let tasks: Vec<_> = (0..5)
+                .map(|_| {
+                    let limiter = limiter.clone();
+                    tokio::spawn(async move {
+                        limiter.acquire().await;
+                        start.elapsed().as_secs()
+                    })
+                })
+                .collect();
+
+            let mut started = Vec::new();
+            for task in tasks {
+                started.push(task.await.unwrap());
+            }
+
+            started.sort();
+            assert_eq!(started, vec![0, 0, 1, 1, 2]);
+        });
+    }
+}
@@ -0,0 +1,661 @@
+use pyo3::exceptions::{PyRuntimeError, PyValueError};
+use pyo3::prelude::*;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::{SocketAddr, TcpListener};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use tokio::sync::oneshot;
+
+use piston_rs::ExecResult as ExecResult_;
+use piston_rs::Executor as Executor_;
+use piston_rs::Runtime as Runtime_;
+
+use super::Executor;
+use super::Runtime;
+
+/// A canned response served by a `MockPiston` server.
+///
+/// When `body` is set it is sent verbatim, otherwise a Piston execute
+/// response is generated from the other fields, echoing the language
+/// and version of the request.
+#[pyclass]
+#[derive(Clone)]
+#[pyo3(
+    text_signature = "(stdout: str = \"\", stderr: str = \"\", code: int | None = 0, signal: str | None = None, status: int = 200, delay: float = 0.0, body: str | None = None, /) -> MockResponse"
+)]
+pub struct MockResponse {
+    stdout: String,
+    stderr: String,
+    code: Option<isize>,
+    signal: Option<String>,
+    status: u16,
+    delay: f64,
+    body: Option<String>,
+}
+
+impl Default for MockResponse {
+    fn default() -> Self {
+        Self {
+            stdout: String::new(),
+            stderr: String::new(),
+            code: Some(0),
+            signal: None,
+            status: 200,
+            delay: 0.0,
+            body: None,
+        }
+    }
+}
+
+impl MockResponse {
+    /// Generates the body to send for an execute request.
+    fn execute_body(&self, executor: &Executor_) -> String {
+        if let Some(body) = &self.body {
+            return body.clone();
+        }
+
+        let run = ExecResult_ {
+            stdout: self.stdout.clone(),
+            stderr: self.stderr.clone(),
+            output: format!("{}{}", self.stdout, self.stderr),
+            code: self.code,
+            signal: self.signal.clone(),
+        };
+
+        serde_json::json!({
+            "language": executor.language,
+            "version": executor.version,
+            "run": run,
+        })
+        .to_string()
+    }
+}
+
+#[pymethods]
+impl MockResponse {
+    #[new]
+    #[pyo3(signature = (
+        stdout = "".to_string(),
+        stderr = "".to_string(),
+        code = 0,
+        signal = None,
+        status = 200,
+        delay = 0.0,
+        body = None
+    ))]
+    fn new(
+        stdout: String,
+        stderr: String,
+        code: Option<isize>,
+        signal: Option<String>,
+        status: u16,
+        delay: f64,
+        body: Option<String>,
+    ) -> PyResult<Self> {
+        if StatusCode::from_u16(status).is_err() {
+            return Err(PyValueError::new_err(format!(
+                "Invalid http status {}, expected a value from 100 to 999",
+                status
+            )));
+        }
+
+        if !delay.is_finite() || delay < 0.0 {
+            return Err(PyValueError::new_err(format!(
+                "Invalid delay {}, expected a finite number of seconds that is not negative",
+                delay
+            )));
+        }
+
+        Ok(Self {
+            stdout,
+            stderr,
+            code,
+            signal,
+            status,
+            delay,
+            body,
+        })
+    }
+
+    fn __repr__(&self) -> PyResult<String> {
+        Ok(format!(
+            "MockResponse {{ status: {}, code: {:?}, delay: {} }}",
+            self.status, self.code, self.delay
+        ))
+    }
+
+    fn __str__(&self) -> PyResult<String> {
+        self.__repr__()
+    }
+
+    /// `str`: The text to send as `stdout`.
+    #[getter]
+    fn stdout(&self) -> String {
+        self.stdout.clone()
+    }
+
+    /// `str`: The text to send as `stderr`.
+    #[getter]
+    fn stderr(&self) -> String {
+        self.stderr.clone()
+    }
+
+    /// `int | None`: The exit code to send.
+    #[getter]
+    fn code(&self) -> Option<isize> {
+        self.code
+    }
+
+    /// `str | None`: The signal to send.
+    #[getter]
+    fn signal(&self) -> Option<String> {
+        self.signal.clone()
+    }
+
+    /// `int`: The http status to respond with.
+    #[getter]
+    fn status(&self) -> u16 {
+        self.status
+    }
+
+    /// `float`: The number of seconds to wait before responding.
+    #[getter]
+    fn delay(&self) -> f64 {
+        self.delay
+    }
+
+    /// `str | None`: The raw body to respond with.
+    #[getter]
+    fn body(&self) -> Option<String> {
+        self.body.clone()
+    }
+}
+
+/// A request received by a `MockPiston` server.
+///
+/// **NOTE**:
+///
+/// - This object cannot be instantiated, and is immutable.
+#[pyclass]
+#[derive(Clone)]
+pub struct MockRequest {
+    method: String,
+    path: String,
+    headers: HashMap<String, String>,
+    body: String,
+}
+
+#[pymethods]
+impl MockRequest {
+    fn __repr__(&self) -> PyResult<String> {
+        Ok(format!(
+            "MockRequest {{ method: \"{}\", path: \"{}\" }}",
+            self.method, self.path
+        ))
+    }
+
+    fn __str__(&self) -> PyResult<String> {
+        self.__repr__()
+    }
+
+    /// `str`: The http method of the request.
+    #[getter]
+    fn method(&self) -> String {
+        self.method.clone()
+    }
+
+    /// `str`: The path of the request.
+    #[getter]
+    fn path(&self) -> String {
+        self.path.clone()
+    }
+
+    /// `dict[str, str]`: The headers of the request.
+    #[getter]
+    fn headers(&self) -> HashMap<String, String> {
+        self.headers.clone()
+    }
+
+    /// `str`: The body of the request.
+    #[getter]
+    fn body(&self) -> String {
+        self.body.clone()
+    }
+}
+
+/// How an execute request is answered.
+#[derive(Clone)]
+enum ExecuteHandler {
+    Response(MockResponse),
+    Callable(PyObject),
+}
+
+/// The fixtures and recorded requests shared with the server task.
+#[derive(Default)]
+struct MockState {
+    runtimes: Vec<Runtime_>,
+    runtimes_response: Option<MockResponse>,
+    default_handler: Option<ExecuteHandler>,
+    handlers: HashMap<String, ExecuteHandler>,
+    requests: Vec<MockRequest>,
+}
+
+/// Generates a response with a JSON body.
+fn json_response(status: u16, body: String) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .body(Body::from(body))
+        .unwrap()
+}
+
+/// Generates a Piston style error response.
+fn error_response(status: StatusCode, message: &str) -> Response<Body> {
+    let body = serde_json::json!({ "message": message }).to_string();
+    json_response(status.as_u16(), body)
+}
+
+async fn delay(response: &MockResponse) {
+    if response.delay > 0.0 {
+        tokio::time::sleep(Duration::from_secs_f64(response.delay)).await;
+    }
+}
+
+async fn handle_runtimes(state: &Mutex<MockState>) -> Response<Body> {
+    let (runtimes, response) = {
+        let state = state.lock().unwrap();
+        (state.runtimes.clone(), state.runtimes_response.clone())
+    };
+
+    match response {
+        Some(response) => {
+            delay(&response).await;
+            let body = response
+                .body
+                .clone()
+                .unwrap_or_else(|| serde_json::to_string(&runtimes).unwrap());
+
+            json_response(response.status, body)
+        }
+        None => json_response(200, serde_json::to_string(&runtimes).unwrap()),
+    }
+}
+
+async fn handle_execute(state: &Mutex<MockState>, body: &str) -> Response<Body> {
+    let executor: Executor_ = match serde_json::from_str(body) {
+        Ok(executor) => executor,
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, &e.to_string()),
+    };
+
+    let handler = {
+        let state = state.lock().unwrap();
+        state
+            .handlers
+            .get(&executor.language.to_lowercase())
+            .or(state.default_handler.as_ref())
+            .cloned()
+    };
+
+    let response = match handler {
+        Some(ExecuteHandler::Response(response)) => response,
+        Some(ExecuteHandler::Callable(callable)) => {
+            let result = Python::with_gil(|py| {
+                callable
+                    .call1(py, (Executor::from_executor(executor.clone()),))
+                    .and_then(|r| r.extract::<MockResponse>(py))
+            });
+
+            match result {
+                Ok(response) => response,
+                Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+            }
+        }
+        None => MockResponse::default(),
+    };
+
+    delay(&response).await;
+    json_response(response.status, response.execute_body(&executor))
+}
+
+async fn handle(state: Arc<Mutex<MockState>>, req: Request<Body>) -> Response<Body> {
+    let method = req.method().clone();
+    let path = req.uri().path().to_string();
+    let headers = req
+        .headers()
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_str().unwrap_or_default().to_string()))
+        .collect();
+
+    let body = match hyper::body::to_bytes(req.into_body()).await {
+        Ok(bytes) => String::from_utf8_lossy(&bytes).to_string(),
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, &e.to_string()),
+    };
+
+    state.lock().unwrap().requests.push(MockRequest {
+        method: method.to_string(),
+        path: path.clone(),
+        headers,
+        body: body.clone(),
+    });
+
+    match (method, path.trim_end_matches('/')) {
+        (Method::GET, "/api/v2/runtimes") => handle_runtimes(&state).await,
+        (Method::POST, "/api/v2/execute") => handle_execute(&state, &body).await,
+        _ => error_response(StatusCode::NOT_FOUND, "Not found"),
+    }
+}
+
+/// A local http server that mimics the Piston v2 api, for testing
+/// without network access.
+///
+/// It serves `/api/v2/runtimes` and `/api/v2/execute` from programmable
+/// fixtures, and records every request it receives. Point a client at
+/// it with `Client.with_url(mock.url)`.
+///
+/// The server can be used as a context manager, which starts it on
+/// enter and stops it on exit.
+#[pyclass]
+#[pyo3(text_signature = "(port: int = 0, /) -> MockPiston")]
+pub struct MockPiston {
+    port: u16,
+    addr: Option<SocketAddr>,
+    state: Arc<Mutex<MockState>>,
+    shutdown: Option<oneshot::Sender<()>>,
+}
+
+#[pymethods]
+impl MockPiston {
+    #[new]
+    #[pyo3(signature = (port = 0))]
+    fn new(port: u16) -> Self {
+        Self {
+            port,
+            addr: None,
+            state: Arc::new(Mutex::new(MockState::default())),
+            shutdown: None,
+        }
+    }
+
+    fn __repr__(&self) -> PyResult<String> {
+        Ok(format!(
+            "MockPiston {{ url: {:?}, running: {} }}",
+            self.url(),
+            self.shutdown.is_some()
+        ))
+    }
+
+    fn __str__(&self) -> PyResult<String> {
+        self.__repr__()
+    }
+
+    fn __enter__(mut slf: PyRefMut<Self>) -> PyResult<PyRefMut<Self>> {
+        slf.start()?;
+        Ok(slf)
+    }
+
+    fn __exit__(&mut self, _exc_type: &PyAny, _exc_value: &PyAny, _traceback: &PyAny) {
+        self.stop();
+    }
+
+    /// `str | None`: The base url to pass to `Client.with_url`, or
+    /// `None` if the server has not been started.
+    #[getter]
+    fn url(&self) -> Option<String> {
+        self.addr.map(|addr| format!("http://{}/api/v2", addr))
+    }
+
+    /// `int | None`: The port the server is bound to, or `None` if the
+    /// server has not been started.
+    #[getter]
+    fn port(&self) -> Option<u16> {
+        self.addr.map(|addr| addr.port())
+    }
+
+    /// `list[MockRequest]`: The requests received by the server, in
+    /// order.
+    #[getter]
+    fn requests(&self) -> Vec<MockRequest> {
+        self.state.lock().unwrap().requests.clone()
+    }
+
+    /// Starts the server on `127.0.0.1`. Does nothing if the server is
+    /// already running.
+    ///
+    /// ### Raises:
+    ///
+    /// - `RuntimeError`: If the port could not be bound.
+    #[pyo3(text_signature = "(self) -> None")]
+    fn start(&mut self) -> PyResult<()> {
+        if self.shutdown.is_some() {
+            return Ok(());
+        }
+
+        let listener = TcpListener::bind(("127.0.0.1", self.port))
+            .and_then(|l| l.set_nonblocking(true).map(|_| l))
+            .map_err(|e| PyRuntimeError::new_err(format!("{:?}", e)))?;
+
+        let addr = listener
+            .local_addr()
+            .map_err(|e| PyRuntimeError::new_err(format!("{:?}", e)))?;
+
+        let runtime = pyo3_asyncio::tokio::get_runtime();
+        let _guard = runtime.enter();
+
+        let state = self.state.clone();
+        let make_service = make_service_fn(move |_| {
+            let state = state.clone();
+
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req| {
+                    let state = state.clone();
+                    async move { Ok::<_, Infallible>(handle(state, req).await) }
+                }))
+            }
+        });
+
+        let (tx, rx) = oneshot::channel::<()>();
+        let server = Server::from_tcp(listener)
+            .map_err(|e| PyRuntimeError::new_err(format!("{:?}", e)))?
+            .serve(make_service)
+            .with_graceful_shutdown(async {
+                rx.await.ok();
+            });
+
+        runtime.spawn(server);
+        self.addr = Some(addr);
+        self.shutdown = Some(tx);
+        Ok(())
+    }
+
+    /// Stops the server. Does nothing if the server is not running.
+    #[pyo3(text_signature = "(self) -> None")]
+    fn stop(&mut self) {
+        if let Some(tx) = self.shutdown.take() {
+            tx.send(()).ok();
+        }
+
+        self.addr = None;
+    }
+
+    /// Sets the runtimes served by `/api/v2/runtimes`.
+    ///
+    /// ### Args:
+    ///
+    /// - runtimes `list[Runtime]`:
+    /// The runtimes to serve.
+    ///
+    /// - response `MockResponse | None`:
+    /// An optional response controlling the status and delay, or
+    /// replacing the body entirely. Defaults to `None`.
+    ///
+    /// ### Returns:
+    ///
+    /// - `MockPiston`: The server, for chained method calls.
+    #[pyo3(
+        signature = (runtimes, response = None),
+        text_signature = "(self, runtimes: list[Runtime], /, response: MockResponse | None = None) -> MockPiston"
+    )]
+    fn set_runtimes(
+        slf: PyRefMut<Self>,
+        runtimes: Vec<Runtime>,
+        response: Option<MockResponse>,
+    ) -> PyRefMut<Self> {
+        {
+            let mut state = slf.state.lock().unwrap();
+            state.runtimes = runtimes.iter().map(|r| r.convert()).collect();
+            state.runtimes_response = response;
+        }
+
+        slf
+    }
+
+    /// Sets how `/api/v2/execute` responds.
+    ///
+    /// ### Args:
+    ///
+    /// - handler `MockResponse | Callable[[Executor], MockResponse]`:
+    /// The canned response to send, or a callable that receives the
+    /// requested `Executor` and returns the response. If the callable
+    /// raises, a 500 response is sent.
+    ///
+    /// - language `str | None`:
+    /// The language this handler applies to. Defaults to `None`,
+    /// meaning any language without its own handler.
+    ///
+    /// ### Returns:
+    ///
+    /// - `MockPiston`: The server, for chained method calls.
+    #[pyo3(
+        signature = (handler, language = None),
+        text_signature = "(self, handler: MockResponse | Callable[[Executor], MockResponse], /, language: str | None = None) -> MockPiston"
+    )]
+    fn on_execute<'a>(
+        slf: PyRefMut<'a, Self>,
+        handler: &PyAny,
+        language: Option<String>,
+    ) -> PyResult<PyRefMut<'a, Self>> {
+        let handler = match handler.extract::<MockResponse>() {
+            Ok(response) => ExecuteHandler::Response(response),
+            Err(_) if handler.is_callable() => ExecuteHandler::Callable(handler.into()),
+            Err(e) => return Err(e),
+        };
+
+        {
+            let mut state = slf.state.lock().unwrap();
+
+            match language {
+                Some(language) => {
+                    state.handlers.insert(language.to_lowercase(), handler);
+                }
+                None => state.default_handler = Some(handler),
+            }
+        }
+
+        Ok(slf)
+    }
+
+    /// Clears the recorded requests.
+    #[pyo3(text_signature = "(self) -> None")]
+    fn clear_requests(&self) {
+        self.state.lock().unwrap().requests.clear();
+    }
+
+    /// Clears all fixtures and recorded requests.
+    #[pyo3(text_signature = "(self) -> None")]
+    fn reset(&self) {
+        *self.state.lock().unwrap() = MockState::default();
+    }
+}
+
+impl Drop for MockPiston {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// Registers the testing classes on the `testing` submodule.
+pub fn register(m: &PyModule) -> PyResult<()> {
+    m.add_class::<MockPiston>()?;
+    m.add_class::<MockResponse>()?;
+    m.add_class::<MockRequest>()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(status: u16, delay: f64) -> PyResult<MockResponse> {
+        MockResponse::new(
+            String::new(),
+            String::new(),
+            Some(0),
+            None,
+            status,
+            delay,
+            None,
+        )
+    }
+
+    #[test]
+    fn valid_statuses_are_accepted() {
+        for status in [100, 200, 404, 503, 999] {
+            assert!(response(status, 0.0).is_ok(), "status {}", status);
+        }
+    }
+
+    #[test]
+    fn invalid_statuses_are_rejected() {
+        for status in [0, 99, 1000, u16::MAX] {
+            assert!(response(status, 0.0).is_err(), "status {}", status);
+        }
+    }
+
+    #[test]
+    fn valid_delays_are_accepted() {
+        for delay in [0.0, 0.5, 30.0] {
+            assert!(response(200, delay).is_ok(), "delay {}", delay);
+        }
+    }
+
+    #[test]
+    fn invalid_delays_are_rejected() {
+        for delay in [-0.1, f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+            assert!(response(200, delay).is_err(), "delay {}", delay);
+        }
+    }
+
+    #[test]
+    fn execute_body_echoes_the_executor() {
+        let response = MockResponse {
+            stdout: "out".to_string(),
+            stderr: "err".to_string(),
+            ..MockResponse::default()
+        };
+        let executor = Executor_::new()
+            .set_language("python")
+            .set_version("3.10.0");
+        let body: serde_json::Value =
+            serde_json::from_str(&response.execute_body(&executor)).unwrap();
+
+        assert_eq!(body["language"], "python");
+        assert_eq!(body["version"], "3.10.0");
+        assert_eq!(body["run"]["output"], "outerr");
+        assert_eq!(body["run"]["code"], 0);
+    }
+
+    #[test]
+    fn execute_body_prefers_the_raw_body() {
+        let response = MockResponse {
+            body: Some("not json".to_string()),
+            ..MockResponse::default()
+        };
+
+        assert_eq!(response.execute_body(&Executor_::new()), "not json");
+    }
+}
@@ -0,0 +1,794 @@
+use pyo3::exceptions::{PyRuntimeError, PyTypeError};
+use pyo3::prelude::*;
+use std::collections::HashMap;
+use std::future::Future;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+
+use hyper::body::HttpBody;
+use hyperlocal::{UnixClientExt, UnixConnector};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::{Method, Url};
+use serde::{Deserialize, Serialize};
+
+use super::callback::call_maybe_async;
+use super::cassette::Cassette;
+use super::compression::{self, Compression, ACCEPT_ENCODING};
+use super::http::PoolOptions;
+use super::pool::{PoolCounters, PoolStats};
+
+/// A request to send to Piston, relative to the base url.
+#[derive(Clone, Debug)]
+pub struct TransportRequest {
+    pub method: Method,
+    pub path: String,
+    pub headers: HashMap<String, String>,
+    pub body: Option<String>,
+    /// How the body is compressed on the wire. Only applied by
+    /// transports that send requests over http themselves.
+    pub compression: Compression,
+    /// The most bytes of the response body to read, or `None` for no
+    /// limit.
+    pub max_response_bytes: Option<usize>,
+}
+
+/// A response received from Piston.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TransportResponse {
+    pub status: u16,
+    pub body: String,
+    /// The url of the backend that served the response, when it
+    /// differs from the client url.
+    #[serde(skip)]
+    pub backend: Option<String>,
+    /// The number of request body bytes sent on the wire, after
+    /// compression, for transports that send requests themselves.
+    #[serde(skip)]
+    pub sent: Option<usize>,
+    /// The number of response body bytes read from the wire, before
+    /// decompression, for transports that send requests themselves.
+    #[serde(skip)]
+    pub received: Option<usize>,
+    /// Whether the body was cut off at `max_response_bytes`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub truncated: bool,
+    /// The headers received, by lower case name. Transports that do
+    /// not speak http themselves leave this empty.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub headers: HashMap<String, String>,
+}
+
+impl TransportResponse {
+    pub fn new(status: u16, body: String) -> Self {
+        Self {
+            status,
+            body,
+            backend: None,
+            sent: None,
+            received: None,
+            truncated: false,
+            headers: HashMap::new(),
+        }
+    }
+
+    /// Cuts the body off at `limit` bytes, for transports that do not
+    /// stop reading there themselves.
+    pub fn limit(&mut self, limit: usize) {
+        if self.body.len() <= limit {
+            return;
+        }
+
+        let mut end = limit;
+        while !self.body.is_char_boundary(end) {
+            end -= 1;
+        }
+
+        self.body.truncate(end);
+        self.truncated = true;
+    }
+}
+
+/// Collects a response body, stopping once it exceeds a limit.
+struct BodyReader {
+    data: Vec<u8>,
+    limit: Option<usize>,
+    truncated: bool,
+}
+
+impl BodyReader {
+    fn new(limit: Option<usize>) -> Self {
+        Self {
+            data: Vec::new(),
+            limit,
+            truncated: false,
+        }
+    }
+
+    /// Appends a chunk of the body, returning whether to keep reading.
+    fn push(&mut self, chunk: &[u8]) -> bool {
+        match self.limit {
+            Some(limit) if self.data.len() + chunk.len() > limit => {
+                self.data
+                    .extend_from_slice(&chunk[..limit - self.data.len()]);
+                self.truncated = true;
+                false
+            }
+            _ => {
+                self.data.extend_from_slice(chunk);
+                true
+            }
+        }
+    }
+
+    /// Decodes the body according to its `Content-Encoding`.
+    async fn finish(
+        self,
+        status: u16,
+        headers: HashMap<String, String>,
+    ) -> PyResult<TransportResponse> {
+        let received = self.data.len();
+        let content_encoding = headers.get("content-encoding").map(String::as_str);
+        let (mut body, truncated) =
+            compression::decode(content_encoding, self.data, self.limit, self.truncated).await?;
+
+        // A character cut off at the limit is dropped, rather than
+        // replaced.
+        if truncated {
+            if let Err(e) = std::str::from_utf8(&body) {
+                if e.error_len().is_none() {
+                    body.truncate(e.valid_up_to());
+                }
+            }
+        }
+
+        let mut response =
+            TransportResponse::new(status, String::from_utf8_lossy(&body).into_owned());
+        response.received = Some(received);
+        response.truncated = truncated;
+        response.headers = headers;
+        Ok(response)
+    }
+}
+
+/// The headers of a response, by name. Repeated headers are joined
+/// with commas, and values that are not visible ASCII are skipped.
+fn response_headers(headers: &HeaderMap) -> HashMap<String, String> {
+    let mut map: HashMap<String, String> = HashMap::with_capacity(headers.keys_len());
+
+    for (name, value) in headers.iter() {
+        if let Ok(value) = value.to_str() {
+            map.entry(name.as_str().to_string())
+                .and_modify(|existing| {
+                    existing.push_str(", ");
+                    existing.push_str(value);
+                })
+                .or_insert_with(|| value.to_string());
+        }
+    }
+
+    map
+}
+
+/// An error sending a request over http.
+#[derive(Debug)]
+pub enum TransportError {
+    /// No connection could be made, so nothing was sent.
+    Connect(reqwest::Error),
+    /// The request failed after connecting, or could not be made.
+    Other(PyErr),
+}
+
+impl std::fmt::Display for TransportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Connect(e) => write!(f, "{:?}", e),
+            Self::Other(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<PyErr> for TransportError {
+    fn from(e: PyErr) -> Self {
+        Self::Other(e)
+    }
+}
+
+impl From<TransportError> for PyErr {
+    fn from(e: TransportError) -> Self {
+        match e {
+            TransportError::Connect(e) => PyRuntimeError::new_err(format!("{:?}", e)),
+            TransportError::Other(e) => e,
+        }
+    }
+}
+
+/// The future returned by `Transport::send`.
+pub type TransportFuture<'a> =
+    Pin<Box<dyn Future<Output = PyResult<TransportResponse>> + Send + 'a>>;
+
+/// Sends requests to Piston on behalf of a `Client`.
+pub trait Transport: Send + Sync {
+    /// Sends a request, returning the response.
+    fn send<'a>(&'a self, request: &'a TransportRequest) -> TransportFuture<'a>;
+
+    /// A short description of the transport, used in reprs.
+    fn name(&self) -> String;
+
+    /// Releases any resources held by the transport, such as pooled
+    /// connections. Called when the client is closed.
+    fn close(&self) {}
+
+    /// The connections used by the transport, if it sends requests
+    /// over tcp.
+    fn pool_stats(&self) -> Option<PoolStats> {
+        None
+    }
+
+    /// The http client used by the transport, if it sends requests
+    /// over tcp and has not been closed.
+    fn http(&self) -> Option<reqwest::Client> {
+        None
+    }
+}
+
+/// Sends requests over http using reqwest.
+pub struct ReqwestTransport {
+    http: Mutex<Option<reqwest::Client>>,
+    url: String,
+    pool: PoolCounters,
+}
+
+impl ReqwestTransport {
+    pub fn new(url: &str, http: reqwest::Client, pool: &PoolOptions) -> Self {
+        Self {
+            http: Mutex::new(Some(http)),
+            url: url.to_string(),
+            pool: PoolCounters::new(pool),
+        }
+    }
+
+    /// Sends a request, telling connection errors, where nothing was
+    /// sent, apart from other failures.
+    pub async fn try_send(
+        &self,
+        request: &TransportRequest,
+    ) -> Result<TransportResponse, TransportError> {
+        let mut headers = HeaderMap::with_capacity(request.headers.len() + 1);
+        for (key, value) in request.headers.iter() {
+            let key = HeaderName::from_bytes(key.as_bytes());
+            let value = HeaderValue::from_str(value);
+
+            if let (Ok(key), Ok(value)) = (key, value) {
+                headers.insert(key, value);
+            }
+        }
+
+        let http = self
+            .http
+            .lock()
+            .unwrap()
+            .clone()
+            .ok_or_else(|| PyRuntimeError::new_err("Client is closed"))?;
+
+        let mut builder = http
+            .request(
+                request.method.clone(),
+                format!("{}{}", self.url, request.path),
+            )
+            .headers(headers);
+
+        let mut sent = 0;
+        if let Some(body) = &request.body {
+            let (body, encoding) = request.compression.encode(body).await?;
+            if let Some(encoding) = encoding {
+                builder = builder.header("content-encoding", encoding.name());
+            }
+
+            sent = body.len();
+            builder = builder
+                .header("content-type", "application/json")
+                .body(body);
+        }
+
+        if request.compression.decompress {
+            builder = builder.header("accept-encoding", ACCEPT_ENCODING);
+        }
+
+        // Held until the body is read, so the request stays in-flight
+        // and within the limit of the pool until its connection is free.
+        let in_flight = self.pool.start().await;
+        let mut response = builder.send().await.map_err(|e| match e.is_connect() {
+            true => TransportError::Connect(e),
+            false => TransportError::Other(PyRuntimeError::new_err(format!("{:?}", e))),
+        })?;
+
+        in_flight.record(&response);
+
+        let status = response.status().as_u16();
+        let headers = response_headers(response.headers());
+        let mut body = BodyReader::new(request.max_response_bytes);
+
+        while let Some(chunk) = response
+            .chunk()
+            .await
+            .map_err(|e| PyRuntimeError::new_err(format!("{:?}", e)))?
+        {
+            if !body.push(&chunk) {
+                break;
+            }
+        }
+
+        drop(in_flight);
+        let mut response = body.finish(status, headers).await?;
+        response.sent = Some(sent);
+        Ok(response)
+    }
+}
+
+impl Transport for ReqwestTransport {
+    fn send<'a>(&'a self, request: &'a TransportRequest) -> TransportFuture<'a> {
+        Box::pin(async move { Ok(self.try_send(request).await?) })
+    }
+
+    fn name(&self) -> String {
+        "reqwest".to_string()
+    }
+
+    fn close(&self) {
+        self.http.lock().unwrap().take();
+    }
+
+    fn pool_stats(&self) -> Option<PoolStats> {
+        Some(self.pool.snapshot())
+    }
+
+    fn http(&self) -> Option<reqwest::Client> {
+        self.http.lock().unwrap().clone()
+    }
+}
+
+/// Sends requests over http to a Piston instance listening on a Unix
+/// domain socket.
+pub struct UnixTransport {
+    http: Mutex<Option<hyper::Client<UnixConnector>>>,
+    socket: PathBuf,
+    prefix: String,
+}
+
+impl UnixTransport {
+    pub fn new(socket: PathBuf, prefix: &str) -> Self {
+        Self {
+            http: Mutex::new(Some(hyper::Client::unix())),
+            socket,
+            prefix: prefix.trim_end_matches('/').to_string(),
+        }
+    }
+
+    /// The path and query to request, with any dot segments resolved
+    /// as they would be in an http url.
+    fn path(&self, path: &str) -> PyResult<String> {
+        let url = Url::parse(&format!("http://localhost{}{}", self.prefix, path))
+            .map_err(|e| PyRuntimeError::new_err(format!("Invalid path {:?}: {}", path, e)))?;
+
+        Ok(match url.query() {
+            Some(query) => format!("{}?{}", url.path(), query),
+            None => url.path().to_string(),
+        })
+    }
+}
+
+impl Transport for UnixTransport {
+    fn send<'a>(&'a self, request: &'a TransportRequest) -> TransportFuture<'a> {
+        Box::pin(async move {
+            let uri = hyperlocal::Uri::new(&self.socket, &self.path(&request.path)?);
+            let mut builder = hyper::Request::builder()
+                .method(request.method.as_str())
+                .uri(hyper::Uri::from(uri));
+
+            for (key, value) in request.headers.iter() {
+                let key = HeaderName::from_bytes(key.as_bytes());
+                let value = HeaderValue::from_str(value);
+
+                if let (Ok(key), Ok(value)) = (key, value) {
+                    builder = builder.header(key, value);
+                }
+            }
+
+            let mut sent = 0;
+            let body = match &request.body {
+                Some(body) => {
+                    let (body, encoding) = request.compression.encode(body).await?;
+                    sent = body.len();
+                    if let Some(encoding) = encoding {
+                        builder = builder.header("content-encoding", encoding.name());
+                    }
+
+                    builder = builder.header("content-type", "application/json");
+                    hyper::Body::from(body)
+                }
+                None => hyper::Body::empty(),
+            };
+
+            if request.compression.decompress {
+                builder = builder.header("accept-encoding", ACCEPT_ENCODING);
+            }
+
+            let http = self
+                .http
+                .lock()
+                .unwrap()
+                .clone()
+                .ok_or_else(|| PyRuntimeError::new_err("Client is closed"))?;
+
+            let hyper_request = builder
+                .body(body)
+                .map_err(|e| PyRuntimeError::new_err(format!("{:?}", e)))?;
+
+            let response = http
+                .request(hyper_request)
+                .await
+                .map_err(|e| PyRuntimeError::new_err(format!("{:?}", e)))?;
+
+            let status = response.status().as_u16();
+            let headers = response_headers(response.headers());
+            let mut stream = response.into_body();
+            let mut body = BodyReader::new(request.max_response_bytes);
+
+            while let Some(chunk) = stream.data().await {
+                let chunk = chunk.map_err(|e| PyRuntimeError::new_err(format!("{:?}", e)))?;
+                if !body.push(&chunk) {
+                    break;
+                }
+            }
+
+            let mut response = body.finish(status, headers).await?;
+            response.sent = Some(sent);
+            Ok(response)
+        })
+    }
+
+    fn name(&self) -> String {
+        "unix".to_string()
+    }
+
+    fn close(&self) {
+        self.http.lock().unwrap().take();
+    }
+}
+
+/// Records requests to, or replays them from, a cassette, sending
+/// unrecorded requests through another transport.
+pub struct CassetteTransport {
+    pub cassette: Arc<Cassette>,
+    inner: Arc<dyn Transport>,
+}
+
+impl CassetteTransport {
+    pub fn new(cassette: Arc<Cassette>, inner: Arc<dyn Transport>) -> Self {
+        Self { cassette, inner }
+    }
+
+    /// The transport unrecorded requests are sent through.
+    pub fn inner(&self) -> &Arc<dyn Transport> {
+        &self.inner
+    }
+}
+
+impl Transport for CassetteTransport {
+    fn send<'a>(&'a self, request: &'a TransportRequest) -> TransportFuture<'a> {
+        Box::pin(async move {
+            let method = request.method.as_str();
+            let body = request.body.as_deref();
+
+            if let Some(response) = self.cassette.play(method, &request.path, body).await? {
+                return Ok(response);
+            }
+
+            let response = self.inner.send(request).await?;
+            self.cassette
+                .record(method, &request.path, body, response.clone())
+                .await?;
+
+            Ok(response)
+        })
+    }
+
+    fn name(&self) -> String {
+        format!("cassette({})", self.inner.name())
+    }
+
+    fn close(&self) {
+        self.inner.close();
+    }
+
+    fn pool_stats(&self) -> Option<PoolStats> {
+        self.inner.pool_stats()
+    }
+}
+
+/// Sends requests through a Python object with an `async send(method,
+/// path, body)` method returning `(status, body)`.
+pub struct PyTransport {
+    object: PyObject,
+}
+
+impl PyTransport {
+    /// Wraps a Python object, ensuring it has a callable `send`.
+    pub fn new(object: &PyAny) -> PyResult<Self> {
+        match object.getattr("send") {
+            Ok(send) if send.is_callable() => Ok(Self {
+                object: object.into(),
+            }),
+            _ => Err(PyTypeError::new_err(
+                "Transport must be a FakeTransport or have an async send(method, path, body) method",
+            )),
+        }
+    }
+}
+
+impl Transport for PyTransport {
+    fn send<'a>(&'a self, request: &'a TransportRequest) -> TransportFuture<'a> {
+        Box::pin(async move {
+            let send = Python::with_gil(|py| self.object.getattr(py, "send"))?;
+            let args = (
+                request.method.to_string(),
+                request.path.clone(),
+                request.body.clone(),
+            );
+
+            let result = call_maybe_async(&send, args).await?;
+
+            let (status, body) = Python::with_gil(|py| result.extract::<(u16, String)>(py))
+                .map_err(|_| {
+                    PyTypeError::new_err("Transport send must return a (status, body) tuple")
+                })?;
+
+            Ok(TransportResponse::new(status, body))
+        })
+    }
+
+    fn name(&self) -> String {
+        Python::with_gil(|py| {
+            self.object
+                .as_ref(py)
+                .get_type()
+                .name()
+                .map(|n| n.to_string())
+                .unwrap_or_else(|_| "python".to_string())
+        })
+    }
+}
+
+/// A request received by a `FakeTransport`.
+///
+/// **NOTE**:
+///
+/// - This object cannot be instantiated, and is immutable.
+#[pyclass]
+#[derive(Clone)]
+pub struct FakeRequest {
+    request: TransportRequest,
+}
+
+#[pymethods]
+impl FakeRequest {
+    fn __repr__(&self) -> PyResult<String> {
+        Ok(format!(
+            "FakeRequest {{ method: \"{}\", path: \"{}\" }}",
+            self.request.method, self.request.path
+        ))
+    }
+
+    fn __str__(&self) -> PyResult<String> {
+        self.__repr__()
+    }
+
+    /// `str`: The http method of the request.
+    #[getter]
+    fn method(&self) -> String {
+        self.request.method.to_string()
+    }
+
+    /// `str`: The path of the request, relative to the base url.
+    #[getter]
+    fn path(&self) -> String {
+        self.request.path.clone()
+    }
+
+    /// `dict[str, str]`: The headers of the request.
+    #[getter]
+    fn headers(&self) -> HashMap<String, String> {
+        self.request.headers.clone()
+    }
+
+    /// `str | None`: The body of the request.
+    #[getter]
+    fn body(&self) -> Option<String> {
+        self.request.body.clone()
+    }
+}
+
+#[derive(Default)]
+struct FakeState {
+    responses: HashMap<(String, String), TransportResponse>,
+    requests: Vec<FakeRequest>,
+}
+
+/// An in-memory transport that answers requests with canned responses,
+/// without any network access. Pass it to `Client.with_transport`.
+///
+/// Requests without a canned response receive a 404.
+#[pyclass]
+#[derive(Clone, Default)]
+#[pyo3(text_signature = "() -> FakeTransport")]
+pub struct FakeTransport {
+    state: Arc<Mutex<FakeState>>,
+}
+
+impl Transport for FakeTransport {
+    fn send<'a>(&'a self, request: &'a TransportRequest) -> TransportFuture<'a> {
+        Box::pin(async move {
+            let mut state = self.state.lock().unwrap();
+            state.requests.push(FakeRequest {
+                request: request.clone(),
+            });
+
+            let key = (request.method.to_string(), request.path.clone());
+            Ok(state.responses.get(&key).cloned().unwrap_or_else(|| {
+                TransportResponse::new(404, "{\"message\":\"Not found\"}".to_string())
+            }))
+        })
+    }
+
+    fn name(&self) -> String {
+        "fake".to_string()
+    }
+}
+
+#[pymethods]
+impl FakeTransport {
+    #[new]
+    fn new() -> Self {
+        Self::default()
+    }
+
+    fn __repr__(&self) -> PyResult<String> {
+        let state = self.state.lock().unwrap();
+        Ok(format!(
+            "FakeTransport {{ responses: {}, requests: {} }}",
+            state.responses.len(),
+            state.requests.len()
+        ))
+    }
+
+    fn __str__(&self) -> PyResult<String> {
+        self.__repr__()
+    }
+
+    /// `list[FakeRequest]`: The requests received, in order.
+    #[getter]
+    fn requests(&self) -> Vec<FakeRequest> {
+        self.state.lock().unwrap().requests.clone()
+    }
+
+    /// Sets the response sent for a method and path, replacing any
+    /// existing response.
+    ///
+    /// ### Args:
+    ///
+    /// - method `str`:
+    /// The http method, such as `GET` or `POST`.
+    ///
+    /// - path `str`:
+    /// The path relative to the base url, such as `/execute`.
+    ///
+    /// - status `int`:
+    /// The http status to respond with.
+    ///
+    /// - body `str`:
+    /// The body to respond with.
+    ///
+    /// ### Returns:
+    ///
+    /// - `FakeTransport`: The transport, for chained method calls.
+    #[pyo3(
+        text_signature = "(self, method: str, path: str, status: int, body: str, /) -> FakeTransport"
+    )]
+    fn add_response(
+        slf: PyRef<Self>,
+        method: String,
+        path: String,
+        status: u16,
+        body: String,
+    ) -> PyRef<Self> {
+        slf.state.lock().unwrap().responses.insert(
+            (method.to_uppercase(), path),
+            TransportResponse::new(status, body),
+        );
+
+        slf
+    }
+
+    /// Clears all responses and recorded requests.
+    #[pyo3(text_signature = "(self) -> None")]
+    fn reset(&self) {
+        *self.state.lock().unwrap() = FakeState::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(method: Method, path: &str) -> TransportRequest {
+        TransportRequest {
+            method,
+            path: path.to_string(),
+            headers: HashMap::new(),
+            body: None,
+            compression: Compression::default(),
+            max_response_bytes: None,
+        }
+    }
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    #[test]
+    fn unix_paths_are_prefixed() {
+        let transport = UnixTransport::new(PathBuf::from("/tmp/piston.sock"), "/api/v2/");
+
+        assert_eq!(transport.path("/runtimes").unwrap(), "/api/v2/runtimes");
+        assert_eq!(
+            transport.path("/packages?language=python").unwrap(),
+            "/api/v2/packages?language=python"
+        );
+    }
+
+    #[test]
+    fn unix_paths_resolve_dot_segments() {
+        let transport = UnixTransport::new(PathBuf::from("/tmp/piston.sock"), "/api/v2");
+
+        assert_eq!(
+            transport.path("/packages/../runtimes").unwrap(),
+            "/api/v2/runtimes"
+        );
+        assert_eq!(transport.path("/./runtimes").unwrap(), "/api/v2/runtimes");
+    }
+
+    #[test]
+    fn fake_transports_answer_unknown_requests_with_404() {
+        let fake = FakeTransport::default();
+        fake.state.lock().unwrap().responses.insert(
+            ("GET".to_string(), "/runtimes".to_string()),
+            TransportResponse::new(200, "[]".to_string()),
+        );
+
+        let found = block_on(fake.send(&request(Method::GET, "/runtimes"))).unwrap();
+        let missing = block_on(fake.send(&request(Method::POST, "/runtimes"))).unwrap();
+
+        assert_eq!((found.status, found.body.as_str()), (200, "[]"));
+        assert_eq!(missing.status, 404);
+        assert_eq!(fake.state.lock().unwrap().requests.len(), 2);
+    }
+
+    #[test]
+    fn limits_keep_whole_characters() {
+        let mut response = TransportResponse::new(200, "aé".to_string());
+        response.limit(2);
+
+        assert_eq!(response.body, "a");
+        assert!(response.truncated);
+
+        let mut response = TransportResponse::new(200, "abc".to_string());
+        response.limit(3);
+
+        assert_eq!(response.body, "abc");
+        assert!(!response.truncated);
+    }
+}